@@ -0,0 +1,18 @@
+#!/bin/sh
+# Regenerate data/oui.tsv (bundled into shannon for `shannon device` vendor
+# lookups) from the IEEE MA-L registry. The checked-in file is a small subset
+# of common consumer vendors; run this and rebuild for the full registry.
+# Lookups binary-search the file, so it must stay sorted with one line per OUI.
+set -e
+
+out="$(dirname "$0")/../../data/oui.tsv"
+
+curl -fsSL https://standards-oui.ieee.org/oui/oui.csv | python3 -c '
+import csv, sys
+rows = csv.reader(sys.stdin)
+next(rows)
+for _registry, assignment, organization, *_ in rows:
+    print(f"{assignment.upper()}\t{organization.strip()}")
+' | LC_ALL=C sort -t "$(printf '\t')" -k1,1 -u > "$out"
+
+echo "$(wc -l < "$out") vendors written to $out"
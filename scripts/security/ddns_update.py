@@ -2,7 +2,8 @@
 """SHANNON Dynamic DNS updater via Loopia XMLRPC API.
 
 Updates the 'shannon' A record at fredrikbranstrom.se to match
-the current WAN IP. Only makes API calls when the IP changes.
+the current WAN IP, and the AAAA record to the WAN's stable global IPv6
+address when it has one. Only makes API calls when an address changes.
 
 Designed to run via systemd timer every 5 minutes.
 
@@ -24,6 +25,7 @@ DOMAIN = "fredrikbranstrom.se"
 SUBDOMAIN = "shannon"
 WAN_INTERFACE = "enxc84d4421f975"
 CACHE_FILE = "/var/cache/shannon-ddns-ip"
+CACHE_FILE_V6 = "/var/cache/shannon-ddns-ipv6"
 STATE_FILE = "/var/cache/shannon-ddns-state.json"
 LOG_FILE = "/var/log/shannon-llm-triage.log"
 ENV_FILE = "/etc/shannon-security/env"
@@ -64,23 +66,47 @@ def get_wan_ip():
     return None
 
 
-def get_cached_ip():
+def get_wan_ipv6():
+    """Stable global IPv6 address on the WAN interface, if any.
+
+    Privacy (temporary) addresses rotate, and deprecated/tentative ones are
+    on their way out or not yet usable, so none of them belong in DNS.
+    """
+    try:
+        result = subprocess.run(
+            ["ip", "-6", "-o", "addr", "show", WAN_INTERFACE, "scope", "global"],
+            capture_output=True, text=True, timeout=5
+        )
+        # Output format: "5: enxc84d4421f975    inet6 2001:db8::2/64 scope global dynamic ..."
+        for line in result.stdout.splitlines():
+            fields = line.split()
+            if any(flag in fields for flag in ("temporary", "deprecated", "tentative")):
+                continue
+            if "inet6" in fields:
+                return fields[fields.index("inet6") + 1].split("/")[0]
+    except Exception as e:
+        log("ERROR", f"Failed to read WAN IPv6 from {WAN_INTERFACE}: {e}")
+    return None
+
+
+def get_cached_ip(cache_file=CACHE_FILE):
     """Read last known IP from cache."""
     try:
-        return Path(CACHE_FILE).read_text().strip()
+        return Path(cache_file).read_text().strip()
     except FileNotFoundError:
         return None
 
 
-def save_cached_ip(ip):
+def save_cached_ip(ip, cache_file=CACHE_FILE):
     """Save current IP to cache."""
-    Path(CACHE_FILE).write_text(ip + "\n")
+    Path(cache_file).write_text(ip + "\n")
 
 
-def save_state(ip, status, message=""):
+def save_state(ip, status, message="", ipv6=None):
     """Save full state for CLI status command."""
     state = {
         "ip": ip,
+        "ipv6": ipv6,
         "status": status,
         "message": message,
         "timestamp": datetime.now().isoformat(timespec="seconds"),
@@ -117,8 +143,8 @@ def load_loopia_creds():
     return user, password
 
 
-def update_dns(user, password, ip):
-    """Update or create the A record via Loopia XMLRPC API."""
+def update_dns(user, password, ip, rtype="A"):
+    """Update or create the A (or AAAA) record via Loopia XMLRPC API."""
     client = xmlrpc.client.ServerProxy(LOOPIA_API, encoding="utf-8")
 
     # Get existing records for the subdomain
@@ -141,11 +167,11 @@ def update_dns(user, password, ip):
             log("ERROR", f"Loopia API error: {records}")
         return False
 
-    # Find existing A record
+    # Find existing record of this type
     a_record = None
     if isinstance(records, list):
         for r in records:
-            if isinstance(r, dict) and r.get("type") == "A":
+            if isinstance(r, dict) and r.get("type") == rtype:
                 a_record = r
                 break
 
@@ -161,7 +187,7 @@ def update_dns(user, password, ip):
         try:
             result = client.updateZoneRecord(user, password, DOMAIN, SUBDOMAIN, a_record)
             if result == "OK":
-                log("OK", f"Updated existing A record → {ip}")
+                log("OK", f"Updated existing {rtype} record → {ip}")
                 return True
             else:
                 log("ERROR", f"updateZoneRecord returned: {result}")
@@ -170,7 +196,7 @@ def update_dns(user, password, ip):
             log("ERROR", f"updateZoneRecord failed: {e}")
             return False
     else:
-        # No A record exists — create subdomain + record
+        # No record of this type exists — create subdomain + record
         try:
             sub_result = client.addSubdomain(user, password, DOMAIN, SUBDOMAIN)
             if sub_result not in ("OK", "DOMAIN_OCCUPIED"):
@@ -179,7 +205,7 @@ def update_dns(user, password, ip):
             pass  # Subdomain may already exist
 
         new_record = {
-            "type": "A",
+            "type": rtype,
             "ttl": TTL,
             "priority": 0,
             "rdata": ip,
@@ -187,7 +213,7 @@ def update_dns(user, password, ip):
         try:
             result = client.addZoneRecord(user, password, DOMAIN, SUBDOMAIN, new_record)
             if result == "OK":
-                log("OK", f"Created new A record → {ip}")
+                log("OK", f"Created new {rtype} record → {ip}")
                 return True
             else:
                 log("ERROR", f"addZoneRecord returned: {result}")
@@ -212,6 +238,8 @@ def show_status():
     """Display current DDNS state."""
     wan_ip = get_wan_ip() or "unknown"
     cached_ip = get_cached_ip() or "never updated"
+    wan_ipv6 = get_wan_ipv6() or "none"
+    cached_ipv6 = get_cached_ip(CACHE_FILE_V6) or "never updated"
 
     try:
         state = json.loads(Path(STATE_FILE).read_text())
@@ -221,6 +249,8 @@ def show_status():
     print(f"DDNS: {SUBDOMAIN}.{DOMAIN}")
     print(f"  WAN IP:     {wan_ip}")
     print(f"  DNS record: {cached_ip}")
+    print(f"  WAN IPv6:   {wan_ipv6}")
+    print(f"  AAAA:       {cached_ipv6}")
     print(f"  Status:     {state.get('status', 'unknown')}")
     print(f"  Last check: {state.get('timestamp', 'never')}")
     print(f"  Message:    {state.get('message', 'n/a')}")
@@ -229,6 +259,8 @@ def show_status():
 
     if wan_ip != "unknown" and cached_ip != "never updated" and wan_ip != cached_ip:
         print(f"  ⚠ IP MISMATCH — DNS needs update")
+    if wan_ipv6 != "none" and wan_ipv6 != cached_ipv6:
+        print(f"  ⚠ IPv6 MISMATCH — AAAA needs update")
 
 
 def main():
@@ -246,34 +278,55 @@ def main():
         sys.exit(1)
 
     cached_ip = get_cached_ip()
+    current_ipv6 = get_wan_ipv6()
+    cached_ipv6 = get_cached_ip(CACHE_FILE_V6)
+    v6_changed = current_ipv6 is not None and current_ipv6 != cached_ipv6
 
-    if cached_ip == current_ip and not force:
+    if cached_ip == current_ip and not v6_changed and not force:
         # No change — exit silently (no log noise)
-        save_state(current_ip, "ok", "No change")
+        save_state(current_ip, "ok", "No change", cached_ipv6)
         return
 
     # IP changed (or first run, or --force)
     old_ip = cached_ip or "(first run)"
-    if force and cached_ip == current_ip:
+    if force and cached_ip == current_ip and not v6_changed:
         log("INFO", f"Force update: {current_ip}")
-    else:
+    elif cached_ip != current_ip:
         log("INFO", f"IP change detected: {old_ip} → {current_ip}")
+    if v6_changed:
+        log("INFO", f"IPv6 change detected: {cached_ipv6 or '(first run)'} → {current_ipv6}")
 
     user, password = load_loopia_creds()
 
+    ipv6 = cached_ipv6
+    if current_ipv6 and (v6_changed or force):
+        if update_dns(user, password, current_ipv6, "AAAA"):
+            save_cached_ip(current_ipv6, CACHE_FILE_V6)
+            ipv6 = current_ipv6
+            if cached_ipv6 and v6_changed:
+                notify(f"SHANNON IPv6 changed: {cached_ipv6} → {current_ipv6} ({SUBDOMAIN}.{DOMAIN})")
+        else:
+            log("ERROR", f"Failed to update AAAA to {current_ipv6}")
+
+    if cached_ip == current_ip and not force:
+        save_state(current_ip, "updated" if ipv6 == current_ipv6 else "error",
+                   f"IPv6 changed from {cached_ipv6 or '(first run)'}", ipv6)
+        if ipv6 != current_ipv6:
+            sys.exit(1)
+        return
+
     if update_dns(user, password, current_ip):
         save_cached_ip(current_ip)
-        save_state(current_ip, "updated", f"Changed from {old_ip}")
+        save_state(current_ip, "updated", f"Changed from {old_ip}", ipv6)
         log("OK", f"Updated {SUBDOMAIN}.{DOMAIN} → {current_ip}")
 
         # Notify on change (not on first run to avoid spam)
         if cached_ip and cached_ip != current_ip:
             notify(f"SHANNON IP changed: {cached_ip} → {current_ip} ({SUBDOMAIN}.{DOMAIN})")
     else:
-        save_state(current_ip, "error", "DNS update failed")
+        save_state(current_ip, "error", "DNS update failed", ipv6)
         log("ERROR", f"Failed to update DNS to {current_ip}")
         sys.exit(1)
 
-
 if __name__ == "__main__":
     main()
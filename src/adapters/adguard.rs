@@ -1,12 +1,19 @@
 //! AdGuard Home adapter — wraps REST API on localhost:3000
 
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::net::IpAddr;
+use std::time::Duration;
 
-use crate::location::execute_shell;
+use crate::adapters::crowdsec::query_escape;
+use crate::adapters::resolver;
+use crate::error::ShannonError;
+use crate::location::{execute_mutation_with_input, execute_shell, execute_shell_with_input};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct AdguardStatus {
     pub running: bool,
     pub dns_queries_today: u64,
@@ -40,6 +47,9 @@ struct AdguardFilterStatus {
 struct AdguardFilter {
     rules_count: Option<u32>,
     enabled: Option<bool>,
+    #[serde(default)]
+    id: i64,
+    name: Option<String>,
 }
 
 /// Get AdGuard Home status via REST API
@@ -68,6 +78,80 @@ pub fn get_status() -> Result<AdguardStatus> {
     })
 }
 
+/// One entry of the AdGuard Home query log
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsQuery {
+    pub time: String,
+    pub domain: String,
+    /// Record type, e.g. "A" or "HTTPS"
+    pub qtype: String,
+    /// Whether a filter list or rule blocked the answer
+    pub blocked: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryLogResponse {
+    data: Option<Vec<QueryLogEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryLogEntry {
+    client: Option<String>,
+    time: Option<String>,
+    question: Option<QueryLogQuestion>,
+    reason: Option<String>,
+    answer: Option<Vec<QueryLogAnswer>>,
+    upstream: Option<String>,
+    #[serde(rename = "elapsedMs")]
+    elapsed_ms: Option<String>,
+    cached: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryLogAnswer {
+    #[serde(rename = "type")]
+    rtype: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryLogQuestion {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    qtype: Option<String>,
+}
+
+/// Most recent queries made by one client, newest first
+pub fn client_queries(client: &str, limit: u32) -> Result<Vec<DnsQuery>> {
+    let output = execute_shell(&format!(
+        "curl -s -u admin:shannon-admin-2026 'http://localhost:3000/control/querylog?search={}&limit={}'",
+        client, limit
+    ))?;
+
+    if !output.status.success() {
+        return Ok(vec![]);
+    }
+
+    let text = String::from_utf8_lossy(&output.stdout);
+    let log: QueryLogResponse = serde_json::from_str(&text).unwrap_or(QueryLogResponse { data: None });
+
+    // `search` also matches domain names, so keep only this client's queries
+    Ok(log.data
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.client.as_deref() == Some(client))
+        .map(|entry| {
+            let question = entry.question;
+            DnsQuery {
+                time: entry.time.unwrap_or_default(),
+                domain: question.as_ref().and_then(|q| q.name.clone()).unwrap_or_default(),
+                qtype: question.and_then(|q| q.qtype).unwrap_or_default(),
+                blocked: entry.reason.is_some_and(|r| r.starts_with("Filtered")),
+            }
+        })
+        .collect())
+}
+
 fn get_stats() -> Result<(u64, u64)> {
     let output = execute_shell(
         "curl -s -u admin:shannon-admin-2026 http://localhost:3000/control/stats"
@@ -112,3 +196,336 @@ fn get_filter_rules() -> Result<u32> {
 
     Ok(count)
 }
+
+/// Upstream settings from `/control/dns_info`
+#[derive(Debug, Deserialize, Default)]
+pub struct DnsInfo {
+    /// Upstreams, one per line; `[/domain/]upstream` lines route a domain
+    /// to its own upstream
+    #[serde(default)]
+    pub upstream_dns: Vec<String>,
+    /// Plain DNS servers used to resolve the hostnames of encrypted upstreams
+    #[serde(default)]
+    pub bootstrap_dns: Vec<String>,
+}
+
+impl DnsInfo {
+    /// Upstreams for all domains (no `[/domain/]` lines or comments)
+    pub fn general_upstreams(&self) -> Vec<String> {
+        self.upstream_dns
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with("[/"))
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+/// Current upstream settings
+pub fn dns_info() -> Result<DnsInfo> {
+    let output = execute_shell(
+        "curl -fsS -u admin:shannon-admin-2026 http://localhost:3000/control/dns_info",
+    )?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "AdGuard /control/dns_info".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Replace the upstream list and/or the bootstrap servers; AdGuard
+/// validates them and applies them at once
+pub fn set_dns_config(upstreams: Option<&[String]>, bootstrap: Option<&[String]>) -> Result<()> {
+    let mut body = serde_json::Map::new();
+    if let Some(upstreams) = upstreams {
+        body.insert("upstream_dns".to_string(), serde_json::json!(upstreams));
+    }
+    if let Some(bootstrap) = bootstrap {
+        body.insert("bootstrap_dns".to_string(), serde_json::json!(bootstrap));
+    }
+    let output = execute_mutation_with_input(
+        "curl -fsS -u admin:shannon-admin-2026 -H 'Content-Type: application/json' \
+         --data-binary @- http://localhost:3000/control/dns_config",
+        &serde_json::Value::Object(body).to_string(),
+    )?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "AdGuard /control/dns_config".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Ask AdGuard to query each upstream, returning an error message for the
+/// ones that did not answer
+pub fn test_upstreams(
+    upstreams: &[String],
+    bootstrap: &[String],
+) -> Result<Vec<(String, Option<String>)>> {
+    let (results, _) = run_upstream_test(upstreams, bootstrap)?;
+    Ok(upstreams
+        .iter()
+        .map(|upstream| {
+            let error = match results.get(upstream).map(String::as_str) {
+                Some("OK") => None,
+                Some(error) => Some(error.to_string()),
+                None => Some("not tested".to_string()),
+            };
+            (upstream.clone(), error)
+        })
+        .collect())
+}
+
+/// Test one upstream and time it; the time includes connection setup (the
+/// TLS or QUIC handshake for encrypted upstreams), as for a cold query
+pub fn time_upstream(upstream: &str, bootstrap: &[String]) -> Result<(Option<String>, Duration)> {
+    let upstreams = [upstream.to_string()];
+    let (mut results, elapsed) = run_upstream_test(&upstreams, bootstrap)?;
+    let error = match results.remove(upstream) {
+        Some(result) if result == "OK" => None,
+        Some(error) => Some(error),
+        None => Some("not tested".to_string()),
+    };
+    Ok((error, elapsed))
+}
+
+/// POST to `/control/test_upstream_dns`, timed by curl on the router
+fn run_upstream_test(
+    upstreams: &[String],
+    bootstrap: &[String],
+) -> Result<(HashMap<String, String>, Duration)> {
+    let body = serde_json::json!({ "upstream_dns": upstreams, "bootstrap_dns": bootstrap });
+    let output = execute_shell_with_input(
+        "curl -fsS -u admin:shannon-admin-2026 -H 'Content-Type: application/json' \
+         -w '\\n%{time_total}' --data-binary @- http://localhost:3000/control/test_upstream_dns",
+        &body.to_string(),
+    )?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "AdGuard /control/test_upstream_dns".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let text = text.trim_end();
+    let (json, seconds) = text.rsplit_once('\n').unwrap_or((text, "0"));
+    let elapsed = Duration::from_secs_f64(seconds.trim().parse().unwrap_or(0.0));
+    Ok((serde_json::from_str(json)?, elapsed))
+}
+
+/// Transport of an upstream, from its URL scheme
+pub fn upstream_protocol(upstream: &str) -> &'static str {
+    match upstream.split_once("://").map(|(scheme, _)| scheme) {
+        Some("https") => "DoH",
+        Some("h3") => "DoH3",
+        Some("tls") => "DoT",
+        Some("quic") => "DoQ",
+        Some("sdns") => "DNSCrypt",
+        Some("tcp") => "TCP",
+        _ => "plain",
+    }
+}
+
+/// Domains and upstream(s) of a `[/a/b/]upstream` line; None for general
+/// upstream lines
+pub fn parse_domain_upstream(line: &str) -> Option<(Vec<String>, String)> {
+    let (domains, upstream) = line.trim().strip_prefix("[/")?.split_once("/]")?;
+    Some((
+        domains
+            .split('/')
+            .filter(|d| !d.is_empty())
+            .map(str::to_string)
+            .collect(),
+        upstream.trim().to_string(),
+    ))
+}
+
+/// Upstream lines without `domain`; lines that also route other domains
+/// keep those
+pub fn without_domain(upstreams: &[String], domain: &str) -> Vec<String> {
+    upstreams
+        .iter()
+        .filter_map(|line| match parse_domain_upstream(line) {
+            Some((domains, upstream)) if domains.iter().any(|d| d == domain) => {
+                let rest: Vec<&str> = domains
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|d| *d != domain)
+                    .collect();
+                (!rest.is_empty()).then(|| format!("[/{}/]{}", rest.join("/"), upstream))
+            }
+            _ => Some(line.clone()),
+        })
+        .collect()
+}
+
+/// How AdGuard's filtering treats a name (`/control/filtering/check_host`)
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FilterCheck {
+    /// AdGuard's reason, e.g. NotFilteredNotFound, FilteredBlackList, Rewrite
+    pub reason: String,
+    pub blocked: bool,
+    /// Matching rules, each with the list it comes from
+    pub rules: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckHostResponse {
+    reason: Option<String>,
+    #[serde(default)]
+    rules: Vec<CheckHostRule>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckHostRule {
+    text: Option<String>,
+    #[serde(default)]
+    filter_list_id: i64,
+}
+
+/// Check a name against the filters, with a client's own settings (blocked
+/// services, per-client rules) when given
+pub fn check_host(name: &str, client: Option<IpAddr>, qtype: &str) -> Result<FilterCheck> {
+    resolver::check_name(name)?;
+    let client = client
+        .map(|ip| format!("&client={}", ip))
+        .unwrap_or_default();
+    let output = execute_shell(&format!(
+        "curl -fsS -u admin:shannon-admin-2026 'http://localhost:3000/control/filtering/check_host?name={}&qtype={}{}'",
+        query_escape(name),
+        query_escape(qtype),
+        client
+    ))?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "AdGuard /control/filtering/check_host".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    let check: CheckHostResponse = serde_json::from_slice(&output.stdout)?;
+    let lists = filter_names();
+    let reason = check.reason.unwrap_or_default();
+    Ok(FilterCheck {
+        blocked: reason.starts_with("Filtered"),
+        reason,
+        rules: check
+            .rules
+            .into_iter()
+            .filter_map(|rule| {
+                let list = match rule.filter_list_id {
+                    0 => "custom rules".to_string(),
+                    id => lists
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or_else(|| format!("list {}", id)),
+                };
+                Some(format!("{} ({})", rule.text?, list))
+            })
+            .collect(),
+    })
+}
+
+/// Filter list names by id
+fn filter_names() -> HashMap<i64, String> {
+    execute_shell(
+        "curl -s -u admin:shannon-admin-2026 http://localhost:3000/control/filtering/status",
+    )
+    .ok()
+    .and_then(|output| serde_json::from_slice::<AdguardFilterStatus>(&output.stdout).ok())
+    .and_then(|status| status.filters)
+    .unwrap_or_default()
+    .into_iter()
+    .filter_map(|filter| Some((filter.id, filter.name?)))
+    .collect()
+}
+
+/// A client's query as AdGuard logged it
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct LoggedQuery {
+    pub time: String,
+    /// AdGuard's reason, as in `FilterCheck`
+    pub reason: String,
+    /// Answer section as `TYPE data`
+    pub answers: Vec<String>,
+    /// Upstream that answered; None when blocked, rewritten or cached
+    pub upstream: Option<String>,
+    pub elapsed_ms: Option<f64>,
+    pub cached: bool,
+}
+
+/// A client's most recent query for a name, if still in the query log
+pub fn last_query(client: IpAddr, name: &str) -> Result<Option<LoggedQuery>> {
+    let output = execute_shell(&format!(
+        "curl -s -u admin:shannon-admin-2026 'http://localhost:3000/control/querylog?search={}&limit=100'",
+        name
+    ))?;
+    let log: QueryLogResponse =
+        serde_json::from_slice(&output.stdout).unwrap_or(QueryLogResponse { data: None });
+
+    let client = client.to_string();
+    let name = name.trim_end_matches('.');
+    Ok(log
+        .data
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| entry.client.as_deref() == Some(client.as_str()))
+        .find(|entry| {
+            entry
+                .question
+                .as_ref()
+                .and_then(|q| q.name.as_deref())
+                .is_some_and(|q| q.trim_end_matches('.').eq_ignore_ascii_case(name))
+        })
+        .map(|entry| LoggedQuery {
+            time: entry.time.unwrap_or_default(),
+            reason: entry.reason.unwrap_or_default(),
+            answers: entry
+                .answer
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|a| Some(format!("{} {}", a.rtype?, a.value?)))
+                .collect(),
+            upstream: entry.upstream.filter(|u| !u.is_empty()),
+            elapsed_ms: entry.elapsed_ms.and_then(|ms| ms.parse().ok()),
+            cached: entry.cached.unwrap_or(false),
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_domain() {
+        let upstreams = vec![
+            "https://dns.cloudflare.com/dns-query".to_string(),
+            "[/corp.example.com/]10.0.0.53".to_string(),
+            "[/home.arpa/lan/]127.0.0.1:5353".to_string(),
+        ];
+        assert_eq!(
+            without_domain(&upstreams, "home.arpa"),
+            vec![
+                upstreams[0].clone(),
+                upstreams[1].clone(),
+                "[/lan/]127.0.0.1:5353".to_string()
+            ]
+        );
+        assert_eq!(without_domain(&upstreams, "corp.example.com").len(), 2);
+    }
+
+    #[test]
+    fn test_upstream_protocol() {
+        assert_eq!(upstream_protocol("9.9.9.9"), "plain");
+        assert_eq!(upstream_protocol("udp://9.9.9.9"), "plain");
+        assert_eq!(upstream_protocol("https://dns.quad9.net/dns-query"), "DoH");
+        assert_eq!(upstream_protocol("tls://one.one.one.one"), "DoT");
+        assert_eq!(upstream_protocol("quic://dns.adguard-dns.com"), "DoQ");
+    }
+}
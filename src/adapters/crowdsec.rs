@@ -1,16 +1,39 @@
-//! CrowdSec IDS adapter — wraps `cscli` commands
+//! CrowdSec IDS adapter — client for the local API (LAPI)
+//!
+//! Logs in with the machine credentials `cscli` uses and reads alerts
+//! together with their decisions, so each decision carries the scenario and
+//! the source's country and AS. Without machine credentials it falls back to
+//! the firewall bouncer's API key, which can only list decisions. Engine
+//! counters come from the Prometheus endpoint; hub items, bouncers and
+//! decision deletes go through `cscli`. The allowlist is a whitelist parser
+//! file shannon owns.
 
 use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Display;
 
-use crate::location::execute_shell;
+use crate::error::ShannonError;
+use crate::location::{
+    execute_mutation, execute_shell, execute_shell_with_input, systemctl, write_file,
+};
 
-#[derive(Debug, Serialize)]
+const MACHINE_CREDENTIALS: &str = "/etc/crowdsec/local_api_credentials.yaml";
+const BOUNCER_CONFIG: &str = "/etc/crowdsec/bouncers/crowdsec-firewall-bouncer.yaml";
+const DEFAULT_LAPI_URL: &str = "http://127.0.0.1:8080/";
+const METRICS_URL: &str = "http://127.0.0.1:6060/metrics";
+/// Whitelist parser holding `shannon sec allowlist` entries
+pub const ALLOWLIST_PATH: &str = "/etc/crowdsec/parsers/s02-enrich/shannon-allowlist.yaml";
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct CrowdsecStatus {
     pub running: bool,
     pub active_decisions: u32,
     pub scenarios_loaded: u32,
+    /// Engine counters since the service started
+    pub metrics: CrowdsecMetrics,
 }
 
 impl Display for CrowdsecStatus {
@@ -18,33 +41,461 @@ impl Display for CrowdsecStatus {
         if !self.running {
             return writeln!(f, "  CrowdSec: not running");
         }
-        writeln!(f, "  CrowdSec: active ({} decisions, {} scenarios)",
-            self.active_decisions, self.scenarios_loaded)?;
+        writeln!(
+            f,
+            "  CrowdSec: active ({} decisions, {} scenarios, {} lines parsed, {} overflows)",
+            self.active_decisions,
+            self.scenarios_loaded,
+            self.metrics.lines_parsed,
+            self.metrics.overflows
+        )?;
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An active remediation, e.g. a ban on an IP
+#[derive(Debug, Serialize, JsonSchema, Clone)]
 pub struct CrowdsecDecision {
     pub id: u64,
-    #[serde(alias = "value")]
-    pub source_ip: String,
-    #[serde(alias = "scenario")]
-    pub reason: String,
-    #[serde(alias = "type")]
+    /// IP, range, country code or AS number, depending on `scope`
+    pub value: String,
+    /// "Ip", "Range", "Country" or "AS"
+    pub scope: String,
+    /// Remediation, usually "ban"
     pub action: String,
-    pub duration: String,
+    pub scenario: String,
+    /// "crowdsec" (local detection), "cscli" (manual) or a blocklist
     pub origin: String,
+    /// ISO country code of the source
+    pub country: Option<String>,
+    pub as_number: Option<String>,
+    pub as_name: Option<String>,
+    pub until: Option<DateTime<Utc>>,
 }
 
 impl Display for CrowdsecDecision {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "  {} — {} ({})", self.source_ip, self.reason, self.duration)?;
-        writeln!(f, "     Action: {} | Origin: {}", self.action, self.origin)?;
+        let until = self
+            .until
+            .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        writeln!(f, "  {} — {} (until {})", self.value, self.scenario, until)?;
+        let source = [self.country.as_deref(), self.as_name.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if source.is_empty() {
+            writeln!(f, "     Action: {} | Origin: {}", self.action, self.origin)?;
+        } else {
+            writeln!(
+                f,
+                "     Action: {} | Origin: {} | Source: {}",
+                self.action, self.origin, source
+            )?;
+        }
         Ok(())
     }
 }
 
+/// An alert raised when a scenario's bucket overflowed
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct CrowdsecAlert {
+    pub id: u64,
+    pub scenario: String,
+    pub message: String,
+    /// Source IP (or range, for range-scoped alerts)
+    pub source: String,
+    pub country: Option<String>,
+    pub as_number: Option<String>,
+    pub as_name: Option<String>,
+    /// Log events that made up the alert
+    pub events_count: u32,
+    pub started_at: Option<DateTime<Utc>>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    /// Decisions taken in response, including expired ones
+    pub decisions: Vec<CrowdsecDecision>,
+}
+
+/// Filters for `LapiClient::alerts`
+#[derive(Debug, Default)]
+pub struct AlertQuery {
+    /// Only alerts newer than this
+    pub since: Option<Duration>,
+    pub scenario: Option<String>,
+    pub ip: Option<String>,
+    /// Only alerts with a decision still in force
+    pub active_only: bool,
+}
+
+/// Engine counters from the Prometheus endpoint
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct CrowdsecMetrics {
+    /// Log lines read from all acquisition sources
+    pub lines_read: u64,
+    pub lines_parsed: u64,
+    pub lines_unparsed: u64,
+    /// Scenario buckets that overflowed into alerts
+    pub overflows: u64,
+}
+
+/// An installed hub item (collection, parser, scenario, ...)
+#[derive(Debug, Serialize, JsonSchema, Deserialize)]
+pub struct HubItem {
+    pub name: String,
+    /// e.g. "enabled", "enabled,update-available", "enabled,tainted"
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub local_version: Option<String>,
+    #[serde(default = "default_true", rename = "utd")]
+    pub up_to_date: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Installed hub items by kind
+#[derive(Debug, Default, Serialize, JsonSchema, Deserialize)]
+pub struct HubState {
+    #[serde(default)]
+    pub collections: Vec<HubItem>,
+    #[serde(default)]
+    pub parsers: Vec<HubItem>,
+    #[serde(default)]
+    pub scenarios: Vec<HubItem>,
+    #[serde(default)]
+    pub postoverflows: Vec<HubItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawDecision {
+    id: u64,
+    origin: String,
+    #[serde(rename = "type")]
+    action: String,
+    scope: String,
+    value: String,
+    /// Remaining time as a Go duration ("3h59m12.5s"), negative once expired
+    duration: String,
+    #[serde(default)]
+    scenario: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawSource {
+    #[serde(default)]
+    value: Option<String>,
+    #[serde(default)]
+    cn: Option<String>,
+    #[serde(default)]
+    as_number: Option<String>,
+    #[serde(default)]
+    as_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawAlert {
+    id: u64,
+    scenario: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    events_count: u32,
+    #[serde(default)]
+    start_at: Option<String>,
+    #[serde(default)]
+    stop_at: Option<String>,
+    #[serde(default)]
+    source: RawSource,
+    #[serde(default)]
+    decisions: Option<Vec<RawDecision>>,
+}
+
+/// LAPI returns empty strings for unknown source fields
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+fn parse_time(value: Option<&str>) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value?)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+impl RawDecision {
+    fn into_decision(self, source: &RawSource, now: DateTime<Utc>) -> CrowdsecDecision {
+        CrowdsecDecision {
+            until: parse_go_duration(&self.duration).map(|d| now + d),
+            id: self.id,
+            value: self.value,
+            scope: self.scope,
+            action: self.action,
+            scenario: self.scenario,
+            origin: self.origin,
+            country: non_empty(source.cn.clone()),
+            as_number: non_empty(source.as_number.clone()),
+            as_name: non_empty(source.as_name.clone()),
+        }
+    }
+}
+
+impl RawAlert {
+    fn into_alert(self, now: DateTime<Utc>) -> CrowdsecAlert {
+        let source = self.source;
+        CrowdsecAlert {
+            id: self.id,
+            scenario: self.scenario,
+            message: self.message,
+            events_count: self.events_count,
+            started_at: parse_time(self.start_at.as_deref()),
+            stopped_at: parse_time(self.stop_at.as_deref()),
+            decisions: self
+                .decisions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|d| d.into_decision(&source, now))
+                .collect(),
+            source: source.value.clone().unwrap_or_default(),
+            country: non_empty(source.cn),
+            as_number: non_empty(source.as_number),
+            as_name: non_empty(source.as_name),
+        }
+    }
+}
+
+/// Parse a Go duration string ("168h0m0s", "-2m3.5s", "350ms")
+fn parse_go_duration(text: &str) -> Option<Duration> {
+    let (negative, mut rest) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut nanos: f64 = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3_600e9,
+            "m" => 60e9,
+            "s" => 1e9,
+            "ms" => 1e6,
+            "us" | "µs" => 1e3,
+            "ns" => 1.0,
+            _ => return None,
+        };
+        nanos += value * scale;
+        rest = &rest[unit_end..];
+    }
+
+    let duration = Duration::nanoseconds(nanos as i64);
+    Some(if negative { -duration } else { duration })
+}
+
+/// Flat `key: value` pairs of a simple YAML file (no nesting needed here)
+fn yaml_values(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter(|line| !line.starts_with([' ', '\t', '#']))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| {
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+fn read_config(path: &str) -> Option<HashMap<String, String>> {
+    let output = execute_shell(&format!("cat {} 2>/dev/null", path)).ok()?;
+    output
+        .status
+        .success()
+        .then(|| yaml_values(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Percent-encode a query parameter value
+pub(crate) fn query_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn lapi_error(what: &str, stderr: &[u8]) -> anyhow::Error {
+    ShannonError::CommandFailed {
+        command: format!("crowdsec lapi {}", what),
+        stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+    }
+    .into()
+}
+
+enum Auth {
+    /// JWT from a watcher login; can read alerts
+    Machine(String),
+    /// Bouncer API key; can only read decisions
+    Bouncer(String),
+}
+
+/// Authenticated session with the local API
+pub struct LapiClient {
+    url: String,
+    auth: Auth,
+}
+
+impl LapiClient {
+    /// Log in with the machine credentials, or fall back to the bouncer key
+    pub fn connect() -> Result<Self> {
+        if let Some(creds) = read_config(MACHINE_CREDENTIALS) {
+            if let (Some(login), Some(password)) = (creds.get("login"), creds.get("password")) {
+                let url = creds.get("url").map_or(DEFAULT_LAPI_URL, String::as_str);
+                return Self::login(url, login, password);
+            }
+        }
+
+        let bouncer = read_config(BOUNCER_CONFIG).unwrap_or_default();
+        let key = bouncer
+            .get("api_key")
+            .ok_or_else(|| ShannonError::CommandFailed {
+                command: "crowdsec lapi login".to_string(),
+                stderr: format!(
+                    "No machine credentials in {} and no bouncer key in {}",
+                    MACHINE_CREDENTIALS, BOUNCER_CONFIG
+                ),
+            })?;
+        Ok(LapiClient {
+            url: bouncer
+                .get("api_url")
+                .map_or(DEFAULT_LAPI_URL, String::as_str)
+                .trim_end_matches('/')
+                .to_string(),
+            auth: Auth::Bouncer(key.clone()),
+        })
+    }
+
+    fn login(url: &str, login: &str, password: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/').to_string();
+        let body = serde_json::json!({
+            "machine_id": login,
+            "password": password,
+            "scenarios": [],
+        });
+        // Credentials go over stdin so they never show up in `ps`
+        let output = execute_shell_with_input(
+            &format!("curl -sS -f --max-time 10 -H 'Content-Type: application/json' -d @- {}/v1/watchers/login", url),
+            &body.to_string(),
+        )?;
+        if !output.status.success() {
+            return Err(lapi_error("login", &output.stderr));
+        }
+
+        #[derive(Deserialize)]
+        struct LoginResponse {
+            token: String,
+        }
+        let response: LoginResponse = serde_json::from_slice(&output.stdout)?;
+        Ok(LapiClient {
+            url,
+            auth: Auth::Machine(response.token),
+        })
+    }
+
+    fn get(&self, path: &str) -> Result<String> {
+        let header = match &self.auth {
+            Auth::Machine(token) => format!("Authorization: Bearer {}", token),
+            Auth::Bouncer(key) => format!("X-Api-Key: {}", key),
+        };
+        let output = execute_shell_with_input(
+            &format!("curl -sS -f --max-time 10 -H @- '{}{}'", self.url, path),
+            &header,
+        )?;
+        if !output.status.success() {
+            return Err(lapi_error(&format!("GET {}", path), &output.stderr));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Local alerts, newest first (community blocklist entries excluded)
+    pub fn alerts(&self, query: &AlertQuery) -> Result<Vec<CrowdsecAlert>> {
+        if matches!(self.auth, Auth::Bouncer(_)) {
+            return Err(ShannonError::CommandFailed {
+                command: "crowdsec lapi GET /v1/alerts".to_string(),
+                stderr: format!(
+                    "Reading alerts needs the machine credentials in {}",
+                    MACHINE_CREDENTIALS
+                ),
+            }
+            .into());
+        }
+
+        let mut params = vec!["include_capi=false".to_string(), "limit=0".to_string()];
+        if let Some(since) = query.since {
+            params.push(format!("since={}s", since.num_seconds()));
+        }
+        if let Some(scenario) = &query.scenario {
+            params.push(format!("scenario={}", query_escape(scenario)));
+        }
+        if let Some(ip) = &query.ip {
+            params.push(format!("ip={}", query_escape(ip)));
+        }
+        if query.active_only {
+            params.push("has_active_decision=true".to_string());
+        }
+
+        let text = self.get(&format!("/v1/alerts?{}", params.join("&")))?;
+        let raw: Option<Vec<RawAlert>> = serde_json::from_str(text.trim())?;
+        let now = Utc::now();
+        Ok(raw
+            .unwrap_or_default()
+            .into_iter()
+            .map(|a| a.into_alert(now))
+            .collect())
+    }
+
+    /// Decisions currently in force from local detections and manual bans
+    pub fn decisions(&self) -> Result<Vec<CrowdsecDecision>> {
+        let now = Utc::now();
+        let decisions: Vec<CrowdsecDecision> = match self.auth {
+            Auth::Machine(_) => {
+                let query = AlertQuery {
+                    active_only: true,
+                    ..Default::default()
+                };
+                self.alerts(&query)?
+                    .into_iter()
+                    .flat_map(|a| a.decisions)
+                    .collect()
+            }
+            Auth::Bouncer(_) => {
+                let text = self.get("/v1/decisions")?;
+                let raw: Option<Vec<RawDecision>> = serde_json::from_str(text.trim())?;
+                raw.unwrap_or_default()
+                    .into_iter()
+                    .filter(|d| d.origin != "CAPI" && d.origin != "lists")
+                    .map(|d| d.into_decision(&RawSource::default(), now))
+                    .collect()
+            }
+        };
+        Ok(decisions
+            .into_iter()
+            .filter(|d| d.until.is_none_or(|t| t > now))
+            .collect())
+    }
+}
+
 /// Check if CrowdSec engine is running
 pub fn get_status() -> Result<CrowdsecStatus> {
     let running = execute_shell("systemctl is-active crowdsec 2>/dev/null")
@@ -56,62 +507,327 @@ pub fn get_status() -> Result<CrowdsecStatus> {
             running: false,
             active_decisions: 0,
             scenarios_loaded: 0,
+            metrics: CrowdsecMetrics::default(),
         });
     }
 
-    let decisions = count_decisions().unwrap_or(0);
-    let scenarios = count_scenarios().unwrap_or(0);
+    let decisions = list_decisions().map(|d| d.len() as u32).unwrap_or(0);
+    let scenarios = hub(false).map(|h| h.scenarios.len() as u32).unwrap_or(0);
 
     Ok(CrowdsecStatus {
         running,
         active_decisions: decisions,
         scenarios_loaded: scenarios,
+        metrics: metrics().unwrap_or_default(),
     })
 }
 
 /// List active CrowdSec decisions
 pub fn list_decisions() -> Result<Vec<CrowdsecDecision>> {
-    let output = execute_shell("cscli decisions list -o json 2>/dev/null")?;
+    LapiClient::connect()?.decisions()
+}
 
+/// Engine counters from the Prometheus endpoint
+pub fn metrics() -> Result<CrowdsecMetrics> {
+    let output = execute_shell(&format!("curl -sS -f --max-time 10 {}", METRICS_URL))?;
     if !output.status.success() {
-        return Ok(vec![]);
+        return Err(lapi_error("metrics", &output.stderr));
     }
+    Ok(parse_metrics(&String::from_utf8_lossy(&output.stdout)))
+}
 
-    let text = String::from_utf8_lossy(&output.stdout);
-    let text = text.trim();
+fn parse_metrics(text: &str) -> CrowdsecMetrics {
+    let mut metrics = CrowdsecMetrics::default();
+    for line in text.lines().filter(|l| !l.starts_with('#')) {
+        let Some((series, value)) = line.rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            continue;
+        };
+        let name = series.split('{').next().unwrap_or(series);
+        let counter = match name {
+            "cs_parser_hits_ok_total" => &mut metrics.lines_parsed,
+            "cs_parser_hits_ko_total" => &mut metrics.lines_unparsed,
+            "cs_bucket_overflowed_total" => &mut metrics.overflows,
+            // One `*_hits_total` counter per acquisition type (file, journalctl, ...)
+            n if n.starts_with("cs_") && n.ends_with("source_hits_total") => {
+                &mut metrics.lines_read
+            }
+            _ => continue,
+        };
+        *counter += value as u64;
+    }
+    metrics
+}
 
-    if text.is_empty() || text == "null" {
-        return Ok(vec![]);
+/// Hub items: installed ones, or the whole hub index with `all`
+pub fn hub(all: bool) -> Result<HubState> {
+    let cmd = if all {
+        "cscli hub list -a -o json"
+    } else {
+        "cscli hub list -o json"
+    };
+    let output = execute_shell(&format!("{} 2>/dev/null", cmd))?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
     }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
 
-    let decisions: Vec<CrowdsecDecision> = serde_json::from_str(text)
-        .unwrap_or_default();
+impl HubState {
+    /// Items of one kind ("collection", "parser", "scenario", "postoverflow")
+    pub fn items(&self, kind: &str) -> &[HubItem] {
+        match kind {
+            "collection" => &self.collections,
+            "parser" => &self.parsers,
+            "scenario" => &self.scenarios,
+            "postoverflow" => &self.postoverflows,
+            _ => &[],
+        }
+    }
+}
 
-    Ok(decisions)
+/// Hub item kinds, as accepted by `cscli <kind>s ...`
+pub const HUB_KINDS: [&str; 4] = ["collection", "parser", "scenario", "postoverflow"];
+
+fn cscli(args: &str) -> Result<()> {
+    let cmd = format!("cscli {}", args);
+    let output = execute_mutation(&cmd)?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(())
 }
 
-fn count_decisions() -> Result<u32> {
-    let output = execute_shell("cscli decisions list -o json 2>/dev/null")?;
-    let text = String::from_utf8_lossy(&output.stdout);
-    let text = text.trim();
+/// Install a hub item (after refreshing the hub index)
+pub fn hub_install(kind: &str, name: &str) -> Result<()> {
+    cscli("hub update")?;
+    cscli(&format!("{}s install {}", kind, name))
+}
 
-    if text.is_empty() || text == "null" {
-        return Ok(0);
+pub fn hub_remove(kind: &str, name: &str) -> Result<()> {
+    cscli(&format!("{}s remove {}", kind, name))
+}
+
+/// Upgrade one item, or everything installed when `item` is None
+pub fn hub_upgrade(item: Option<(&str, &str)>) -> Result<()> {
+    cscli("hub update")?;
+    match item {
+        Some((kind, name)) => cscli(&format!("{}s upgrade {}", kind, name)),
+        None => cscli("hub upgrade"),
     }
+}
+
+/// Reload the engine so hub and allowlist changes take effect
+pub fn reload() -> Result<()> {
+    systemctl("reload", "crowdsec")
+}
+
+/// Names of the bouncers registered with the local API
+pub fn bouncers() -> Result<Vec<String>> {
+    let output = execute_shell("cscli bouncers list -o json 2>/dev/null")?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "cscli bouncers list".to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+
+    #[derive(Deserialize)]
+    struct Bouncer {
+        name: String,
+    }
+    let bouncers: Option<Vec<Bouncer>> = serde_json::from_slice(&output.stdout)?;
+    Ok(bouncers
+        .unwrap_or_default()
+        .into_iter()
+        .map(|b| b.name)
+        .collect())
+}
+
+/// An address or range CrowdSec never raises alerts for
+#[derive(Debug, Serialize, JsonSchema, Clone, PartialEq)]
+pub struct AllowlistEntry {
+    /// IP address or CIDR range
+    pub value: String,
+    /// Why it is allowlisted (e.g. "office")
+    pub note: Option<String>,
+}
+
+/// Parse the allowlist parser file written by `render_allowlist`
+fn parse_allowlist(text: &str) -> Vec<AllowlistEntry> {
+    let mut in_list = false;
+    text.lines()
+        .filter_map(|line| {
+            let trimmed = line.trim();
+            if trimmed == "ip:" || trimmed == "cidr:" {
+                in_list = true;
+                return None;
+            }
+            if !trimmed.starts_with("- ") {
+                in_list = false;
+                return None;
+            }
+            if !in_list {
+                return None;
+            }
+            let item = trimmed.trim_start_matches("- ");
+            let (value, note) = match item.split_once('#') {
+                Some((value, note)) => (value, Some(note.trim().to_string())),
+                None => (item, None),
+            };
+            Some(AllowlistEntry {
+                value: value.trim().trim_matches('"').to_string(),
+                note: note.filter(|n| !n.is_empty()),
+            })
+        })
+        .collect()
+}
+
+/// Render the allowlist as a CrowdSec whitelist parser
+fn render_allowlist(entries: &[AllowlistEntry]) -> String {
+    let list = |key: &str, cidr: bool| -> String {
+        let items: String = entries
+            .iter()
+            .filter(|e| e.value.contains('/') == cidr)
+            .map(|e| match &e.note {
+                Some(note) => format!("    - \"{}\" # {}\n", e.value, note),
+                None => format!("    - \"{}\"\n", e.value),
+            })
+            .collect();
+        if items.is_empty() {
+            format!("  {}: []\n", key)
+        } else {
+            format!("  {}:\n{}", key, items)
+        }
+    };
+    format!(
+        "# Managed by shannon (`shannon sec allowlist`); edits here are overwritten\n\
+         name: shannon/allowlist\n\
+         description: \"Addresses shannon never lets CrowdSec ban\"\n\
+         whitelist:\n  \
+           reason: \"shannon allowlist\"\n{}{}",
+        list("ip", false),
+        list("cidr", true)
+    )
+}
+
+/// Current allowlist (empty when the parser file doesn't exist yet)
+pub fn read_allowlist() -> Result<Vec<AllowlistEntry>> {
+    let output = execute_shell(&format!("cat {} 2>/dev/null", ALLOWLIST_PATH))?;
+    Ok(parse_allowlist(&String::from_utf8_lossy(&output.stdout)))
+}
+
+pub fn write_allowlist(entries: &[AllowlistEntry]) -> Result<()> {
+    write_file(ALLOWLIST_PATH, &render_allowlist(entries))
+}
 
-    let arr: Vec<serde_json::Value> = serde_json::from_str(text).unwrap_or_default();
-    Ok(arr.len() as u32)
+/// Lift any ban on an IP or range that was just allowlisted
+pub fn delete_decisions(value: &str) -> Result<()> {
+    let flag = if value.contains('/') {
+        "--range"
+    } else {
+        "--ip"
+    };
+    cscli(&format!("decisions delete {} {}", flag, value))
 }
 
-fn count_scenarios() -> Result<u32> {
-    let output = execute_shell("cscli scenarios list -o json 2>/dev/null")?;
-    let text = String::from_utf8_lossy(&output.stdout);
-    let text = text.trim();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if text.is_empty() || text == "null" {
-        return Ok(0);
+    #[test]
+    fn test_parse_go_duration() {
+        assert_eq!(parse_go_duration("168h0m0s"), Some(Duration::hours(168)));
+        assert_eq!(
+            parse_go_duration("3h59m12.5s"),
+            Some(Duration::seconds(3 * 3600 + 59 * 60 + 12) + Duration::milliseconds(500))
+        );
+        assert_eq!(parse_go_duration("-2m3s"), Some(-Duration::seconds(123)));
+        assert_eq!(
+            parse_go_duration("350ms"),
+            Some(Duration::milliseconds(350))
+        );
+        assert_eq!(parse_go_duration("4d"), None);
+        assert_eq!(parse_go_duration(""), None);
     }
 
-    let arr: Vec<serde_json::Value> = serde_json::from_str(text).unwrap_or_default();
-    Ok(arr.len() as u32)
+    #[test]
+    fn test_alert_decisions_carry_source() {
+        let json = r#"[{"id": 7, "scenario": "crowdsecurity/ssh-bf", "message": "",
+            "events_count": 6, "start_at": "2026-10-18T09:00:00Z", "stop_at": "2026-10-18T09:00:05Z",
+            "source": {"scope": "Ip", "value": "203.0.113.9", "cn": "NL", "as_number": "1136", "as_name": "KPN B.V."},
+            "decisions": [{"id": 41, "origin": "crowdsec", "type": "ban", "scope": "Ip",
+                "value": "203.0.113.9", "duration": "3h59m0s", "scenario": "crowdsecurity/ssh-bf"}]},
+            {"id": 8, "scenario": "crowdsecurity/http-probing", "source": {"cn": ""}, "decisions": null}]"#;
+        let now = DateTime::parse_from_rfc3339("2026-10-18T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let alerts: Vec<CrowdsecAlert> = serde_json::from_str::<Vec<RawAlert>>(json)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.into_alert(now))
+            .collect();
+
+        let decision = &alerts[0].decisions[0];
+        assert_eq!(decision.country.as_deref(), Some("NL"));
+        assert_eq!(decision.as_name.as_deref(), Some("KPN B.V."));
+        assert_eq!(decision.until, Some(now + Duration::minutes(239)));
+        assert_eq!(alerts[1].country, None);
+        assert!(alerts[1].decisions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_metrics_sums_labelled_series() {
+        let text = "# HELP cs_parser_hits_ok_total Total events successfully parsed.\n\
+            cs_parser_hits_ok_total{source=\"/var/log/auth.log\",type=\"file\"} 1200\n\
+            cs_parser_hits_ok_total{source=\"journalctl\",type=\"journalctl\"} 34\n\
+            cs_parser_hits_ko_total{source=\"/var/log/auth.log\",type=\"file\"} 5\n\
+            cs_filesource_hits_total{source=\"/var/log/auth.log\"} 1205\n\
+            cs_journalctlsource_hits_total{source=\"journalctl\"} 34\n\
+            cs_bucket_overflowed_total{name=\"crowdsecurity/ssh-bf\"} 3\n\
+            go_goroutines 57\n";
+        let metrics = parse_metrics(text);
+        assert_eq!(metrics.lines_parsed, 1234);
+        assert_eq!(metrics.lines_unparsed, 5);
+        assert_eq!(metrics.lines_read, 1239);
+        assert_eq!(metrics.overflows, 3);
+    }
+
+    #[test]
+    fn test_allowlist_round_trip() {
+        let entries = vec![
+            AllowlistEntry {
+                value: "198.51.100.20".to_string(),
+                note: Some("office".to_string()),
+            },
+            AllowlistEntry {
+                value: "10.8.0.0/24".to_string(),
+                note: Some("wg0 peers".to_string()),
+            },
+            AllowlistEntry {
+                value: "192.0.2.1".to_string(),
+                note: None,
+            },
+        ];
+        let text = render_allowlist(&entries);
+        assert!(text
+            .contains("  ip:\n    - \"198.51.100.20\" # office\n    - \"192.0.2.1\"\n  cidr:\n"));
+
+        let mut parsed = parse_allowlist(&text);
+        parsed.sort_by(|a, b| a.value.cmp(&b.value));
+        let mut expected = entries;
+        expected.sort_by(|a, b| a.value.cmp(&b.value));
+        assert_eq!(parsed, expected);
+    }
 }
@@ -1,29 +1,112 @@
 //! dnsmasq configuration adapter
 
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::Serialize;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-use crate::location::{execute_shell, read_file, systemctl, write_file};
+use crate::error::ShannonError;
+use crate::location::{read_file, systemctl, write_file};
 
 const DNSMASQ_CUSTOM: &str = "/etc/dnsmasq.d/custom.conf";
 const DNSMASQ_LEASES: &str = "/var/lib/misc/dnsmasq.leases";
 const DNSMASQ_CONF: &str = "/etc/dnsmasq.conf";
 
-#[derive(Debug, Serialize, Clone)]
+/// `dns add --type` values; A and AAAA are also inferred from an address
+pub const RECORD_TYPES: [&str; 7] = ["a", "aaaa", "cname", "txt", "srv", "mx", "ptr"];
+
+#[derive(Debug, Serialize, JsonSchema, Clone)]
 pub struct DnsRecord {
+    /// Record name (`*.domain` for a wildcard, reverse name for PTR)
     pub hostname: String,
-    pub ip: IpAddr,
+    /// A, AAAA, CNAME, TXT, SRV, MX or PTR
+    #[serde(rename = "type")]
+    pub record_type: String,
+    /// Address, target name or text; SRV as `target,port,priority,weight`
+    /// and MX as `host,preference`
+    pub value: String,
     pub source: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, JsonSchema, Clone)]
 pub struct DhcpLease {
     pub mac: String,
     pub ip: String,
-    pub hostname: String,
-    pub expires: String,
-    pub is_static: bool,
+    pub hostname: Option<String>,
+    /// None for infinite leases
+    pub expires: Option<DateTime<Utc>>,
+    /// Not yet expired (dnsmasq prunes the leases file lazily)
+    pub active: bool,
+    /// DHCP client identifier (option 61), if the client sent one
+    pub client_id: Option<String>,
+    /// A `dhcp-host=` reservation exists for the MAC
+    pub reserved: bool,
+}
+
+/// Dynamic pool and the options handed out with it
+#[derive(Debug, Serialize, JsonSchema, Clone, Default)]
+pub struct DhcpScope {
+    /// First address of the dynamic pool (first non-static dhcp-range=)
+    pub range_start: Option<Ipv4Addr>,
+    /// Last address of the dynamic pool
+    pub range_end: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    /// As written in the config ("12h", "infinite"); dnsmasq's default is 1h
+    pub lease_time: Option<String>,
+    /// Router option (3)
+    pub gateway: Option<Ipv4Addr>,
+    /// DNS server option (6)
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// Local domain (domain=)
+    pub domain: Option<String>,
+    /// How IPv6 clients are configured: "ra-only", "stateless", "slaac" or
+    /// "stateful"; None when dnsmasq serves no IPv6
+    pub ipv6_mode: Option<String>,
+    /// LAN interface whose (delegated) prefix the IPv6 range is built from
+    pub ipv6_interface: Option<String>,
+}
+
+impl DhcpScope {
+    /// Addresses in the dynamic pool
+    pub fn size(&self) -> Option<u32> {
+        let (start, end) = (self.range_start?, self.range_end?);
+        Some(u32::from(end).checked_sub(u32::from(start))? + 1)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        match (self.range_start, self.range_end) {
+            (Some(start), Some(end)) => (start..=end).contains(&ip),
+            _ => false,
+        }
+    }
+}
+
+/// Changes for `DnsmasqAdapter::set_scope`; None leaves a setting alone
+#[derive(Debug, Default)]
+pub struct ScopeChange {
+    pub range: Option<(Ipv4Addr, Ipv4Addr)>,
+    pub lease_time: Option<String>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Option<Vec<Ipv4Addr>>,
+    pub domain: Option<String>,
+    /// One of `IPV6_MODES`
+    pub ipv6_mode: Option<String>,
+}
+
+/// IPv6 modes for `set_scope`: no IPv6, router advertisements only, SLAAC
+/// with DHCPv6 for options, SLAAC plus DHCPv6 addresses, DHCPv6 addresses only
+pub const IPV6_MODES: [&str; 5] = ["off", "ra-only", "stateless", "slaac", "stateful"];
+
+/// Host part of DHCPv6 address ranges, inside the LAN's /64
+const DHCPV6_RANGE: (&str, &str) = ("::1000", "::1fff");
+
+/// A static `dhcp-host=` reservation
+#[derive(Debug, Serialize, JsonSchema, Clone)]
+pub struct DhcpReservation {
+    pub mac: String,
+    pub ip: String,
+    pub hostname: Option<String>,
 }
 
 pub struct DnsmasqAdapter;
@@ -61,7 +144,8 @@ impl DnsmasqAdapter {
                             if *hostname != "localhost" {
                                 records.push(DnsRecord {
                                     hostname: hostname.to_string(),
-                                    ip,
+                                    record_type: address_type(ip).to_string(),
+                                    value: ip.to_string(),
                                     source: "hosts".to_string(),
                                 });
                             }
@@ -75,121 +159,130 @@ impl DnsmasqAdapter {
     }
 
     fn parse_dns_entries(&self, content: &str, source: &str) -> Vec<DnsRecord> {
-        let mut records = Vec::new();
-
-        for line in content.lines() {
-            let line = line.trim();
-            if line.starts_with("address=/") {
-                // Format: address=/hostname/ip
-                let parts: Vec<&str> = line.trim_start_matches("address=/").split('/').collect();
-                if parts.len() >= 2 {
-                    if let Ok(ip) = parts[1].parse::<IpAddr>() {
-                        records.push(DnsRecord {
-                            hostname: parts[0].to_string(),
-                            ip,
-                            source: source.to_string(),
-                        });
-                    }
-                }
-            }
-        }
-
-        records
+        content
+            .lines()
+            .flat_map(|line| parse_dns_line(line, source))
+            .collect()
     }
 
-    /// Add a DNS entry to custom config
-    pub fn add_dns_entry(&self, hostname: &str, ip: IpAddr) -> Result<()> {
+    /// Add a DNS record to custom config
+    ///
+    /// A records also get a PTR record for their address, unless one is
+    /// already there. Returns the records written.
+    pub fn add_dns_record(
+        &self,
+        name: &str,
+        record_type: &str,
+        value: &str,
+    ) -> Result<Vec<DnsRecord>> {
         // Read existing custom config or create empty
         let existing = read_file(DNSMASQ_CUSTOM).unwrap_or_default();
+        let records = self.parse_dns_entries(&existing, "custom");
 
-        // Check for duplicates
-        if existing.contains(&format!("address=/{}/", hostname)) {
-            anyhow::bail!("DNS entry for {} already exists", hostname);
-        }
+        let line = record_line(name, record_type, value)?;
+        let mut added = parse_dns_line(&line, "custom");
+        let record = added[0].clone();
+        check_conflicts(&records, &record)?;
 
-        // Append new entry
-        let new_content = format!("{}address=/{}/{}\n", existing, hostname, ip);
+        let mut new_content = format!("{}{}\n", existing, line);
+        if record.record_type == "A" && !name.starts_with("*.") {
+            let ip: IpAddr = record.value.parse()?;
+            let reverse = reverse_name(ip);
+            if !records
+                .iter()
+                .any(|r| r.record_type == "PTR" && r.hostname == reverse)
+            {
+                let ptr = format!("ptr-record={},{}", reverse, name);
+                new_content.push_str(&ptr);
+                new_content.push('\n');
+                added.extend(parse_dns_line(&ptr, "custom"));
+            }
+        }
         write_file(DNSMASQ_CUSTOM, &new_content)?;
 
-        // Reload dnsmasq
-        self.reload()?;
+        // cname=, txt-record=, srv-host=, mx-host= and ptr-record= are only
+        // read at startup
+        self.restart()?;
 
-        Ok(())
+        Ok(added)
     }
 
-    /// Remove a DNS entry from custom config
+    /// Remove every record for a name from custom config, along with PTR
+    /// records pointing at it
     pub fn remove_dns_entry(&self, hostname: &str) -> Result<()> {
         let content = read_file(DNSMASQ_CUSTOM)?;
 
-        let pattern = format!("address=/{}/", hostname);
+        // PTR records can be removed by address too
+        let name = match hostname.parse::<IpAddr>() {
+            Ok(ip) => reverse_name(ip),
+            Err(_) => hostname.to_string(),
+        };
         let new_content: String = content
             .lines()
-            .filter(|line| !line.contains(&pattern))
+            .filter(|line| {
+                !parse_dns_line(line, "custom")
+                    .iter()
+                    .any(|r| r.hostname == name || (r.record_type == "PTR" && r.value == name))
+            })
             .collect::<Vec<_>>()
             .join("\n")
             + "\n";
 
         if new_content == content {
-            anyhow::bail!("DNS entry for {} not found in custom config", hostname);
+            return Err(ShannonError::NotFound(format!(
+                "DNS entry for {} in custom config",
+                hostname
+            ))
+            .into());
         }
 
         write_file(DNSMASQ_CUSTOM, &new_content)?;
-        self.reload()?;
+        self.restart()?;
 
         Ok(())
     }
 
-    /// List DHCP leases
+    /// List DHCP leases from the leases file, including expired ones
     pub fn list_leases(&self) -> Result<Vec<DhcpLease>> {
-        let mut leases = Vec::new();
-
-        // Parse active leases file
-        // Format: expiry mac ip hostname client-id
-        if let Ok(content) = read_file(DNSMASQ_LEASES) {
-            for line in content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 4 {
-                    leases.push(DhcpLease {
-                        expires: parts[0].to_string(),
-                        mac: parts[1].to_string(),
-                        ip: parts[2].to_string(),
-                        hostname: parts[3].to_string(),
-                        is_static: false,
-                    });
-                }
-            }
+        let reservations = self.list_reservations()?;
+        let content = read_file(DNSMASQ_LEASES).unwrap_or_default();
+        let mut leases = parse_leases(&content, Utc::now());
+        for lease in &mut leases {
+            lease.reserved = reservations
+                .iter()
+                .any(|r| r.mac.eq_ignore_ascii_case(&lease.mac));
         }
+        Ok(leases)
+    }
 
-        // Parse static reservations from config (dhcp-host= lines)
-        if let Ok(content) = read_file(DNSMASQ_CONF) {
-            leases.extend(self.parse_static_reservations(&content));
-        }
+    /// List static reservations (dhcp-host= lines)
+    pub fn list_reservations(&self) -> Result<Vec<DhcpReservation>> {
+        let content = read_file(DNSMASQ_CONF).unwrap_or_default();
+        Ok(parse_reservations(&content))
+    }
 
-        Ok(leases)
+    /// Dynamic pools (dhcp-range=), as inclusive IPv4 start/end pairs
+    pub fn dynamic_ranges(&self) -> Result<Vec<(Ipv4Addr, Ipv4Addr)>> {
+        let content = read_file(DNSMASQ_CONF).unwrap_or_default();
+        Ok(parse_dynamic_ranges(&content))
     }
 
-    fn parse_static_reservations(&self, content: &str) -> Vec<DhcpLease> {
-        let mut reservations = Vec::new();
+    /// The dynamic pool, lease time, gateway, DNS servers and domain
+    pub fn scope(&self) -> Result<DhcpScope> {
+        let content = read_file(DNSMASQ_CONF).unwrap_or_default();
+        Ok(parse_scope(&content))
+    }
 
-        for line in content.lines() {
-            let line = line.trim();
-            if line.starts_with("dhcp-host=") {
-                // Format: dhcp-host=mac,ip[,hostname]
-                let value = line.trim_start_matches("dhcp-host=");
-                let parts: Vec<&str> = value.split(',').collect();
-                if parts.len() >= 2 {
-                    reservations.push(DhcpLease {
-                        mac: parts[0].to_string(),
-                        ip: parts[1].to_string(),
-                        hostname: parts.get(2).unwrap_or(&"").to_string(),
-                        expires: "static".to_string(),
-                        is_static: true,
-                    });
-                }
-            }
+    /// Rewrite the scope settings in dnsmasq.conf and restart dnsmasq (it
+    /// only reads dhcp-range and dhcp-option at startup); returns the new scope
+    pub fn set_scope(&self, change: &ScopeChange) -> Result<DhcpScope> {
+        let mut content = apply_scope_change(&read_file(DNSMASQ_CONF)?, change)?;
+        if let Some(mode) = &change.ipv6_mode {
+            content = apply_ipv6_mode(&content, mode)?;
         }
-
-        reservations
+        write_file(DNSMASQ_CONF, &content)?;
+        systemctl("restart", "dnsmasq").context("Failed to restart dnsmasq")?;
+        Ok(parse_scope(&content))
     }
 
     /// Add a static DHCP reservation
@@ -198,7 +291,7 @@ impl DnsmasqAdapter {
 
         // Check for existing reservation
         if content.contains(mac) {
-            anyhow::bail!("Reservation for MAC {} already exists", mac);
+            return Err(ShannonError::AlreadyExists(format!("Reservation for MAC {}", mac)).into());
         }
 
         // Build new entry
@@ -233,7 +326,7 @@ impl DnsmasqAdapter {
             + "\n";
 
         if new_content == content {
-            anyhow::bail!("Reservation for {} not found", target);
+            return Err(ShannonError::NotFound(format!("Reservation for {}", target)).into());
         }
 
         write_file(DNSMASQ_CONF, &new_content)?;
@@ -242,8 +335,994 @@ impl DnsmasqAdapter {
         Ok(())
     }
 
+    /// Per-domain upstreams (`server=/domain/ip`), with None for domains
+    /// answered from local data only (`local=/domain/`)
+    pub fn list_forwards(&self) -> Result<Vec<(String, Option<SocketAddr>)>> {
+        let mut forwards = Vec::new();
+        for path in [DNSMASQ_CONF, DNSMASQ_CUSTOM] {
+            if let Ok(content) = read_file(path) {
+                forwards.extend(content.lines().flat_map(parse_forward));
+            }
+        }
+        Ok(forwards)
+    }
+
+    /// Forward a domain to an upstream, or keep it local with None (restarts
+    /// dnsmasq, which only reads server= and local= at startup)
+    pub fn add_forward(&self, domain: &str, upstream: Option<SocketAddr>) -> Result<()> {
+        let existing = read_file(DNSMASQ_CUSTOM).unwrap_or_default();
+        let line = match upstream {
+            Some(addr) if addr.port() == 53 => format!("server=/{}/{}", domain, addr.ip()),
+            Some(addr) => format!("server=/{}/{}#{}", domain, addr.ip(), addr.port()),
+            None => format!("local=/{}/", domain),
+        };
+        write_file(DNSMASQ_CUSTOM, &format!("{}{}\n", existing, line))?;
+        self.restart()
+    }
+
+    /// Remove a domain's forwarding lines from custom config and restart
+    /// dnsmasq; false if it had none
+    pub fn remove_forward(&self, domain: &str) -> Result<bool> {
+        let content = read_file(DNSMASQ_CUSTOM).unwrap_or_default();
+        let new_content: String = content
+            .lines()
+            .filter(|line| !parse_forward(line).iter().any(|(d, _)| d == domain))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        if new_content == content {
+            return Ok(false);
+        }
+        write_file(DNSMASQ_CUSTOM, &new_content)?;
+        self.restart()?;
+        Ok(true)
+    }
+
+    /// Port dnsmasq answers DNS on (`port=`, 0 when DNS is off)
+    pub fn dns_port(&self) -> Result<u16> {
+        let content = read_file(DNSMASQ_CONF)?;
+        Ok(content
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("port="))
+            .filter_map(|port| port.trim().parse().ok())
+            .next_back()
+            .unwrap_or(53))
+    }
+
     /// Reload dnsmasq service
     fn reload(&self) -> Result<()> {
         systemctl("reload", "dnsmasq").context("Failed to reload dnsmasq")
     }
+
+    /// Restart dnsmasq, for settings a reload (SIGHUP) doesn't reread
+    fn restart(&self) -> Result<()> {
+        systemctl("restart", "dnsmasq").context("Failed to restart dnsmasq")
+    }
+}
+
+pub fn address_type(ip: IpAddr) -> &'static str {
+    if ip.is_ipv6() {
+        "AAAA"
+    } else {
+        "A"
+    }
+}
+
+/// `in-addr.arpa` / `ip6.arpa` name for an address
+pub fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let nibbles: Vec<String> = v6
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|byte| [byte & 0xf, byte >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+/// Records defined by one dnsmasq config line (a `cname=` line can name
+/// several aliases)
+fn parse_dns_line(line: &str, source: &str) -> Vec<DnsRecord> {
+    let record = |hostname: &str, record_type: &str, value: String| DnsRecord {
+        hostname: hostname.to_string(),
+        record_type: record_type.to_string(),
+        value,
+        source: source.to_string(),
+    };
+    let Some((option, args)) = line.trim().split_once('=') else {
+        return Vec::new();
+    };
+    let fields: Vec<&str> = args.split(',').map(str::trim).collect();
+    match option {
+        // Format: address=/hostname/ip
+        "address" => {
+            let parts: Vec<&str> = args.trim_start_matches('/').split('/').collect();
+            match parts.get(1).and_then(|ip| ip.parse::<IpAddr>().ok()) {
+                Some(ip) => vec![record(parts[0], address_type(ip), ip.to_string())],
+                None => Vec::new(),
+            }
+        }
+        // Format: cname=alias[,alias...],target[,ttl]
+        "cname" => {
+            let mut fields = fields;
+            if fields.len() > 2 && fields.last().is_some_and(|f| f.parse::<u32>().is_ok()) {
+                fields.pop();
+            }
+            match fields.split_last() {
+                Some((target, aliases)) if !aliases.is_empty() => aliases
+                    .iter()
+                    .map(|alias| record(alias, "CNAME", target.to_string()))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        }
+        "txt-record" | "srv-host" | "mx-host" | "ptr-record" => {
+            let record_type = match option {
+                "txt-record" => "TXT",
+                "srv-host" => "SRV",
+                "mx-host" => "MX",
+                _ => "PTR",
+            };
+            let value = fields[1..].join(",");
+            let value = if record_type == "TXT" {
+                value.trim_matches('"').to_string()
+            } else {
+                value
+            };
+            vec![record(fields[0], record_type, value)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Longest TXT text, in bytes: a single DNS character-string
+const TXT_MAX_LEN: usize = 255;
+
+/// The dnsmasq line for a new record, validating the name and value
+fn record_line(name: &str, record_type: &str, value: &str) -> Result<String> {
+    let invalid = |message: String| -> anyhow::Error { ShannonError::InvalidInput(message).into() };
+    let valid_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    let number = |field: Option<&str>, what: &str| -> Result<()> {
+        match field {
+            None => Ok(()),
+            Some(f) if f.parse::<u16>().is_ok() => Ok(()),
+            Some(f) => Err(invalid(format!(
+                "{} must be a number 0-65535, got {}",
+                what, f
+            ))),
+        }
+    };
+
+    let record_type = record_type.to_ascii_uppercase();
+    let wildcard = name.strip_prefix("*.");
+    if !valid_name(wildcard.unwrap_or(name)) {
+        return Err(invalid(format!("Invalid DNS name: {}", name)));
+    }
+    if wildcard.is_some() && !matches!(record_type.as_str(), "A" | "AAAA") {
+        return Err(invalid(format!(
+            "Wildcards are only supported for A/AAAA records, not {}",
+            record_type
+        )));
+    }
+
+    match record_type.as_str() {
+        "A" | "AAAA" => {
+            let ip: IpAddr = value
+                .parse()
+                .map_err(|_| invalid(format!("{} is not an IP address", value)))?;
+            if address_type(ip) != record_type {
+                return Err(invalid(format!(
+                    "{} is not an address for a {} record",
+                    ip, record_type
+                )));
+            }
+            Ok(format!("address=/{}/{}", name, ip))
+        }
+        "CNAME" if valid_name(value) => Ok(format!("cname={},{}", name, value)),
+        // One quoted character-string: no quotes or escapes, and no control
+        // characters, which would end the line and start another directive
+        "TXT" if value.len() > TXT_MAX_LEN => Err(invalid(format!(
+            "TXT text is {} bytes; dnsmasq accepts at most {}",
+            value.len(),
+            TXT_MAX_LEN
+        ))),
+        "TXT"
+            if value
+                .chars()
+                .any(|c| c == '"' || c == '\\' || c.is_control()) =>
+        {
+            Err(invalid(
+                "TXT text cannot contain double quotes, backslashes or control characters"
+                    .to_string(),
+            ))
+        }
+        "TXT" => Ok(format!("txt-record={},\"{}\"", name, value)),
+        "SRV" => {
+            // target,port[,priority[,weight]]
+            let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+            if fields.len() < 2 || fields.len() > 4 || !valid_name(fields[0]) {
+                return Err(invalid(format!(
+                    "SRV value must be target,port[,priority[,weight]], got {}",
+                    value
+                )));
+            }
+            number(fields.get(1).copied(), "SRV port")?;
+            number(fields.get(2).copied(), "SRV priority")?;
+            number(fields.get(3).copied(), "SRV weight")?;
+            Ok(format!("srv-host={},{}", name, fields.join(",")))
+        }
+        "MX" => {
+            // host[,preference]
+            let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+            if fields.len() > 2 || !valid_name(fields[0]) {
+                return Err(invalid(format!(
+                    "MX value must be host[,preference], got {}",
+                    value
+                )));
+            }
+            number(fields.get(1).copied(), "MX preference")?;
+            Ok(format!("mx-host={},{}", name, fields.join(",")))
+        }
+        "PTR" if valid_name(value) => {
+            // The name can be given as the address itself
+            let name = match name.parse::<IpAddr>() {
+                Ok(ip) => reverse_name(ip),
+                Err(_) => name.to_string(),
+            };
+            Ok(format!("ptr-record={},{}", name, value))
+        }
+        "CNAME" | "PTR" => Err(invalid(format!("Invalid target name: {}", value))),
+        other => Err(invalid(format!("Unsupported record type: {}", other))),
+    }
+}
+
+/// Check that a lease can be pinned: neither its MAC nor its address may be
+/// reserved already, and an existing `dns_name` record of the address's type
+/// must point at `ip`. Returns whether the DNS record still has to be added.
+pub fn check_pin(
+    reservations: &[DhcpReservation],
+    records: &[DnsRecord],
+    mac: &str,
+    ip: IpAddr,
+    dns_name: Option<&str>,
+) -> Result<bool> {
+    for reservation in reservations {
+        if reservation.mac.eq_ignore_ascii_case(mac) {
+            return Err(ShannonError::AlreadyExists(format!(
+                "Reservation for {} ({})",
+                mac, reservation.ip
+            ))
+            .into());
+        }
+        if reservation.ip.parse::<IpAddr>().ok() == Some(ip) {
+            return Err(ShannonError::AlreadyExists(format!(
+                "Reservation of {} (for {})",
+                ip, reservation.mac
+            ))
+            .into());
+        }
+    }
+
+    let Some(name) = dns_name else {
+        return Ok(false);
+    };
+    match records
+        .iter()
+        .find(|r| r.hostname == name && r.record_type == address_type(ip))
+    {
+        Some(record) if record.value.parse::<IpAddr>().ok() == Some(ip) => Ok(false),
+        Some(record) => Err(ShannonError::AlreadyExists(format!(
+            "DNS record {} -> {}",
+            name, record.value
+        ))
+        .into()),
+        None => Ok(true),
+    }
+}
+
+/// The dynamic pool an address falls in, if any
+pub fn dynamic_range_of(
+    ranges: &[(Ipv4Addr, Ipv4Addr)],
+    ip: IpAddr,
+) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    let IpAddr::V4(v4) = ip else {
+        return None;
+    };
+    ranges
+        .iter()
+        .copied()
+        .find(|(start, end)| (*start..=*end).contains(&v4))
+}
+
+/// A CNAME can't share its name with other records; one A and one AAAA per
+/// name; no exact duplicates
+fn check_conflicts(existing: &[DnsRecord], new: &DnsRecord) -> Result<()> {
+    let same_name: Vec<&DnsRecord> = existing
+        .iter()
+        .filter(|r| r.hostname == new.hostname)
+        .collect();
+    let conflict = same_name.iter().find(|r| {
+        r.record_type == "CNAME"
+            || new.record_type == "CNAME"
+            || (r.record_type == new.record_type
+                && (matches!(r.record_type.as_str(), "A" | "AAAA" | "PTR") || r.value == new.value))
+    });
+    match conflict {
+        Some(r) => Err(ShannonError::AlreadyExists(format!(
+            "{} record for {}",
+            r.record_type, new.hostname
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// `server=/a/b/ip[#port]` and `local=/a/`: one entry per domain. Plain
+/// `server=ip` lines (no domain) are the general upstreams and skipped.
+fn parse_forward(line: &str) -> Vec<(String, Option<SocketAddr>)> {
+    let line = line.trim();
+    let Some(rest) = line
+        .strip_prefix("server=/")
+        .or_else(|| line.strip_prefix("local=/"))
+    else {
+        return Vec::new();
+    };
+    let Some((domains, target)) = rest.rsplit_once('/') else {
+        return Vec::new();
+    };
+    let upstream = if target.is_empty() {
+        None
+    } else {
+        // ip[#port][@interface]
+        let target = target.split('@').next().unwrap_or_default();
+        let (ip, port) = target.split_once('#').unwrap_or((target, "53"));
+        match (ip.parse::<IpAddr>(), port.parse::<u16>()) {
+            (Ok(ip), Ok(port)) => Some(SocketAddr::new(ip, port)),
+            // `#` alone means the general upstreams, not a forward
+            _ => return Vec::new(),
+        }
+    };
+    domains
+        .split('/')
+        .filter(|d| !d.is_empty())
+        .map(|d| (d.to_string(), upstream))
+        .collect()
+}
+
+/// Leases file lines: `expiry mac ip hostname client-id`, with `*` for no
+/// hostname/client-id and expiry 0 for infinite leases
+///
+/// DHCPv6 leases follow a `duid <server duid>` line as `expiry iaid ipv6
+/// hostname client-duid`; they have no MAC, so they are skipped.
+fn parse_leases(content: &str, now: DateTime<Utc>) -> Vec<DhcpLease> {
+    let field = |value: Option<&&str>| value.filter(|v| **v != "*").map(|v| v.to_string());
+    content
+        .lines()
+        .take_while(|line| !line.starts_with("duid "))
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 4 || parts[2].parse::<Ipv4Addr>().is_err() {
+                return None;
+            }
+            let expires = match parts[0].parse::<i64>().ok()? {
+                0 => None,
+                epoch => Some(DateTime::from_timestamp(epoch, 0)?),
+            };
+            Some(DhcpLease {
+                mac: parts[1].to_string(),
+                ip: parts[2].to_string(),
+                hostname: field(parts.get(3)),
+                expires,
+                active: expires.is_none_or(|t| t > now),
+                client_id: field(parts.get(4)),
+                reserved: false,
+            })
+        })
+        .collect()
+}
+
+/// One `dhcp-range=[tag:..,][set:..,]start,end[,mode][,netmask][,lease time]`
+struct RangeLine {
+    start: Ipv4Addr,
+    end: Ipv4Addr,
+    netmask: Option<Ipv4Addr>,
+    lease_time: Option<String>,
+}
+
+/// Parse a dhcp-range value; `static` ranges (no dynamic pool) give None
+fn parse_range(value: &str) -> Option<RangeLine> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    if parts.contains(&"static") {
+        return None;
+    }
+    let mut addresses = parts.iter().filter_map(|p| p.parse::<Ipv4Addr>().ok());
+    Some(RangeLine {
+        start: addresses.next()?,
+        end: addresses.next()?,
+        netmask: addresses.next(),
+        lease_time: parts
+            .last()
+            .filter(|p| is_lease_time(p))
+            .map(|p| p.to_string()),
+    })
+}
+
+/// "infinite", or a number with an optional s/m/h/d/w suffix
+pub fn is_lease_time(value: &str) -> bool {
+    value == "infinite"
+        || value
+            .strip_suffix(['s', 'm', 'h', 'd', 'w'])
+            .unwrap_or(value)
+            .parse::<u64>()
+            .is_ok()
+}
+
+/// Untagged `dhcp-option=` value for a numbered/named option, as its addresses
+fn option_value<'a>(line: &'a str, number: &str, name: &str) -> Option<&'a str> {
+    let value = line.trim().strip_prefix("dhcp-option=")?;
+    let (key, rest) = value.split_once(',')?;
+    let key = key.trim();
+    (key == number || key.strip_prefix("option:") == Some(name)).then_some(rest)
+}
+
+/// `dhcp-range=` pools that hand out addresses (not `static`), as inclusive
+/// IPv4 start/end pairs
+fn parse_dynamic_ranges(content: &str) -> Vec<(Ipv4Addr, Ipv4Addr)> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("dhcp-range="))
+        .filter_map(parse_range)
+        .map(|range| (range.start, range.end))
+        .collect()
+}
+
+fn parse_scope(content: &str) -> DhcpScope {
+    let mut scope = DhcpScope::default();
+    let addresses = |value: &str| -> Vec<Ipv4Addr> {
+        value
+            .split(',')
+            .filter_map(|a| a.trim().parse().ok())
+            .collect()
+    };
+    for line in content.lines().map(str::trim) {
+        if let Some(range) = line.strip_prefix("dhcp-range=").and_then(parse_range) {
+            if scope.range_start.is_none() {
+                scope.range_start = Some(range.start);
+                scope.range_end = Some(range.end);
+                scope.netmask = range.netmask;
+                scope.lease_time = range.lease_time;
+            }
+        } else if let Some((mode, interface)) =
+            line.strip_prefix("dhcp-range=").and_then(parse_v6_range)
+        {
+            if scope.ipv6_mode.is_none() {
+                scope.ipv6_mode = Some(mode.to_string());
+                scope.ipv6_interface = interface;
+            }
+        } else if let Some(value) = option_value(line, "3", "router") {
+            scope.gateway = addresses(value).first().copied();
+        } else if let Some(value) = option_value(line, "6", "dns-server") {
+            scope.dns_servers = addresses(value);
+        } else if let Some(value) = line.strip_prefix("domain=") {
+            scope.domain = value.split(',').next().map(|d| d.trim().to_string());
+        }
+    }
+    scope
+}
+
+/// Mode and constructor interface of an IPv6 dhcp-range value
+fn parse_v6_range(value: &str) -> Option<(&'static str, Option<String>)> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let interface = parts
+        .iter()
+        .find_map(|p| p.strip_prefix("constructor:"))
+        .map(str::to_string);
+    let has_v6 = parts
+        .iter()
+        .any(|p| p.parse::<std::net::Ipv6Addr>().is_ok());
+    if interface.is_none() && !has_v6 {
+        return None;
+    }
+    let mode = if parts.contains(&"ra-only") {
+        "ra-only"
+    } else if parts.contains(&"ra-stateless") {
+        "stateless"
+    } else if parts.contains(&"slaac") {
+        "slaac"
+    } else {
+        "stateful"
+    };
+    Some((mode, interface))
+}
+
+/// dnsmasq.conf with its IPv6 ranges and `enable-ra` replaced for `mode`;
+/// ranges are built from whatever prefix the LAN interface has (constructor:),
+/// so they follow a delegated prefix when the ISP changes it
+fn apply_ipv6_mode(content: &str, mode: &str) -> Result<String> {
+    let scope = parse_scope(content);
+    let mut lines: Vec<&str> = content
+        .lines()
+        .filter(|line| {
+            let line = line.trim();
+            line != "enable-ra"
+                && line
+                    .strip_prefix("dhcp-range=")
+                    .and_then(parse_v6_range)
+                    .is_none()
+        })
+        .collect();
+    if mode == "off" {
+        return Ok(lines.join("\n") + "\n");
+    }
+
+    let interface = match scope.ipv6_interface {
+        Some(interface) => interface,
+        None => content
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("interface="))
+            .and_then(|value| value.split(',').next())
+            .map(|i| i.trim().to_string())
+            .ok_or_else(|| {
+                ShannonError::InvalidInput(
+                    "No interface= line in dnsmasq.conf to build the IPv6 range on".to_string(),
+                )
+            })?,
+    };
+    let (start, end) = DHCPV6_RANGE;
+    let range = match mode {
+        "ra-only" => format!("::,constructor:{},ra-only", interface),
+        "stateless" => format!("::,constructor:{},ra-stateless,ra-names", interface),
+        "slaac" => format!(
+            "{},{},constructor:{},slaac,ra-names,64",
+            start, end, interface
+        ),
+        _ => format!("{},{},constructor:{},64", start, end, interface),
+    };
+    let lease = scope
+        .lease_time
+        .map(|t| format!(",{}", t))
+        .unwrap_or_default();
+    let range = format!("dhcp-range={}{}", range, lease);
+    lines.push("enable-ra");
+    lines.push(&range);
+    Ok(lines.join("\n") + "\n")
+}
+
+/// dnsmasq.conf with the changed settings replaced in place (appended when
+/// missing); other lines, including tagged ranges and options, are kept
+///
+/// A lease time lives on the dynamic range, so it can't be set without one.
+fn apply_scope_change(content: &str, change: &ScopeChange) -> Result<String> {
+    let join = |servers: &[Ipv4Addr]| {
+        servers
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    let mut range_done = change.range.is_none() && change.lease_time.is_none();
+    let mut gateway_done = change.gateway.is_none();
+    let mut dns_done = change.dns_servers.is_none();
+    let mut domain_done = change.domain.is_none();
+
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if let Some(value) = trimmed.strip_prefix("dhcp-range=") {
+                if !range_done && parse_range(value).is_some() {
+                    range_done = true;
+                    return format!("dhcp-range={}", change_range(value, change));
+                }
+            } else if let (Some(gateway), Some(_)) =
+                (change.gateway, option_value(trimmed, "3", "router"))
+            {
+                gateway_done = true;
+                return format!("dhcp-option=option:router,{}", gateway);
+            } else if let (Some(servers), Some(_)) = (
+                &change.dns_servers,
+                option_value(trimmed, "6", "dns-server"),
+            ) {
+                dns_done = true;
+                return format!("dhcp-option=option:dns-server,{}", join(servers));
+            } else if let (Some(domain), true) = (&change.domain, trimmed.starts_with("domain=")) {
+                domain_done = true;
+                return format!("domain={}", domain);
+            }
+            line.to_string()
+        })
+        .collect();
+
+    if !range_done {
+        let (start, end) = change.range.ok_or_else(|| {
+            ShannonError::InvalidInput(
+                "No dynamic dhcp-range to set the lease time on; pass --range as well".to_string(),
+            )
+        })?;
+        let lease = change
+            .lease_time
+            .as_deref()
+            .map(|t| format!(",{}", t))
+            .unwrap_or_default();
+        lines.push(format!("dhcp-range={},{}{}", start, end, lease));
+    }
+    if !gateway_done {
+        lines.extend(
+            change
+                .gateway
+                .map(|g| format!("dhcp-option=option:router,{}", g)),
+        );
+    }
+    if !dns_done {
+        lines.extend(
+            change
+                .dns_servers
+                .as_ref()
+                .map(|s| format!("dhcp-option=option:dns-server,{}", join(s))),
+        );
+    }
+    if !domain_done {
+        lines.extend(change.domain.as_ref().map(|d| format!("domain={}", d)));
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// A dhcp-range value with new bounds and/or lease time, keeping tags,
+/// mode and netmask
+fn change_range(value: &str, change: &ScopeChange) -> String {
+    let mut parts: Vec<String> = value.split(',').map(|p| p.trim().to_string()).collect();
+    if let Some((start, end)) = change.range {
+        let mut bounds = [start, end].into_iter();
+        for part in parts
+            .iter_mut()
+            .filter(|p| p.parse::<Ipv4Addr>().is_ok())
+            .take(2)
+        {
+            *part = bounds.next().map(|b| b.to_string()).unwrap_or_default();
+        }
+    }
+    if let Some(lease_time) = &change.lease_time {
+        match parts.last_mut().filter(|p| is_lease_time(p)) {
+            Some(last) => *last = lease_time.clone(),
+            None => parts.push(lease_time.clone()),
+        }
+    }
+    parts.join(",")
+}
+
+/// `dhcp-host=` lines; fields are told apart by shape, since dnsmasq
+/// accepts them in any order (`set:`/`tag:`/`id:` and lease times skipped)
+fn parse_reservations(content: &str) -> Vec<DhcpReservation> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let value = line.trim().strip_prefix("dhcp-host=")?;
+            let (mut mac, mut ip, mut hostname) = (None, None, None);
+            for part in value.split(',').map(str::trim) {
+                let is_mac = part.len() == 17
+                    && part.split(':').all(|octet| {
+                        octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit())
+                    });
+                let is_lease_time = part == "infinite"
+                    || part
+                        .trim_end_matches(['s', 'm', 'h', 'd', 'w'])
+                        .parse::<u64>()
+                        .is_ok();
+                if is_mac && mac.is_none() {
+                    mac = Some(part.to_string());
+                } else if part.parse::<IpAddr>().is_ok() && ip.is_none() {
+                    ip = Some(part.to_string());
+                } else if part.contains(':') || part.is_empty() || is_lease_time {
+                    continue;
+                } else if hostname.is_none() {
+                    hostname = Some(part.to_string());
+                }
+            }
+            Some(DhcpReservation {
+                mac: mac?,
+                ip: ip?,
+                hostname,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_lines() {
+        let line = |name, record_type, value| record_line(name, record_type, value).unwrap();
+        assert_eq!(
+            line("nas", "a", "192.168.4.10"),
+            "address=/nas/192.168.4.10"
+        );
+        assert_eq!(
+            line("*.apps", "A", "192.168.4.20"),
+            "address=/*.apps/192.168.4.20"
+        );
+        assert_eq!(line("files", "cname", "nas"), "cname=files,nas");
+        assert_eq!(
+            line("lan", "txt", "v=spf1 -all"),
+            "txt-record=lan,\"v=spf1 -all\""
+        );
+        assert_eq!(
+            line("_ldap._tcp.lan", "srv", "dc, 389"),
+            "srv-host=_ldap._tcp.lan,dc,389"
+        );
+        assert_eq!(line("lan", "mx", "mail,10"), "mx-host=lan,mail,10");
+        assert_eq!(
+            line("192.168.4.10", "ptr", "nas"),
+            "ptr-record=10.4.168.192.in-addr.arpa,nas"
+        );
+        assert!(record_line("nas", "aaaa", "192.168.4.10").is_err());
+        assert!(record_line("*.apps", "cname", "nas").is_err());
+        assert!(record_line("lan", "srv", "dc").is_err());
+        assert!(record_line("lan", "txt", "ok\naddress=/bank.example/192.168.4.66").is_err());
+        assert!(record_line("lan", "txt", "tab\there").is_err());
+        assert!(record_line("lan", "txt", "\\n").is_err());
+        assert!(record_line("lan", "txt", &"x".repeat(255)).is_ok());
+        assert!(record_line("lan", "txt", &"x".repeat(256)).is_err());
+
+        // Every line reads back as the record it was written for
+        let records = parse_dns_line(&line("_ldap._tcp.lan", "srv", "dc,389,0,100"), "custom");
+        assert_eq!(records[0].record_type, "SRV");
+        assert_eq!(records[0].value, "dc,389,0,100");
+        let records = parse_dns_line("cname=files,share,nas,300", "main");
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            (records[1].hostname.as_str(), records[1].value.as_str()),
+            ("share", "nas")
+        );
+    }
+
+    #[test]
+    fn test_parse_forward() {
+        let upstream = "10.0.0.53:5353".parse().ok();
+        assert_eq!(
+            parse_forward("server=/corp.example.com/10.0.0.53#5353"),
+            vec![("corp.example.com".to_string(), upstream)]
+        );
+        assert_eq!(
+            parse_forward("local=/home.arpa/"),
+            vec![("home.arpa".to_string(), None)]
+        );
+        assert_eq!(parse_forward("server=/a/b/10.0.0.1").len(), 2);
+        assert!(parse_forward("server=1.1.1.1").is_empty());
+        assert!(parse_forward("server=/lan/#").is_empty());
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_parse_leases() {
+        let now = DateTime::from_timestamp(1760000000, 0).unwrap();
+        let content = "1760003600 aa:bb:cc:dd:ee:01 192.168.4.101 laptop 01:aa:bb:cc:dd:ee:01\n\
+            1759990000 aa:bb:cc:dd:ee:02 192.168.4.102 * *\n\
+            0 aa:bb:cc:dd:ee:03 192.168.4.103 nas *\n";
+        let leases = parse_leases(content, now);
+        assert_eq!(leases.len(), 3);
+        assert_eq!(leases[0].hostname.as_deref(), Some("laptop"));
+        assert_eq!(leases[0].client_id.as_deref(), Some("01:aa:bb:cc:dd:ee:01"));
+        assert!(leases[0].active);
+        assert_eq!(leases[1].hostname, None);
+        assert!(!leases[1].active);
+        assert_eq!(leases[2].expires, None);
+        assert!(leases[2].active);
+    }
+
+    #[test]
+    fn test_parse_leases_skips_dhcpv6() {
+        let now = DateTime::from_timestamp(1760000000, 0).unwrap();
+        let content = "1760003600 aa:bb:cc:dd:ee:01 192.168.4.101 laptop *\n\
+            duid 00:01:00:01:2c:4f:6a:10:aa:bb:cc:dd:ee:00\n\
+            1760003600 2882400001 2001:db8:4::1234 laptop 00:04:9c:3e:28:1a:7d:41:4b:8b\n\
+            1760003600 1234 2001:db8:4::1235 * 00:01:00:01:2c:4f:6a:11:aa:bb:cc:dd:ee:02\n";
+        let leases = parse_leases(content, now);
+        assert_eq!(leases.len(), 1);
+        assert_eq!(leases[0].mac, "aa:bb:cc:dd:ee:01");
+        assert_eq!(leases[0].ip, "192.168.4.101");
+    }
+
+    #[test]
+    fn test_scope_round_trip() {
+        let content = "port=0\n\
+            dhcp-range=set:lan,192.168.4.100,192.168.4.199,255.255.255.0,12h\n\
+            dhcp-option=3,192.168.4.1\n\
+            dhcp-option=tag:guest,6,1.1.1.1\n";
+        let scope = parse_scope(content);
+        assert_eq!(scope.size(), Some(100));
+        assert_eq!(scope.lease_time.as_deref(), Some("12h"));
+        assert_eq!(scope.gateway, Some(Ipv4Addr::new(192, 168, 4, 1)));
+        assert!(scope.dns_servers.is_empty());
+
+        let change = ScopeChange {
+            range: Some((
+                Ipv4Addr::new(192, 168, 4, 50),
+                Ipv4Addr::new(192, 168, 4, 149),
+            )),
+            lease_time: Some("24h".to_string()),
+            dns_servers: Some(vec![Ipv4Addr::new(192, 168, 4, 1)]),
+            domain: Some("lan".to_string()),
+            ..Default::default()
+        };
+        let updated = apply_scope_change(content, &change).unwrap();
+        assert!(
+            updated.contains("dhcp-range=set:lan,192.168.4.50,192.168.4.149,255.255.255.0,24h\n")
+        );
+        assert!(updated.contains("dhcp-option=3,192.168.4.1\n"));
+        assert!(updated.contains("dhcp-option=tag:guest,6,1.1.1.1\n"));
+        assert!(updated.ends_with("dhcp-option=option:dns-server,192.168.4.1\ndomain=lan\n"));
+        let scope = parse_scope(&updated);
+        assert_eq!(scope.size(), Some(100));
+        assert_eq!(scope.domain.as_deref(), Some("lan"));
+    }
+
+    #[test]
+    fn test_lease_time_needs_a_dynamic_range() {
+        let lease_only = ScopeChange {
+            lease_time: Some("24h".to_string()),
+            ..Default::default()
+        };
+        let static_only = "dhcp-range=192.168.4.2,static\n";
+        assert!(apply_scope_change(static_only, &lease_only).is_err());
+
+        let with_range = ScopeChange {
+            range: Some((
+                Ipv4Addr::new(192, 168, 4, 100),
+                Ipv4Addr::new(192, 168, 4, 199),
+            )),
+            ..lease_only
+        };
+        assert_eq!(
+            apply_scope_change(static_only, &with_range).unwrap(),
+            "dhcp-range=192.168.4.2,static\ndhcp-range=192.168.4.100,192.168.4.199,24h\n"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_mode() {
+        let content = "interface=eth0\ndhcp-range=192.168.4.100,192.168.4.199,12h\n";
+        let stateless = apply_ipv6_mode(content, "stateless").unwrap();
+        assert!(stateless
+            .ends_with("enable-ra\ndhcp-range=::,constructor:eth0,ra-stateless,ra-names,12h\n"));
+        let scope = parse_scope(&stateless);
+        assert_eq!(scope.ipv6_mode.as_deref(), Some("stateless"));
+        assert_eq!(scope.size(), Some(100));
+
+        let stateful = apply_ipv6_mode(&stateless, "stateful").unwrap();
+        assert_eq!(
+            parse_scope(&stateful).ipv6_mode.as_deref(),
+            Some("stateful")
+        );
+        assert_eq!(apply_ipv6_mode(&stateful, "off").unwrap(), content);
+        assert!(apply_ipv6_mode("port=0\n", "ra-only").is_err());
+    }
+
+    #[test]
+    fn test_parse_reservations_any_field_order() {
+        let content = "dhcp-host=aa:bb:cc:dd:ee:01,192.168.4.10,nas\n\
+            dhcp-host=printer,AA:BB:CC:DD:EE:02,set:iot,192.168.4.11,infinite\n\
+            dhcp-host=aa:bb:cc:dd:ee:03,ignore\n";
+        let reservations = parse_reservations(content);
+        assert_eq!(reservations.len(), 2);
+        assert_eq!(reservations[0].hostname.as_deref(), Some("nas"));
+        assert_eq!(reservations[1].mac, "AA:BB:CC:DD:EE:02");
+        assert_eq!(reservations[1].ip, "192.168.4.11");
+        assert_eq!(reservations[1].hostname.as_deref(), Some("printer"));
+    }
+
+    const PIN_CONF: &str = "dhcp-range=192.168.4.100,192.168.4.199,12h\n\
+        dhcp-range=192.168.4.2,192.168.4.49,static\n\
+        dhcp-range=::,constructor:eth0,ra-stateless,12h\n\
+        dhcp-host=aa:bb:cc:dd:ee:01,192.168.4.10,nas\n\
+        address=/nas/192.168.4.10\n\
+        address=/printer/192.168.4.11\n";
+
+    fn pin_fixture() -> (Vec<DhcpReservation>, Vec<DnsRecord>) {
+        let records = PIN_CONF
+            .lines()
+            .flat_map(|line| parse_dns_line(line, "main"))
+            .collect();
+        (parse_reservations(PIN_CONF), records)
+    }
+
+    #[test]
+    fn test_check_pin_reservation_clashes() {
+        let (reservations, records) = pin_fixture();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // Same MAC (any case) or same address as an existing reservation
+        let err = check_pin(
+            &reservations,
+            &records,
+            "AA:BB:CC:DD:EE:01",
+            ip("192.168.4.120"),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Reservation for AA:BB:CC:DD:EE:01 (192.168.4.10) already exists"
+        );
+        let err = check_pin(
+            &reservations,
+            &records,
+            "aa:bb:cc:dd:ee:02",
+            ip("192.168.4.10"),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Reservation of 192.168.4.10 (for aa:bb:cc:dd:ee:01) already exists"
+        );
+
+        assert!(!check_pin(
+            &reservations,
+            &records,
+            "aa:bb:cc:dd:ee:02",
+            ip("192.168.4.120"),
+            None
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_check_pin_dns_name() {
+        let (reservations, records) = pin_fixture();
+        let mac = "aa:bb:cc:dd:ee:02";
+        let ip: IpAddr = "192.168.4.11".parse().unwrap();
+
+        // An identical record already exists: nothing to add
+        assert!(!check_pin(&reservations, &records, mac, ip, Some("printer")).unwrap());
+        // The name points elsewhere
+        let err = check_pin(&reservations, &records, mac, ip, Some("nas")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "DNS record nas -> 192.168.4.10 already exists"
+        );
+        // New name; an IPv6 lease only clashes with AAAA records
+        assert!(check_pin(&reservations, &records, mac, ip, Some("scanner")).unwrap());
+        let v6: IpAddr = "fd00::11".parse().unwrap();
+        assert!(check_pin(&reservations, &records, mac, v6, Some("nas")).unwrap());
+    }
+
+    #[test]
+    fn test_dynamic_range_of_skips_static_and_ipv6_ranges() {
+        let ranges = parse_dynamic_ranges(PIN_CONF);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            ranges,
+            vec![(
+                Ipv4Addr::new(192, 168, 4, 100),
+                Ipv4Addr::new(192, 168, 4, 199)
+            )]
+        );
+        assert_eq!(
+            dynamic_range_of(&ranges, ip("192.168.4.199")),
+            Some(ranges[0])
+        );
+        assert_eq!(dynamic_range_of(&ranges, ip("192.168.4.20")), None);
+        assert_eq!(dynamic_range_of(&ranges, ip("fd00::150")), None);
+    }
 }
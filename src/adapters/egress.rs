@@ -0,0 +1,202 @@
+//! Policy routing of selected LAN devices through an outbound WireGuard
+//! tunnel (a commercial VPN provider's config, imported as `wgc0`)
+//!
+//! Packets from an egress device are marked in mangle PREROUTING, matched by
+//! MAC and tagged with a `shannon-egress:<name>` comment. The tunnel's PostUp
+//! hooks add an `ip rule` sending marked packets to a routing table whose
+//! default route is the tunnel; private ranges are thrown back to the main
+//! table so the LAN and VPN peers stay reachable. A persistent FORWARD rule
+//! rejects marked packets leaving via the WAN, so when the tunnel is down the
+//! devices lose internet instead of leaking (kill switch).
+//!
+//! The tunnel only carries IPv4. Egress devices are marked in ip6tables too,
+//! and their IPv6 traffic to the WAN is always rejected, so it can't bypass
+//! the tunnel.
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::adapters::nftables::{NftablesAdapter, RUNTIME_COMMENT};
+use crate::adapters::system::WAN_INTERFACE;
+use crate::error::ShannonError;
+use crate::location::{execute_mutation, execute_shell};
+
+/// Interface name the provider config is imported as
+pub const EGRESS_INTERFACE: &str = "wgc0";
+const MARK: &str = "0x51";
+const TABLE: u32 = 51;
+const RULE_PRIORITY: u32 = 5100;
+const COMMENT_PREFIX: &str = "shannon-egress:";
+const KILL_SWITCH_COMMENT: &str = "shannon-egress-killswitch";
+/// Destinations that never go through the tunnel
+const PRIVATE_RANGES: [&str; 3] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16"];
+
+/// A LAN device whose internet traffic egresses via the tunnel
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct EgressDevice {
+    pub mac: String,
+    pub name: String,
+    /// Current DHCP address, if the device has a lease
+    pub ip: Option<String>,
+}
+
+/// wg-quick hooks for the tunnel, as (key, command) pairs in order; `%i` is
+/// the interface
+pub fn hooks() -> Vec<(&'static str, String)> {
+    let rule = format!("fwmark {} table {} priority {}", MARK, TABLE, RULE_PRIORITY);
+    // Recreated with the tunnel, so kept out of the saved ruleset
+    let masquerade = |op: &str| {
+        format!(
+            "iptables -t nat -{} POSTROUTING -o %i -m comment --comment {} -j MASQUERADE",
+            op, RUNTIME_COMMENT
+        )
+    };
+
+    let mut up = vec![
+        // Replies arrive on the tunnel but the main table routes their
+        // sources via the WAN, so strict reverse-path filtering drops them
+        "sysctl -qw net.ipv4.conf.%i.rp_filter=2".to_string(),
+        format!("ip route replace default dev %i table {}", TABLE),
+    ];
+    up.extend(
+        PRIVATE_RANGES
+            .iter()
+            .map(|range| format!("ip route replace throw {} table {}", range, TABLE)),
+    );
+    up.push(format!("ip rule add {}", rule));
+    up.push(masquerade("A"));
+    let down = vec![
+        format!("ip rule del {}", rule),
+        format!("ip route flush table {}", TABLE),
+        masquerade("D"),
+    ];
+
+    up.into_iter()
+        .map(|cmd| ("PostUp", cmd))
+        .chain(down.into_iter().map(|cmd| ("PostDown", cmd)))
+        .collect()
+}
+
+fn iptables(cmd: &str) -> Result<()> {
+    let output = execute_mutation(cmd)?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Reject marked traffic headed for the WAN, over IPv4 and IPv6
+/// (idempotent)
+pub fn ensure_kill_switch() -> Result<()> {
+    let rule = format!(
+        "FORWARD -o {} -m mark --mark {} -m comment --comment {} -j REJECT",
+        WAN_INTERFACE, MARK, KILL_SWITCH_COMMENT
+    );
+    for tool in ["iptables", "ip6tables"] {
+        iptables(&format!(
+            "{tool} -C {rule} 2>/dev/null || {tool} -I {rule}",
+            tool = tool,
+            rule = rule
+        ))?;
+    }
+    let firewall = NftablesAdapter::new();
+    firewall.persist()?;
+    firewall.persist_v6()
+}
+
+/// Devices currently marked for egress (MAC and name; `ip` is left unset)
+pub fn list_devices() -> Result<Vec<EgressDevice>> {
+    let output = execute_shell("iptables-save -t mangle 2>/dev/null")?;
+    Ok(parse_devices(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_devices(rules: &str) -> Vec<EgressDevice> {
+    rules
+        .lines()
+        .filter(|line| line.starts_with("-A PREROUTING"))
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let after = |flag: &str| {
+                let i = parts.iter().position(|p| *p == flag)?;
+                parts.get(i + 1).copied()
+            };
+            let name = after("--comment")?
+                .trim_matches('"')
+                .strip_prefix(COMMENT_PREFIX)?;
+            Some(EgressDevice {
+                mac: after("--mac-source")?.to_ascii_lowercase(),
+                name: name.to_string(),
+                ip: None,
+            })
+        })
+        .collect()
+}
+
+fn mark_rule(mac: &str, name: &str) -> String {
+    format!(
+        "PREROUTING -m mac --mac-source {} -m comment --comment {}{} -j MARK --set-mark {}",
+        mac, COMMENT_PREFIX, name, MARK
+    )
+}
+
+/// Send a device's traffic through the tunnel
+///
+/// The IPv6 mark makes the kill switch reject the device's IPv6 traffic,
+/// which the tunnel can't carry.
+pub fn add_device(mac: &str, name: &str) -> Result<()> {
+    let rule = mark_rule(mac, name);
+    iptables(&format!("iptables -t mangle -A {}", rule))?;
+    iptables(&format!(
+        "ip6tables -t mangle -C {rule} 2>/dev/null || ip6tables -t mangle -A {rule}",
+        rule = rule
+    ))?;
+    ensure_kill_switch()
+}
+
+/// Return a device to the normal WAN route
+pub fn remove_device(mac: &str, name: &str) -> Result<()> {
+    let rule = mark_rule(mac, name);
+    iptables(&format!("iptables -t mangle -D {}", rule))?;
+    // Devices added before IPv6 was marked have no ip6tables rule
+    iptables(&format!(
+        "! ip6tables -t mangle -C {rule} 2>/dev/null || ip6tables -t mangle -D {rule}",
+        rule = rule
+    ))?;
+    let firewall = NftablesAdapter::new();
+    firewall.persist()?;
+    firewall.persist_v6()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_devices_only_takes_egress_marks() {
+        let rules = "*mangle\n\
+            :PREROUTING ACCEPT [0:0]\n\
+            -A PREROUTING -m mac --mac-source AA:BB:CC:00:11:22 -m comment --comment shannon-egress:tv -j MARK --set-xmark 0x51/0xffffffff\n\
+            -A PREROUTING -m mac --mac-source AA:BB:CC:33:44:55 -m comment --comment \"other\" -j MARK --set-xmark 0x7/0xffffffff\n\
+            COMMIT\n";
+        let devices = parse_devices(rules);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].mac, "aa:bb:cc:00:11:22");
+        assert_eq!(devices[0].name, "tv");
+    }
+
+    #[test]
+    fn test_tunnel_masquerade_is_not_persisted() {
+        let masquerades: Vec<String> = hooks()
+            .into_iter()
+            .map(|(_, cmd)| cmd)
+            .filter(|cmd| cmd.contains("MASQUERADE"))
+            .collect();
+        assert_eq!(masquerades.len(), 2);
+        assert!(masquerades.iter().all(|cmd| cmd.contains(RUNTIME_COMMENT)));
+    }
+}
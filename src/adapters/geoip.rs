@@ -0,0 +1,119 @@
+//! GeoIP country and ASN lookups via `mmdblookup` and the GeoLite2 databases
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use crate::location::execute_shell;
+
+const COUNTRY_DB: &str = "/usr/share/GeoIP/GeoLite2-Country.mmdb";
+const ASN_DB: &str = "/usr/share/GeoIP/GeoLite2-ASN.mmdb";
+
+/// What the local GeoIP databases know about an address
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct GeoInfo {
+    /// ISO country code
+    pub country: Option<String>,
+    pub as_number: Option<u32>,
+    /// Organisation owning the AS, e.g. "KPN B.V."
+    pub as_org: Option<String>,
+}
+
+/// ISO country codes of public addresses, in one round trip. Addresses the
+/// database doesn't know (or private ones) are left out.
+pub fn countries(ips: &[IpAddr]) -> Result<HashMap<IpAddr, String>> {
+    let public: Vec<String> = ips
+        .iter()
+        .filter(|ip| is_public(ip))
+        .map(|ip| ip.to_string())
+        .collect();
+    if public.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // One "<ip> <code>" line per address
+    let output = execute_shell(&format!(
+        "for ip in {}; do echo \"$ip $(mmdblookup --file {} --ip $ip country iso_code 2>/dev/null \
+         | grep -o '\"[A-Z]*\"' | tr -d '\"')\"; done",
+        public.join(" "),
+        COUNTRY_DB
+    ))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (ip, code) = line.split_once(' ')?;
+            let code = code.trim();
+            (!code.is_empty()).then(|| Some((ip.parse().ok()?, code.to_string())))?
+        })
+        .collect())
+}
+
+/// Country and ASN of one public address (all unset for private ones)
+pub fn lookup(ip: IpAddr) -> Result<GeoInfo> {
+    if !is_public(&ip) {
+        return Ok(GeoInfo::default());
+    }
+
+    // One line per query, holding just the value (or nothing when unknown)
+    let query = |db: &str, path: &str| {
+        format!(
+            "echo \"$(mmdblookup --file {} --ip {} {} 2>/dev/null \
+             | sed -n 's/^ *\"\\{{0,1\\}}\\([^\"]*\\)\"\\{{0,1\\}} <.*>$/\\1/p')\"",
+            db, ip, path
+        )
+    };
+    let output = execute_shell(
+        &[
+            query(COUNTRY_DB, "country iso_code"),
+            query(ASN_DB, "autonomous_system_number"),
+            query(ASN_DB, "autonomous_system_organization"),
+        ]
+        .join("; "),
+    )?;
+    Ok(parse_lookup(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_lookup(text: &str) -> GeoInfo {
+    let mut lines = text.lines().map(|line| {
+        let value = line.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    });
+    let mut next = || lines.next().flatten();
+    GeoInfo {
+        country: next(),
+        as_number: next().and_then(|n| n.parse().ok()),
+        as_org: next(),
+    }
+}
+
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified())
+        }
+        IpAddr::V6(v6) => {
+            !(v6.is_loopback() || v6.is_unspecified() || (v6.segments()[0] & 0xfe00) == 0xfc00)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_lookup_keeps_missing_values_unset() {
+        let info = parse_lookup("NL\n1136\nKPN B.V.\n");
+        assert_eq!(info.country.as_deref(), Some("NL"));
+        assert_eq!(info.as_number, Some(1136));
+        assert_eq!(info.as_org.as_deref(), Some("KPN B.V."));
+
+        let info = parse_lookup("SE\n\n\n");
+        assert_eq!(info.country.as_deref(), Some("SE"));
+        assert_eq!(info.as_number, None);
+        assert_eq!(info.as_org, None);
+    }
+}
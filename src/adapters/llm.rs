@@ -3,8 +3,10 @@
 // TODO: Implement GPT-5-mini integration for log analysis
 // See design.md for API structure
 
+#[allow(dead_code)]
 pub struct LlmAdapter;
 
+#[allow(dead_code)]
 impl LlmAdapter {
     pub fn new(_api_key: &str) -> Self {
         Self
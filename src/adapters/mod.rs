@@ -1,7 +1,11 @@
 pub mod adguard;
 pub mod crowdsec;
 pub mod dnsmasq;
+pub mod egress;
+pub mod geoip;
 pub mod llm;
 pub mod nftables;
+pub mod resolver;
 pub mod system;
+pub mod wgconf;
 pub mod wireguard;
@@ -1,13 +1,28 @@
 //! nftables/iptables adapter for firewall management
 
 use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::Serialize;
 
-use crate::location::{execute_shell, read_file, write_file};
+use crate::adapters::system::WAN_INTERFACE;
+use crate::error::ShannonError;
+use crate::location::{execute_mutation, execute_shell, read_file, write_file};
 
 const IPTABLES_RULES: &str = "/etc/iptables/rules.v4";
+const IP6TABLES_RULES: &str = "/etc/iptables/rules.v6";
+/// Comment on the ip6tables FORWARD rules opened by `fw forward` to an IPv6
+/// host (no NAT: the port is opened on the host's own address)
+pub const PINHOLE_COMMENT: &str = "shannon-pinhole";
+/// Comment on the FORWARD rules of quarantined devices
+const QUARANTINE_COMMENT: &str = "shannon-quarantine";
+/// Comment on rules that wg-quick hooks add whenever a tunnel comes up;
+/// they are left out of the saved ruleset so boot doesn't duplicate them
+pub const RUNTIME_COMMENT: &str = "shannon-runtime";
+/// Comment on the ip6tables FORWARD rules installed with the default-deny
+/// policy before LAN hosts get global IPv6 addresses
+const V6_GUARD_COMMENT: &str = "shannon-v6-guard";
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, JsonSchema, Clone)]
 pub struct PortForward {
     pub external_port: u16,
     pub internal_ip: String,
@@ -37,9 +52,56 @@ impl NftablesAdapter {
             }
         }
 
+        forwards.extend(self.list_pinholes()?);
         Ok(forwards)
     }
 
+    /// IPv6 pinholes, as forwards whose external and internal port match
+    fn list_pinholes(&self) -> Result<Vec<PortForward>> {
+        let output = execute_shell("ip6tables -S FORWARD 2>/dev/null")?;
+        Ok(parse_pinholes(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Make sure IPv6 forwarding is default-deny before LAN hosts become
+    /// reachable over IPv6 (idempotent)
+    ///
+    /// There is no NAT for IPv6, so with the stock ACCEPT policy every host
+    /// with a global address would be open to the internet. Installs, and
+    /// saves to rules.v6: accept for replies, accept for LAN-initiated
+    /// traffic to the WAN (only when the policy is changed here), then
+    /// `-P FORWARD DROP`. Pinholes are the only way in after that.
+    pub fn ensure_ipv6_forward_guard(&self) -> Result<()> {
+        let output = execute_shell("ip6tables -S FORWARD")?;
+        if !output.status.success() {
+            return Err(ShannonError::command_failed("ip6tables -S FORWARD", &output).into());
+        }
+        let commands = ipv6_guard_commands(&String::from_utf8_lossy(&output.stdout));
+        if commands.is_empty() {
+            return Ok(());
+        }
+        for cmd in &commands {
+            self.iptables(cmd)?;
+        }
+        self.persist_v6()
+    }
+
+    /// Open a port on an IPv6 LAN host to the internet (idempotent)
+    ///
+    /// A pinhole only means something under a default-deny policy, so that
+    /// is ensured first.
+    pub fn add_pinhole(&self, rule: &PortForward) -> Result<()> {
+        self.ensure_ipv6_forward_guard()?;
+        let spec = format!(
+            "FORWARD -i {} -d {} -p {} --dport {} -m comment --comment {} -j ACCEPT",
+            WAN_INTERFACE, rule.internal_ip, rule.protocol, rule.internal_port, PINHOLE_COMMENT
+        );
+        self.iptables(&format!(
+            "ip6tables -C {spec} 2>/dev/null || ip6tables -A {spec}",
+            spec = spec
+        ))?;
+        self.persist_v6()
+    }
+
     fn parse_dnat_rule(&self, line: &str) -> Option<PortForward> {
         // Example: -A PREROUTING -p tcp --dport 8080 -j DNAT --to-destination 192.168.4.84:80
         let parts: Vec<&str> = line.split_whitespace().collect();
@@ -53,33 +115,25 @@ impl NftablesAdapter {
         let mut i = 0;
         while i < parts.len() {
             match parts[i] {
-                "-p" => {
-                    if i + 1 < parts.len() {
-                        protocol = parts[i + 1].to_string();
-                    }
+                "-p" if i + 1 < parts.len() => {
+                    protocol = parts[i + 1].to_string();
                 }
-                "--dport" => {
-                    if i + 1 < parts.len() {
-                        external_port = parts[i + 1].parse().ok();
-                    }
+                "--dport" if i + 1 < parts.len() => {
+                    external_port = parts[i + 1].parse().ok();
                 }
-                "--to-destination" => {
-                    if i + 1 < parts.len() {
-                        let dest = parts[i + 1];
-                        let dest_parts: Vec<&str> = dest.split(':').collect();
-                        if dest_parts.len() >= 1 {
-                            internal_ip = dest_parts[0].to_string();
-                            internal_port = dest_parts
-                                .get(1)
-                                .and_then(|p| p.parse().ok())
-                                .unwrap_or(0);
-                        }
+                "--to-destination" if i + 1 < parts.len() => {
+                    let dest = parts[i + 1];
+                    let dest_parts: Vec<&str> = dest.split(':').collect();
+                    if !dest_parts.is_empty() {
+                        internal_ip = dest_parts[0].to_string();
+                        internal_port = dest_parts
+                            .get(1)
+                            .and_then(|p| p.parse().ok())
+                            .unwrap_or(0);
                     }
                 }
-                "--comment" => {
-                    if i + 1 < parts.len() {
-                        comment = Some(parts[i + 1].trim_matches('"').to_string());
-                    }
+                "--comment" if i + 1 < parts.len() => {
+                    comment = Some(parts[i + 1].trim_matches('"').to_string());
                 }
                 _ => {}
             }
@@ -106,14 +160,14 @@ impl NftablesAdapter {
             "iptables -t nat -A PREROUTING -p {} --dport {} -j DNAT --to-destination {}:{}",
             rule.protocol, rule.external_port, rule.internal_ip, rule.internal_port
         );
-        execute_shell(&dnat_cmd)?;
+        execute_mutation(&dnat_cmd)?;
 
         // Add FORWARD rule to allow the traffic
         let forward_cmd = format!(
             "iptables -A FORWARD -p {} -d {} --dport {} -j ACCEPT",
             rule.protocol, rule.internal_ip, rule.internal_port
         );
-        execute_shell(&forward_cmd)?;
+        execute_mutation(&forward_cmd)?;
 
         // Persist rules
         self.persist()?;
@@ -135,7 +189,7 @@ impl NftablesAdapter {
             // Parse line number (first field)
             if let Some(num_str) = line.split_whitespace().next() {
                 if let Ok(num) = num_str.parse::<u32>() {
-                    execute_shell(&format!("iptables -t nat -D PREROUTING {}", num))?;
+                    execute_mutation(&format!("iptables -t nat -D PREROUTING {}", num))?;
                 }
             }
         }
@@ -150,18 +204,38 @@ impl NftablesAdapter {
         for line in rules.lines().rev() {
             if let Some(num_str) = line.split_whitespace().next() {
                 if let Ok(num) = num_str.parse::<u32>() {
-                    execute_shell(&format!("iptables -D FORWARD {}", num))?;
+                    execute_mutation(&format!("iptables -D FORWARD {}", num))?;
                 }
             }
         }
 
+        // IPv6 pinholes for the port, deleted by their exact spec
+        let output = execute_shell("ip6tables -S FORWARD 2>/dev/null")?;
+        let rules = String::from_utf8_lossy(&output.stdout);
+        let pinholes: Vec<&str> = rules
+            .lines()
+            .filter(|line| {
+                parse_pinholes(line)
+                    .first()
+                    .is_some_and(|p| p.external_port == external_port)
+            })
+            .collect();
+        for line in &pinholes {
+            if let Some(spec) = line.strip_prefix("-A ") {
+                self.iptables(&format!("ip6tables -D {}", spec))?;
+            }
+        }
+
         self.persist()?;
+        if !pinholes.is_empty() {
+            self.persist_v6()?;
+        }
         Ok(())
     }
 
     /// Block an IP address
     pub fn block_ip(&self, target: &str) -> Result<()> {
-        execute_shell(&format!(
+        execute_mutation(&format!(
             "iptables -I INPUT -s {} -j DROP && iptables -I FORWARD -s {} -j DROP",
             target, target
         ))?;
@@ -169,9 +243,23 @@ impl NftablesAdapter {
         Ok(())
     }
 
+    /// Sources dropped by `fw block`, as iptables prints them (`1.2.3.4/32`)
+    pub fn list_blocks(&self) -> Result<Vec<String>> {
+        let output = execute_shell("iptables -S INPUT 2>/dev/null")?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                match line.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["-A", "INPUT", "-s", source, "-j", "DROP"] => Some(source.to_string()),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+
     /// Unblock an IP address
     pub fn unblock_ip(&self, target: &str) -> Result<()> {
-        execute_shell(&format!(
+        execute_mutation(&format!(
             "iptables -D INPUT -s {} -j DROP 2>/dev/null; iptables -D FORWARD -s {} -j DROP 2>/dev/null",
             target, target
         ))?;
@@ -179,10 +267,283 @@ impl NftablesAdapter {
         Ok(())
     }
 
-    /// Persist rules to file
-    fn persist(&self) -> Result<()> {
-        execute_shell(&format!("iptables-save > {}", IPTABLES_RULES))
-            .context("Failed to persist iptables rules")?;
+    /// Reject everything a device (by MAC) sends through the router, over
+    /// IPv4 and IPv6 (idempotent); the router itself stays reachable for
+    /// DHCP and DNS
+    pub fn quarantine(&self, mac: &str) -> Result<()> {
+        let rule = quarantine_rule(mac);
+        for tool in ["iptables", "ip6tables"] {
+            self.iptables(&format!(
+                "{tool} -C {rule} 2>/dev/null || {tool} -I {rule}",
+                tool = tool,
+                rule = rule
+            ))?;
+        }
+        self.persist()?;
+        self.persist_v6()
+    }
+
+    /// Lift a quarantine
+    pub fn release(&self, mac: &str) -> Result<()> {
+        // Either rule may be missing (quarantined before IPv6 was covered)
+        let rule = quarantine_rule(mac);
+        for tool in ["iptables", "ip6tables"] {
+            self.iptables(&format!(
+                "! {tool} -C {rule} 2>/dev/null || {tool} -D {rule}",
+                tool = tool,
+                rule = rule
+            ))?;
+        }
+        self.persist()?;
+        self.persist_v6()
+    }
+
+    /// MACs (lowercase) of devices quarantined over IPv4 or IPv6
+    pub fn list_quarantined(&self) -> Result<Vec<String>> {
+        let output =
+            execute_shell("iptables -S FORWARD 2>/dev/null; ip6tables -S FORWARD 2>/dev/null")?;
+        let mut macs = parse_quarantined(&String::from_utf8_lossy(&output.stdout));
+        macs.sort();
+        macs.dedup();
+        Ok(macs)
+    }
+
+    fn iptables(&self, cmd: &str) -> Result<()> {
+        let output = execute_mutation(cmd)?;
+        if !output.status.success() {
+            return Err(ShannonError::CommandFailed {
+                command: cmd.to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            }
+            .into());
+        }
         Ok(())
     }
+
+    /// Current live ruleset in `iptables-save` format
+    ///
+    /// The "# Generated by" timestamp comments are dropped to keep diffs clean.
+    pub fn current_ruleset(&self) -> Result<String> {
+        live_ruleset("iptables")
+    }
+
+    /// Current live IPv6 ruleset in `ip6tables-save` format
+    pub fn current_ruleset_v6(&self) -> Result<String> {
+        live_ruleset("ip6tables")
+    }
+
+    /// Replace the live ruleset and persist it
+    ///
+    /// iptables-restore applies all or nothing; if it rejects the rules, the
+    /// saved ruleset is put back too.
+    pub fn restore_ruleset(&self, rules: &str) -> Result<()> {
+        restore_rules("iptables", IPTABLES_RULES, rules)
+    }
+
+    /// Replace the live IPv6 ruleset and persist it, like `restore_ruleset`
+    pub fn restore_ruleset_v6(&self, rules: &str) -> Result<()> {
+        restore_rules("ip6tables", IP6TABLES_RULES, rules)
+    }
+
+    /// Persist the IPv6 rules to file
+    pub fn persist_v6(&self) -> Result<()> {
+        let output = execute_shell("ip6tables-save").context("Failed to read ip6tables rules")?;
+        let rules = persistent_rules(&String::from_utf8_lossy(&output.stdout));
+        write_file(IP6TABLES_RULES, &rules).context("Failed to persist ip6tables rules")
+    }
+
+    /// Persist rules to file
+    ///
+    /// Written through `write_file` so the ruleset diff is journaled.
+    pub fn persist(&self) -> Result<()> {
+        let rules = persistent_rules(&self.current_ruleset()?);
+        write_file(IPTABLES_RULES, &rules).context("Failed to persist iptables rules")
+    }
+}
+
+/// ip6tables commands still needed for a default-deny FORWARD chain, given
+/// its `ip6tables -S FORWARD` listing
+fn ipv6_guard_commands(rules: &str) -> Vec<String> {
+    let policy_drop = rules
+        .lines()
+        .any(|line| matches!(line.trim(), "-P FORWARD DROP" | "-P FORWARD REJECT"));
+    let has_replies = rules.lines().any(|line| {
+        line.starts_with("-A FORWARD")
+            && line.contains("ESTABLISHED")
+            && line.contains("RELATED")
+            && line.ends_with("-j ACCEPT")
+            && !line.contains(" -i ")
+            && !line.contains(" -s ")
+            && !line.contains(" -d ")
+    });
+
+    let mut commands = Vec::new();
+    if !has_replies {
+        commands.push(format!(
+            "ip6tables -A FORWARD -m conntrack --ctstate RELATED,ESTABLISHED \
+             -m comment --comment {} -j ACCEPT",
+            V6_GUARD_COMMENT
+        ));
+    }
+    if !policy_drop {
+        // The LAN keeps its outbound IPv6 once the policy is DROP
+        commands.push(format!(
+            "ip6tables -A FORWARD ! -i {wan} -o {wan} -m comment --comment {comment} -j ACCEPT",
+            wan = WAN_INTERFACE,
+            comment = V6_GUARD_COMMENT
+        ));
+        commands.push("ip6tables -P FORWARD DROP".to_string());
+    }
+    commands
+}
+
+/// `<tool>-save` output without its timestamp comments
+fn live_ruleset(tool: &str) -> Result<String> {
+    let output = execute_shell(&format!("{}-save", tool))
+        .with_context(|| format!("Failed to read {} rules", tool))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+fn restore_rules(tool: &str, path: &str, rules: &str) -> Result<()> {
+    let saved = read_file(path).unwrap_or_default();
+    write_file(path, rules).with_context(|| format!("Failed to write {} rules", tool))?;
+    let output = execute_mutation(&format!("{}-restore < {}", tool, path))?;
+    if !output.status.success() {
+        write_file(path, &saved).with_context(|| format!("Failed to put back {} rules", tool))?;
+        return Err(ShannonError::command_failed(format!("{}-restore", tool), &output).into());
+    }
+    Ok(())
+}
+
+/// An `iptables-save` ruleset without comments and runtime-only rules
+fn persistent_rules(ruleset: &str) -> String {
+    ruleset
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.contains(RUNTIME_COMMENT))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+fn quarantine_rule(mac: &str) -> String {
+    format!(
+        "FORWARD -m mac --mac-source {} -m comment --comment {} -j REJECT",
+        mac, QUARANTINE_COMMENT
+    )
+}
+
+/// `ip6tables -S FORWARD` lines carrying the pinhole comment
+fn parse_pinholes(rules: &str) -> Vec<PortForward> {
+    rules
+        .lines()
+        .filter(|line| line.contains(PINHOLE_COMMENT))
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let after = |flag: &str| {
+                let i = parts.iter().position(|p| *p == flag)?;
+                parts.get(i + 1).copied()
+            };
+            let port: u16 = after("--dport")?.parse().ok()?;
+            Some(PortForward {
+                external_port: port,
+                internal_ip: after("-d")?.trim_end_matches("/128").to_string(),
+                internal_port: port,
+                protocol: after("-p")?.to_string(),
+                comment: Some(PINHOLE_COMMENT.to_string()),
+            })
+        })
+        .collect()
+}
+
+fn parse_quarantined(rules: &str) -> Vec<String> {
+    rules
+        .lines()
+        .filter(|line| line.contains(QUARANTINE_COMMENT))
+        .filter_map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let i = parts.iter().position(|p| *p == "--mac-source")?;
+            Some(parts.get(i + 1)?.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quarantined() {
+        let rules = "-P FORWARD DROP\n\
+            -A FORWARD -m mac --mac-source DA:A1:19:00:00:01 -m comment --comment shannon-quarantine -j REJECT --reject-with icmp-port-unreachable\n\
+            -A FORWARD -i wg0 -j ACCEPT\n";
+        assert_eq!(parse_quarantined(rules), vec!["da:a1:19:00:00:01"]);
+    }
+
+    #[test]
+    fn test_quarantine_rule_fits_ip6tables() {
+        // The same spec is installed with both tools, so it must not use
+        // IPv4-only matches or reject types
+        let rule = quarantine_rule("da:a1:19:00:00:01");
+        assert!(!rule.contains("icmp-"));
+        assert!(!rule.contains(" -s ") && !rule.contains(" -d "));
+    }
+
+    #[test]
+    fn test_parse_pinholes() {
+        let rules = "-P FORWARD DROP\n\
+            -A FORWARD -d 2001:db8:1200:1::10/128 -i enxc84d4421f975 -p tcp -m tcp --dport 443 -m comment --comment shannon-pinhole -j ACCEPT\n";
+        let pinholes = parse_pinholes(rules);
+        assert_eq!(pinholes.len(), 1);
+        assert_eq!(pinholes[0].internal_ip, "2001:db8:1200:1::10");
+        assert_eq!(
+            (pinholes[0].external_port, pinholes[0].internal_port),
+            (443, 443)
+        );
+        assert_eq!(pinholes[0].protocol, "tcp");
+    }
+
+    #[test]
+    fn test_persistent_rules_skip_runtime_rules() {
+        let ruleset = "# Generated by iptables-save\n\
+            *nat\n\
+            -A POSTROUTING -o wgc0 -m comment --comment shannon-runtime -j MASQUERADE\n\
+            -A POSTROUTING -o enxc84d4421f975 -j MASQUERADE\n\
+            COMMIT\n";
+        assert_eq!(
+            persistent_rules(ruleset),
+            "*nat\n-A POSTROUTING -o enxc84d4421f975 -j MASQUERADE\nCOMMIT\n"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_guard_on_stock_policy() {
+        let commands = ipv6_guard_commands("-P FORWARD ACCEPT\n");
+        assert_eq!(commands.len(), 3);
+        assert!(commands[0].contains("--ctstate RELATED,ESTABLISHED"));
+        assert!(commands[1].contains("! -i enxc84d4421f975 -o enxc84d4421f975"));
+        // The policy is switched last, once replies and outbound are accepted
+        assert_eq!(commands[2], "ip6tables -P FORWARD DROP");
+    }
+
+    #[test]
+    fn test_ipv6_guard_already_in_place() {
+        let rules = "-P FORWARD DROP\n\
+            -A FORWARD -m conntrack --ctstate RELATED,ESTABLISHED -m comment --comment shannon-v6-guard -j ACCEPT\n\
+            -A FORWARD ! -i enxc84d4421f975 -o enxc84d4421f975 -m comment --comment shannon-v6-guard -j ACCEPT\n";
+        assert!(ipv6_guard_commands(rules).is_empty());
+    }
+
+    #[test]
+    fn test_ipv6_guard_adds_missing_reply_rule() {
+        // A DROP policy with only a narrow stateful rule still needs the
+        // general one; the admin's own outbound rules are left alone
+        let rules = "-P FORWARD DROP\n\
+            -A FORWARD -i wg0 -m state --state RELATED,ESTABLISHED -j ACCEPT\n";
+        let commands = ipv6_guard_commands(rules);
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].contains("--ctstate RELATED,ESTABLISHED"));
+    }
 }
@@ -0,0 +1,174 @@
+//! DNS queries against one specific server, via `dig` on the router
+//!
+//! Queries run where SHANNON's resolvers are reachable (127.0.0.1, site
+//! peers), so `dig` (package `bind9-dnsutils`) must be installed there. DoH
+//! and DoT need dig 9.18 or later; DoQ and DNSCrypt can't be queried.
+
+use anyhow::Result;
+use std::net::SocketAddr;
+
+use crate::error::ShannonError;
+use crate::location::execute_shell;
+
+/// What one server answered
+#[derive(Debug, Default)]
+pub struct DigAnswer {
+    /// Response code, e.g. NOERROR or NXDOMAIN
+    pub status: String,
+    /// Answer section as `TYPE data`, e.g. `A 192.168.4.10`
+    pub records: Vec<String>,
+    pub latency_ms: Option<u64>,
+}
+
+/// Fail early when dig is missing, instead of once per server
+pub fn ensure_dig() -> Result<()> {
+    let output = execute_shell("command -v dig")?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: "dig".to_string(),
+            stderr: "dig not found on SHANNON (apt install bind9-dnsutils)".to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// dig server arguments for an AdGuard upstream; None for protocols dig
+/// can't speak (DoQ, DoH3, DNSCrypt)
+pub fn dig_server(upstream: &str) -> Option<String> {
+    let (scheme, rest) = upstream.split_once("://").unwrap_or(("udp", upstream));
+    let (authority, path) = match rest.split_once('/') {
+        Some((authority, path)) => (authority, format!("/{}", path)),
+        None => (rest, "/dns-query".to_string()),
+    };
+    let (flags, default_port) = match scheme {
+        "udp" => (String::new(), 53),
+        "tcp" => ("+tcp ".to_string(), 53),
+        "tls" => ("+tls ".to_string(), 853),
+        "https" => (format!("+https={} ", path), 443),
+        _ => return None,
+    };
+    let (host, port) = match authority.parse::<SocketAddr>() {
+        Ok(addr) => (addr.ip().to_string(), addr.port()),
+        Err(_) => match authority.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => (host.to_string(), port.parse().ok()?),
+            _ => (authority.trim_matches(['[', ']']).to_string(), default_port),
+        },
+    };
+    Some(format!("{}@{} -p {}", flags, host, port))
+}
+
+/// Reject names that aren't plain hostnames, before they reach a shell
+pub fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(ShannonError::InvalidInput(format!("Invalid name: {}", name)).into());
+    }
+    Ok(())
+}
+
+/// Ask one server (as returned by `dig_server`, or `@ip -p port`) for a
+/// record; errors are timeouts and refused connections
+pub fn query(server: &str, name: &str, qtype: &str) -> Result<DigAnswer> {
+    check_name(name)?;
+    let command = format!(
+        "dig +tries=1 +time=3 +noall +comments +answer +stats {} {} {}",
+        server, name, qtype
+    );
+    let output = execute_shell(&format!("{} 2>&1", command))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        let reason = text
+            .lines()
+            .find(|line| line.starts_with(";;") && !line.contains("Query time"))
+            .unwrap_or("no answer")
+            .trim_start_matches(";; ")
+            .to_string();
+        return Err(ShannonError::CommandFailed {
+            command,
+            stderr: reason,
+        }
+        .into());
+    }
+    Ok(parse_dig(&text))
+}
+
+fn parse_dig(text: &str) -> DigAnswer {
+    let mut answer = DigAnswer::default();
+    for line in text.lines() {
+        if let Some((_, rest)) = line.split_once("status: ") {
+            answer.status = rest.split(',').next().unwrap_or_default().to_string();
+        } else if let Some(rest) = line.strip_prefix(";; Query time: ") {
+            answer.latency_ms = rest
+                .split_whitespace()
+                .next()
+                .and_then(|ms| ms.parse().ok());
+        } else if !line.starts_with(';') && !line.trim().is_empty() {
+            // name TTL class type data...
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() >= 5 {
+                answer
+                    .records
+                    .push(format!("{} {}", fields[3], fields[4..].join(" ")));
+            }
+        }
+    }
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dig_server() {
+        assert_eq!(dig_server("9.9.9.9").as_deref(), Some("@9.9.9.9 -p 53"));
+        assert_eq!(
+            dig_server("10.0.0.53:5353").as_deref(),
+            Some("@10.0.0.53 -p 5353")
+        );
+        assert_eq!(
+            dig_server("tls://one.one.one.one").as_deref(),
+            Some("+tls @one.one.one.one -p 853")
+        );
+        assert_eq!(
+            dig_server("https://dns.quad9.net/dns-query").as_deref(),
+            Some("+https=/dns-query @dns.quad9.net -p 443")
+        );
+        assert_eq!(
+            dig_server("[2620:fe::fe]:53").as_deref(),
+            Some("@2620:fe::fe -p 53")
+        );
+        assert_eq!(dig_server("quic://dns.adguard-dns.com"), None);
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("www.example.com").is_ok());
+        assert!(check_name("_sip._tcp.example.com").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("example.com; reboot").is_err());
+        assert!(check_name("x'$(id)'").is_err());
+        assert!(check_name("a&qtype=TXT").is_err());
+    }
+
+    #[test]
+    fn test_parse_dig() {
+        let text = ";; Got answer:\n\
+            ;; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: 4242\n\
+            ;; flags: qr rd ra; QUERY: 1, ANSWER: 2, AUTHORITY: 0, ADDITIONAL: 1\n\
+            www.example.com.\t300\tIN\tCNAME\texample.com.\n\
+            example.com.\t300\tIN\tA\t93.184.215.14\n\
+            ;; Query time: 23 msec\n";
+        let answer = parse_dig(text);
+        assert_eq!(answer.status, "NOERROR");
+        assert_eq!(
+            answer.records,
+            vec!["CNAME example.com.", "A 93.184.215.14"]
+        );
+        assert_eq!(answer.latency_ms, Some(23));
+    }
+}
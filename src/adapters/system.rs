@@ -1,9 +1,15 @@
-//! System metrics and service status
+//! System metrics, service status and host lookups
 
 use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::net::IpAddr;
 
 use crate::location::execute_shell;
 
+/// USB ethernet adapter facing the ISP
+pub const WAN_INTERFACE: &str = "enxc84d4421f975";
+
 #[derive(Debug)]
 pub struct SystemMetrics {
     pub uptime: String,
@@ -53,6 +59,72 @@ pub fn get_wan_ip() -> Result<String> {
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
+/// IPv6 on the WAN side and what was delegated for the LAN
+#[derive(Debug, Serialize, JsonSchema, Default)]
+pub struct Ipv6Status {
+    /// Stable global address on the WAN interface (temporary and deprecated
+    /// ones skipped)
+    pub wan_address: Option<String>,
+    /// Default route via the WAN, learned from the ISP's router advertisements
+    pub default_route: bool,
+    /// Prefixes delegated by the ISP (DHCPv6-PD), from the unreachable
+    /// route the DHCPv6 client installs for the whole delegation
+    pub delegated_prefixes: Vec<String>,
+    /// Global /64s on LAN-side interfaces, announced to clients
+    pub lan_prefixes: Vec<String>,
+}
+
+pub fn ipv6_status() -> Result<Ipv6Status> {
+    let addresses = execute_shell("ip -6 -o addr show scope global 2>/dev/null")?;
+    let routes = execute_shell("ip -6 route show 2>/dev/null")?;
+    Ok(parse_ipv6_status(
+        &String::from_utf8_lossy(&addresses.stdout),
+        &String::from_utf8_lossy(&routes.stdout),
+    ))
+}
+
+fn parse_ipv6_status(addresses: &str, routes: &str) -> Ipv6Status {
+    let mut status = Ipv6Status::default();
+
+    // 2: eth0    inet6 2001:db8:1:2::1/64 scope global dynamic mngtmpaddr ...
+    for line in addresses.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (Some(dev), Some(address)) = (parts.get(1), parts.get(3)) else {
+            continue;
+        };
+        if parts
+            .iter()
+            .any(|p| matches!(*p, "temporary" | "deprecated" | "tentative"))
+        {
+            continue;
+        }
+        if *dev == WAN_INTERFACE {
+            if status.wan_address.is_none() {
+                status.wan_address = address.split('/').next().map(str::to_string);
+            }
+        } else if !dev.starts_with("wg") {
+            if let Ok(net) = address.parse::<ipnetwork::Ipv6Network>() {
+                let prefix = format!("{}/{}", net.network(), net.prefix());
+                if !status.lan_prefixes.contains(&prefix) {
+                    status.lan_prefixes.push(prefix);
+                }
+            }
+        }
+    }
+
+    for line in routes.lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts[..] {
+            ["default", ..] if parts.contains(&WAN_INTERFACE) => status.default_route = true,
+            ["unreachable", prefix, ..] if parts.contains(&"dhcp") => {
+                status.delegated_prefixes.push(prefix.to_string())
+            }
+            _ => {}
+        }
+    }
+    status
+}
+
 /// Check if a systemd service is active
 pub fn get_service_status(service: &str) -> Result<bool> {
     // nftables is a oneshot service — it loads rules and exits.
@@ -69,3 +141,101 @@ pub fn get_service_status(service: &str) -> Result<bool> {
     let output = execute_shell(&format!("systemctl is-active {} 2>/dev/null", service))?;
     Ok(output.status.success())
 }
+
+/// Names the resolver returns for an address (dnsmasq leases, /etc/hosts, PTR)
+pub fn reverse_dns(ip: IpAddr) -> Result<Vec<String>> {
+    let output = execute_shell(&format!("getent hosts {} 2>/dev/null", ip))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .map(str::to_string)
+        .collect())
+}
+
+/// sshd authentication attempts from one address
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct SshActivity {
+    pub failed: u32,
+    pub accepted: u32,
+    /// Usernames tried, in the order first seen
+    pub users: Vec<String>,
+    /// Journal timestamp of the most recent attempt
+    pub last_seen: Option<String>,
+}
+
+/// sshd log lines mentioning `ip` over the last `days` days
+pub fn ssh_activity(ip: IpAddr, days: u32) -> Result<SshActivity> {
+    let output = execute_shell(&format!(
+        "journalctl -u ssh -u sshd --since '-{}d' --no-pager -o short-iso 2>/dev/null \
+         | grep -F 'from {} port'",
+        days, ip
+    ))?;
+    Ok(parse_ssh_log(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_ssh_log(text: &str) -> SshActivity {
+    let mut activity = SshActivity::default();
+    for line in text.lines() {
+        let message = line.split_once(": ").map_or(line, |(_, message)| message);
+        let user = if let Some(rest) = message.strip_prefix("Accepted ") {
+            activity.accepted += 1;
+            rest.split_once(" for ").map(|(_, rest)| rest)
+        } else if let Some(rest) = message.strip_prefix("Failed ") {
+            activity.failed += 1;
+            rest.split_once(" for ")
+                .map(|(_, rest)| rest.trim_start_matches("invalid user "))
+        } else if let Some(rest) = message.strip_prefix("Invalid user ") {
+            Some(rest)
+        } else {
+            continue;
+        };
+
+        if let Some(user) = user
+            .and_then(|u| u.split(" from ").next())
+            .filter(|u| !u.is_empty())
+        {
+            if !activity.users.iter().any(|u| u == user) {
+                activity.users.push(user.to_string());
+            }
+        }
+        activity.last_seen = line.split_whitespace().next().map(str::to_string);
+    }
+    activity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ipv6_status() {
+        let addresses = "3: enxc84d4421f975    inet6 2001:db8:ff::23/128 scope global dynamic noprefixroute \\       valid_lft 6000sec preferred_lft 3000sec\n\
+            2: eth0    inet6 2001:db8:1200:1:5054:ff:fe12:3456/64 scope global temporary dynamic \\       valid_lft 6000sec preferred_lft 3000sec\n\
+            2: eth0    inet6 2001:db8:1200:1::1/64 scope global \\       valid_lft forever preferred_lft forever\n\
+            5: wg0    inet6 fd00:8::1/64 scope global \\       valid_lft forever preferred_lft forever\n";
+        let routes = "unreachable 2001:db8:1200::/56 dev lo proto dhcp metric 1024 pref medium\n\
+            2001:db8:1200:1::/64 dev eth0 proto kernel metric 256 pref medium\n\
+            default via fe80::1 dev enxc84d4421f975 proto ra metric 1024 expires 1790sec hoplimit 64 pref medium\n";
+        let status = parse_ipv6_status(addresses, routes);
+        assert_eq!(status.wan_address.as_deref(), Some("2001:db8:ff::23"));
+        assert!(status.default_route);
+        assert_eq!(status.delegated_prefixes, vec!["2001:db8:1200::/56"]);
+        assert_eq!(status.lan_prefixes, vec!["2001:db8:1200:1::/64"]);
+    }
+
+    #[test]
+    fn test_parse_ssh_log() {
+        let log = "2026-10-18T09:00:01+0200 shannon sshd[812]: Invalid user admin from 203.0.113.9 port 40122\n\
+            2026-10-18T09:00:03+0200 shannon sshd[812]: Failed password for invalid user admin from 203.0.113.9 port 40122 ssh2\n\
+            2026-10-18T09:00:09+0200 shannon sshd[815]: Failed password for root from 203.0.113.9 port 40180 ssh2\n\
+            2026-10-18T09:05:00+0200 shannon sshd[901]: Accepted publickey for roy from 203.0.113.9 port 51000 ssh2: ED25519 SHA256:abc\n";
+        let activity = parse_ssh_log(log);
+        assert_eq!(activity.failed, 2);
+        assert_eq!(activity.accepted, 1);
+        assert_eq!(activity.users, vec!["admin", "root", "roy"]);
+        assert_eq!(
+            activity.last_seen.as_deref(),
+            Some("2026-10-18T09:05:00+0200")
+        );
+    }
+}
@@ -0,0 +1,427 @@
+//! Structural model of wg-quick config files (`/etc/wireguard/wg0.conf`)
+//!
+//! Comments, blank lines and key order are kept, so rewriting a file only
+//! changes the sections that were edited. A peer's name is the `# name`
+//! comment above its `[Peer]` header, which is what
+//! `wireguard::resolve_peer_names` reads.
+//!
+//! Disabled peers stay in the file with their header and entries commented
+//! out as `#! [Peer]` / `#! Key = Value`; wg-quick ignores them.
+
+use anyhow::Result;
+use ipnetwork::Ipv4Network;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::Ipv4Addr;
+
+use crate::error::ShannonError;
+use crate::location::{read_file, write_file};
+
+/// Path of an interface's config file
+pub fn config_path(interface: &str) -> String {
+    format!("/etc/wireguard/{}.conf", interface)
+}
+
+/// Read and parse an interface's config file
+pub fn load(interface: &str) -> Result<WgConfig> {
+    WgConfig::parse(&read_file(&config_path(interface))?)
+}
+
+/// Write an interface's config file (does not apply it)
+pub fn save(interface: &str, config: &WgConfig) -> Result<()> {
+    write_file(&config_path(interface), &config.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionKind {
+    Interface,
+    Peer,
+}
+
+/// A line inside a section
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    /// `Key = Value`
+    Entry { key: String, value: String },
+    /// Comment or blank line, kept verbatim
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    /// Comment and blank lines directly above the header
+    pub leading: Vec<String>,
+    pub kind: SectionKind,
+    /// Commented out with `#!`
+    pub disabled: bool,
+    pub lines: Vec<Line>,
+}
+
+impl Section {
+    /// A new `[Peer]` section named by a `# name` comment
+    pub fn peer(name: &str, entries: &[(&str, &str)]) -> Self {
+        Section {
+            leading: vec![String::new(), format!("# {}", name)],
+            kind: SectionKind::Peer,
+            disabled: false,
+            lines: entries
+                .iter()
+                .map(|(key, value)| Line::Entry {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Value of a key (keys are case-insensitive, as in wg-quick)
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { key: k, value } if k.eq_ignore_ascii_case(key) => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Set a key, replacing its first occurrence or appending it
+    pub fn set(&mut self, key: &str, value: &str) {
+        for line in &mut self.lines {
+            if let Line::Entry { key: k, value: v } = line {
+                if k.eq_ignore_ascii_case(key) {
+                    *v = value.to_string();
+                    return;
+                }
+            }
+        }
+        self.push(key, value);
+    }
+
+    /// Remove every occurrence of a key, returning the removed values
+    pub fn remove(&mut self, key: &str) -> Vec<String> {
+        let mut removed = Vec::new();
+        self.lines.retain(|line| match line {
+            Line::Entry { key: k, value } if k.eq_ignore_ascii_case(key) => {
+                removed.push(value.clone());
+                false
+            }
+            _ => true,
+        });
+        removed
+    }
+
+    /// Append an entry, keeping existing ones with the same key
+    pub fn push(&mut self, key: &str, value: &str) {
+        self.lines.push(Line::Entry {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    /// Peer name: the last comment above the header, or failing that a
+    /// comment inside the section before `PublicKey`
+    pub fn name(&self) -> Option<String> {
+        let comment = |line: &str| {
+            let text = line.trim().strip_prefix('#')?;
+            let text = text.trim();
+            (!text.is_empty() && !text.starts_with('!')).then(|| text.to_string())
+        };
+
+        if let Some(name) = self.leading.iter().rev().find_map(|l| comment(l)) {
+            return Some(name);
+        }
+        self.lines
+            .iter()
+            .take_while(
+                |l| !matches!(l, Line::Entry { key, .. } if key.eq_ignore_ascii_case("PublicKey")),
+            )
+            .find_map(|l| match l {
+                Line::Other(text) => comment(text),
+                Line::Entry { .. } => None,
+            })
+    }
+
+    /// Rename the peer, rewriting the comment its name came from
+    pub fn set_name(&mut self, name: &str) {
+        let current = self.name();
+        let is_name =
+            |line: &str| current.as_deref() == line.trim().strip_prefix('#').map(str::trim);
+
+        if let Some(line) = self.leading.iter_mut().rev().find(|l| is_name(l)) {
+            *line = format!("# {}", name);
+            return;
+        }
+        for line in &mut self.lines {
+            if let Line::Other(text) = line {
+                if current.is_some() && is_name(text) {
+                    *text = format!("# {}", name);
+                    return;
+                }
+            }
+        }
+        self.leading.push(format!("# {}", name));
+    }
+
+    /// IPv4 addresses in `Address` (interface) or `AllowedIPs` (peer)
+    pub fn addresses(&self) -> Vec<Ipv4Network> {
+        let key = match self.kind {
+            SectionKind::Interface => "Address",
+            SectionKind::Peer => "AllowedIPs",
+        };
+        self.get(key)
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|a| a.trim().parse().ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WgConfig {
+    pub sections: Vec<Section>,
+    /// Comment and blank lines after the last entry
+    pub trailing: Vec<String>,
+}
+
+impl WgConfig {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = WgConfig::default();
+        let mut pending: Vec<String> = Vec::new();
+
+        for (number, raw) in text.lines().enumerate() {
+            let (line, disabled) = match raw.trim().strip_prefix("#!") {
+                Some(rest) => (rest.trim(), true),
+                None => (raw.trim(), false),
+            };
+
+            if line.is_empty() || line.starts_with('#') {
+                pending.push(raw.to_string());
+            } else if line.starts_with('[') {
+                let kind = match line.to_ascii_lowercase().as_str() {
+                    "[interface]" => SectionKind::Interface,
+                    "[peer]" => SectionKind::Peer,
+                    _ => {
+                        return Err(ShannonError::InvalidInput(format!(
+                            "Unknown section {} on line {}",
+                            line,
+                            number + 1
+                        ))
+                        .into())
+                    }
+                };
+                config.sections.push(Section {
+                    leading: std::mem::take(&mut pending),
+                    kind,
+                    disabled,
+                    lines: Vec::new(),
+                });
+            } else if config
+                .sections
+                .last()
+                .is_some_and(|s| s.disabled != disabled)
+            {
+                // Not part of the section it's in (e.g. a `#!` remark)
+                pending.push(raw.to_string());
+            } else {
+                let (Some(section), Some((key, value))) =
+                    (config.sections.last_mut(), line.split_once('='))
+                else {
+                    return Err(ShannonError::InvalidInput(format!(
+                        "Unexpected line {}: {}",
+                        number + 1,
+                        line
+                    ))
+                    .into());
+                };
+                section.lines.extend(pending.drain(..).map(Line::Other));
+                section.lines.push(Line::Entry {
+                    key: key.trim().to_string(),
+                    value: value.trim().to_string(),
+                });
+            }
+        }
+
+        config.trailing = pending;
+        Ok(config)
+    }
+
+    pub fn interface(&self) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|s| s.kind == SectionKind::Interface)
+    }
+
+    /// Index of the peer with this name or public key
+    pub fn find_peer(&self, name_or_key: &str) -> Option<usize> {
+        self.sections.iter().position(|s| {
+            s.kind == SectionKind::Peer
+                && (s.name().as_deref() == Some(name_or_key)
+                    || s.get("PublicKey") == Some(name_or_key))
+        })
+    }
+
+    pub fn add_peer(&mut self, peer: Section) {
+        self.sections.push(peer);
+    }
+
+    /// Remove a peer together with its name comment
+    pub fn remove_peer(&mut self, index: usize) -> Section {
+        self.sections.remove(index)
+    }
+
+    /// Public key → name of every named peer
+    pub fn peer_names(&self) -> HashMap<String, String> {
+        self.sections
+            .iter()
+            .filter(|s| s.kind == SectionKind::Peer)
+            .filter_map(|s| Some((s.get("PublicKey")?.to_string(), s.name()?)))
+            .collect()
+    }
+
+    /// A client tunnel to a VPN provider rather than a server: wg-quick's
+    /// routing turned off, or a peer that takes the default route
+    pub fn is_client(&self) -> bool {
+        let table_off = self
+            .interface()
+            .and_then(|s| s.get("Table"))
+            .is_some_and(|table| table.eq_ignore_ascii_case("off"));
+        let default_route = self
+            .sections
+            .iter()
+            .filter(|s| s.kind == SectionKind::Peer && !s.disabled)
+            .filter_map(|s| s.get("AllowedIPs"))
+            .flat_map(|ips| ips.split(','))
+            .any(|net| matches!(net.trim(), "0.0.0.0/0" | "::/0"));
+        table_off || default_route
+    }
+
+    /// The tunnel subnet, from the interface's first IPv4 `Address`
+    pub fn subnet(&self) -> Option<Ipv4Network> {
+        self.interface()?.addresses().into_iter().next()
+    }
+
+    /// Lowest host address in the subnet not used by the interface or a peer
+    pub fn next_free_address(&self) -> Option<Ipv4Addr> {
+        let subnet = self.subnet()?;
+        let used: Vec<Ipv4Addr> = self
+            .sections
+            .iter()
+            .flat_map(|s| s.addresses())
+            .filter(|net| net.prefix() == 32 || net.prefix() == subnet.prefix())
+            .map(|net| net.ip())
+            .collect();
+
+        subnet
+            .iter()
+            .filter(|ip| *ip != subnet.network() && *ip != subnet.broadcast())
+            .find(|ip| !used.contains(ip))
+    }
+}
+
+impl Display for WgConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for section in &self.sections {
+            for line in &section.leading {
+                writeln!(f, "{}", line)?;
+            }
+            let prefix = if section.disabled { "#! " } else { "" };
+            match section.kind {
+                SectionKind::Interface => writeln!(f, "{}[Interface]", prefix)?,
+                SectionKind::Peer => writeln!(f, "{}[Peer]", prefix)?,
+            }
+            for line in &section.lines {
+                match line {
+                    Line::Entry { key, value } => writeln!(f, "{}{} = {}", prefix, key, value)?,
+                    Line::Other(text) => writeln!(f, "{}", text)?,
+                }
+            }
+        }
+        for line in &self.trailing {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+[Interface]
+Address = 10.8.0.1/24
+ListenPort = 51820
+PrivateKey = c2VydmVy
+PostUp = iptables -A FORWARD -i wg0 -j ACCEPT
+
+# iPhone
+[Peer]
+PublicKey = aVBob25l
+AllowedIPs = 10.8.0.2/32
+
+[Peer]
+# laptop
+PublicKey = bGFwdG9w
+AllowedIPs = 10.8.0.4/32
+";
+
+    #[test]
+    fn test_roundtrip_preserves_text() {
+        let config = WgConfig::parse(SAMPLE).unwrap();
+        assert_eq!(config.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn test_peer_names_and_lookup() {
+        let config = WgConfig::parse(SAMPLE).unwrap();
+        let names = config.peer_names();
+        assert_eq!(names["aVBob25l"], "iPhone");
+        assert_eq!(names["bGFwdG9w"], "laptop");
+        assert_eq!(config.find_peer("laptop"), config.find_peer("bGFwdG9w"));
+        assert!(config.find_peer("tablet").is_none());
+    }
+
+    #[test]
+    fn test_is_client() {
+        assert!(!WgConfig::parse(SAMPLE).unwrap().is_client());
+
+        let provider = "[Interface]\nPrivateKey = a2V5\nAddress = 10.64.0.2/32\n\n\
+            [Peer]\nPublicKey = cHJvdmlkZXI=\nEndpoint = 198.51.100.1:51820\n\
+            AllowedIPs = 0.0.0.0/0, ::/0\n";
+        assert!(WgConfig::parse(provider).unwrap().is_client());
+
+        let imported = "[Interface]\nPrivateKey = a2V5\nTable = off\n\n\
+            [Peer]\nPublicKey = cHJvdmlkZXI=\nAllowedIPs = 10.64.0.1/32\n";
+        assert!(WgConfig::parse(imported).unwrap().is_client());
+    }
+
+    #[test]
+    fn test_next_free_address_skips_used() {
+        let mut config = WgConfig::parse(SAMPLE).unwrap();
+        assert_eq!(config.next_free_address(), Some(Ipv4Addr::new(10, 8, 0, 3)));
+
+        config.add_peer(Section::peer("tablet", &[("AllowedIPs", "10.8.0.3/32")]));
+        assert_eq!(config.next_free_address(), Some(Ipv4Addr::new(10, 8, 0, 5)));
+    }
+
+    #[test]
+    fn test_disable_and_rename_keep_the_rest() {
+        let mut config = WgConfig::parse(SAMPLE).unwrap();
+        let laptop = config.find_peer("laptop").unwrap();
+        config.sections[laptop].disabled = true;
+        let iphone = config.find_peer("iPhone").unwrap();
+        config.sections[iphone].set_name("phone");
+
+        let text = config.to_string();
+        assert!(text.contains("# phone\n[Peer]\nPublicKey = aVBob25l"));
+        assert!(text.contains("#! [Peer]\n# laptop\n#! PublicKey = bGFwdG9w"));
+        assert_eq!(text.lines().count(), SAMPLE.lines().count());
+
+        let reparsed = WgConfig::parse(&text).unwrap();
+        let laptop = reparsed.find_peer("laptop").unwrap();
+        assert!(reparsed.sections[laptop].disabled);
+        assert_eq!(
+            reparsed.sections[laptop].get("AllowedIPs"),
+            Some("10.8.0.4/32")
+        );
+        assert_eq!(reparsed.to_string(), text);
+    }
+}
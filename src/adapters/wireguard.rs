@@ -1,36 +1,57 @@
-//! WireGuard VPN adapter — wraps `wg show` output
+//! WireGuard VPN adapter — wraps `wg show` and `wg set`
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::fmt::Display;
 
-use crate::location::execute_shell;
+use crate::adapters::egress::EGRESS_INTERFACE;
+use crate::adapters::wgconf;
+use crate::error::ShannonError;
+use crate::location::{
+    execute_mutation, execute_mutation_with_input, execute_shell, execute_shell_with_input,
+};
+use crate::output::{format_bytes, format_span};
 
-#[derive(Debug, Serialize)]
+/// A peer counts as connected if it completed a handshake this recently;
+/// WireGuard re-handshakes every two minutes while traffic flows
+const CONNECTED_WITHIN_SECS: i64 = 180;
+
+/// Interface `shannon vpn` commands act on unless `--interface` is given
+pub const DEFAULT_INTERFACE: &str = "wg0";
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct WireguardStatus {
+    pub interface: String,
     pub interface_up: bool,
     pub listening_port: Option<u16>,
     pub public_key: String,
     pub peers: Vec<WireguardPeer>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct WireguardPeer {
     pub name: String,
     pub public_key: String,
+    /// Address the peer last connected from (ip:port)
+    pub endpoint: Option<String>,
     pub allowed_ips: String,
-    pub last_handshake: Option<String>,
-    pub transfer_rx: String,
-    pub transfer_tx: String,
+    pub last_handshake: Option<DateTime<Utc>>,
+    /// Bytes received from the peer
+    pub transfer_rx: u64,
+    /// Bytes sent to the peer
+    pub transfer_tx: u64,
+    /// Handshake within the last 3 minutes
     pub connected: bool,
 }
 
 impl Display for WireguardStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if !self.interface_up {
-            return writeln!(f, "  WireGuard: down");
+            return writeln!(f, "  WireGuard {}: down", self.interface);
         }
-        writeln!(f, "  WireGuard: up (port {})", self.listening_port.unwrap_or(0))?;
+        writeln!(f, "  WireGuard {}: up (port {})", self.interface, self.listening_port.unwrap_or(0))?;
         writeln!(f, "  Peers: {}/{} connected",
             self.peers.iter().filter(|p| p.connected).count(),
             self.peers.len()
@@ -43,20 +64,63 @@ impl Display for WireguardPeer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let indicator = if self.connected { "●" } else { "○" };
         writeln!(f, "{} {} ({})", indicator, self.name, self.allowed_ips)?;
-        if let Some(ref hs) = self.last_handshake {
-            writeln!(f, "    Last handshake: {}", hs)?;
+        if let Some(ref endpoint) = self.endpoint {
+            writeln!(f, "    Endpoint: {}", endpoint)?;
         }
-        writeln!(f, "    Transfer: {} rx / {} tx", self.transfer_rx, self.transfer_tx)?;
+        if let Some(hs) = self.last_handshake {
+            writeln!(f, "    Last handshake: {}", format_ago(Utc::now() - hs))?;
+        }
+        writeln!(f, "    Transfer: {} rx / {} tx",
+            format_bytes(self.transfer_rx),
+            format_bytes(self.transfer_tx)
+        )?;
         Ok(())
     }
 }
 
-/// Get WireGuard status by parsing `wg show wg0`
-pub fn get_status() -> Result<WireguardStatus> {
-    let output = execute_shell("wg show wg0 2>/dev/null")?;
+/// WireGuard interfaces: those with a config file plus any running ones
+pub fn interfaces() -> Result<Vec<String>> {
+    let output = execute_shell(
+        "(ls /etc/wireguard 2>/dev/null | sed -n 's/\\.conf$//p'; \
+         wg show interfaces 2>/dev/null | tr ' ' '\\n') | sort -u",
+    )?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether an interface is a client tunnel to a VPN provider (the egress
+/// tunnel, under whatever name it was imported as) rather than a server
+pub fn is_client(interface: &str) -> bool {
+    interface == EGRESS_INTERFACE || wgconf::load(interface).is_ok_and(|c| c.is_client())
+}
+
+/// Interfaces that peers connect to, i.e. all but client tunnels
+pub fn server_interfaces() -> Result<Vec<String>> {
+    Ok(interfaces()?
+        .into_iter()
+        .filter(|iface| !is_client(iface))
+        .collect())
+}
+
+/// Status of every server interface, in name order
+pub fn get_all_status() -> Result<Vec<WireguardStatus>> {
+    server_interfaces()?
+        .iter()
+        .map(|iface| get_status(iface))
+        .collect()
+}
+
+/// Get an interface's status from `wg show <interface> dump`
+pub fn get_status(interface: &str) -> Result<WireguardStatus> {
+    let output = execute_shell(&format!("wg show {} dump 2>/dev/null", interface))?;
 
     if !output.status.success() {
         return Ok(WireguardStatus {
+            interface: interface.to_string(),
             interface_up: false,
             listening_port: None,
             public_key: String::new(),
@@ -64,124 +128,159 @@ pub fn get_status() -> Result<WireguardStatus> {
         });
     }
 
-    let text = String::from_utf8_lossy(&output.stdout);
-    let mut public_key = String::new();
-    let mut listening_port = None;
-    let mut peers = Vec::new();
-    let mut current_peer: Option<PeerBuilder> = None;
-
-    for line in text.lines() {
-        let line = line.trim();
-
-        if line.starts_with("public key:") {
-            if current_peer.is_none() {
-                public_key = line.trim_start_matches("public key:").trim().to_string();
-            }
-        } else if line.starts_with("listening port:") {
-            listening_port = line.trim_start_matches("listening port:")
-                .trim()
-                .parse()
-                .ok();
-        } else if line.starts_with("peer:") {
-            if let Some(builder) = current_peer.take() {
-                peers.push(builder.build());
-            }
-            current_peer = Some(PeerBuilder {
-                public_key: line.trim_start_matches("peer:").trim().to_string(),
-                ..PeerBuilder::default()
-            });
-        } else if let Some(ref mut builder) = current_peer {
-            if line.starts_with("allowed ips:") {
-                builder.allowed_ips = line.trim_start_matches("allowed ips:").trim().to_string();
-            } else if line.starts_with("latest handshake:") {
-                builder.last_handshake = Some(
-                    line.trim_start_matches("latest handshake:").trim().to_string()
-                );
-            } else if line.starts_with("transfer:") {
-                let transfer = line.trim_start_matches("transfer:").trim();
-                if let Some((rx, tx)) = transfer.split_once("received,") {
-                    builder.transfer_rx = rx.trim().to_string();
-                    builder.transfer_tx = tx.trim_end_matches("sent").trim().to_string();
-                }
-            }
+    let mut status = parse_dump(interface, &String::from_utf8_lossy(&output.stdout), Utc::now());
+
+    // Resolve peer names from the config file's comments
+    let names = resolve_peer_names(interface);
+    for peer in &mut status.peers {
+        if let Some(name) = names.get(&peer.public_key) {
+            peer.name = name.clone();
         }
     }
 
-    if let Some(builder) = current_peer.take() {
-        peers.push(builder.build());
-    }
+    Ok(status)
+}
 
-    // Resolve peer names from wg0.conf comments
-    let names = resolve_peer_names();
-    for peer in &mut peers {
-        if let Some(name) = names.get(&peer.public_key) {
-            peer.name = name.clone();
+/// Parse `wg show <interface> dump`: a tab-separated interface line
+/// (private key, public key, port, fwmark), then one line per peer (public
+/// key, preshared key, endpoint, allowed ips, handshake, rx, tx, keepalive)
+fn parse_dump(interface_name: &str, text: &str, now: DateTime<Utc>) -> WireguardStatus {
+    let none = |field: &str| (field != "(none)" && !field.is_empty()).then(|| field.to_string());
+    let mut lines = text.lines();
+
+    let interface: Vec<&str> = lines.next().unwrap_or_default().split('\t').collect();
+    let mut peers = Vec::new();
+
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 8 {
+            continue;
         }
+        let last_handshake = fields[4]
+            .parse::<i64>()
+            .ok()
+            .filter(|&secs| secs > 0)
+            .and_then(|secs| DateTime::from_timestamp(secs, 0));
+
+        peers.push(WireguardPeer {
+            name: short_key(fields[0]),
+            public_key: fields[0].to_string(),
+            endpoint: none(fields[2]),
+            allowed_ips: none(fields[3]).unwrap_or_default().replace(',', ", "),
+            connected: last_handshake
+                .is_some_and(|hs| (now - hs).num_seconds() < CONNECTED_WITHIN_SECS),
+            last_handshake,
+            transfer_rx: fields[5].parse().unwrap_or(0),
+            transfer_tx: fields[6].parse().unwrap_or(0),
+        });
     }
 
-    Ok(WireguardStatus {
+    WireguardStatus {
+        interface: interface_name.to_string(),
         interface_up: true,
-        listening_port,
-        public_key,
+        listening_port: interface.get(2).and_then(|p| p.parse().ok()),
+        public_key: interface.get(1).map(|k| k.to_string()).unwrap_or_default(),
         peers,
-    })
-}
-
-#[derive(Default)]
-struct PeerBuilder {
-    public_key: String,
-    allowed_ips: String,
-    last_handshake: Option<String>,
-    transfer_rx: String,
-    transfer_tx: String,
-}
-
-impl PeerBuilder {
-    fn build(self) -> WireguardPeer {
-        let connected = self.last_handshake.is_some();
-        WireguardPeer {
-            name: short_key(&self.public_key),
-            public_key: self.public_key,
-            allowed_ips: self.allowed_ips,
-            last_handshake: self.last_handshake,
-            transfer_rx: if self.transfer_rx.is_empty() { "0 B".into() } else { self.transfer_rx },
-            transfer_tx: if self.transfer_tx.is_empty() { "0 B".into() } else { self.transfer_tx },
-            connected,
+    }
+}
+
+/// "45s ago", "3m ago", "2h 5m ago", "4d ago"
+fn format_ago(elapsed: chrono::Duration) -> String {
+    format!("{} ago", format_span(elapsed))
+}
+
+/// Apply the interface's config file to the running interface without
+/// disturbing peers whose settings didn't change
+pub fn sync_config(interface: &str) -> Result<()> {
+    let output = execute_mutation(&format!(
+        "wg-quick strip {iface} | wg syncconf {iface} /dev/stdin",
+        iface = interface
+    ))?;
+    if !output.status.success() {
+        return Err(
+            ShannonError::command_failed(format!("wg syncconf {}", interface), &output).into(),
+        );
+    }
+    Ok(())
+}
+
+/// Route a subnet into the interface. wg-quick adds routes for AllowedIPs
+/// only at `up`, so peers added to a running interface need this.
+pub fn add_route(interface: &str, subnet: &str) -> Result<()> {
+    let cmd = format!("ip route replace {} dev {}", subnet, interface);
+    let output = execute_mutation(&cmd)?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
         }
+        .into());
     }
+    Ok(())
 }
 
-/// Resolve peer names from wg0.conf comment lines (e.g. "# iPhone")
-fn resolve_peer_names() -> std::collections::HashMap<String, String> {
-    let mut map = std::collections::HashMap::new();
+/// Drop a subnet's route through the interface, if there is one
+pub fn remove_route(interface: &str, subnet: &str) -> Result<()> {
+    execute_mutation(&format!("ip route del {} dev {} 2>/dev/null || true", subnet, interface))?;
+    Ok(())
+}
 
-    let Ok(output) = execute_shell("cat /etc/wireguard/wg0.conf 2>/dev/null") else {
-        return map;
+/// Run a `wg` key command, optionally feeding it a key on stdin
+fn wg_key(cmd: &str, input: Option<&str>) -> Result<String> {
+    let output = match input {
+        Some(input) => execute_shell_with_input(cmd, input)?,
+        None => execute_shell(cmd)?,
     };
-
     if !output.status.success() {
-        return map;
+        return Err(ShannonError::CommandFailed {
+            command: cmd.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
     }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-    let text = String::from_utf8_lossy(&output.stdout);
-    let mut last_comment = String::new();
-
-    for line in text.lines() {
-        let trimmed = line.trim();
-        if trimmed.starts_with('#') {
-            last_comment = trimmed.trim_start_matches('#').trim().to_string();
-        } else if trimmed.starts_with("PublicKey") {
-            if let Some(pos) = trimmed.find('=') {
-                let key = trimmed[pos + 1..].trim().to_string();
-                if !last_comment.is_empty() {
-                    map.insert(key, last_comment.clone());
-                }
-            }
-            last_comment.clear();
+/// Generate a private key and its public key
+pub fn generate_keypair() -> Result<(String, String)> {
+    let private_key = wg_key("wg genkey", None)?;
+    let public_key = public_key(&private_key)?;
+    Ok((private_key, public_key))
+}
+
+/// Public key for a private key
+pub fn public_key(private_key: &str) -> Result<String> {
+    wg_key("wg pubkey", Some(private_key))
+}
+
+/// Generate a preshared key
+pub fn generate_psk() -> Result<String> {
+    wg_key("wg genpsk", None)
+}
+
+/// Add (or update) a peer on the running interface
+pub fn set_peer(interface: &str, public_key: &str, psk: &str, allowed_ips: &str) -> Result<()> {
+    // The preshared key goes in on stdin so it stays out of the audit log
+    let cmd = format!(
+        "wg set {} peer {} preshared-key /dev/stdin allowed-ips {}",
+        interface, public_key, allowed_ips
+    );
+    let output = execute_mutation_with_input(&cmd, psk)?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
         }
+        .into());
     }
+    Ok(())
+}
 
-    map
+/// Resolve peer names from the config file's comment lines (e.g. "# iPhone")
+fn resolve_peer_names(interface: &str) -> std::collections::HashMap<String, String> {
+    wgconf::load(interface)
+        .map(|config| config.peer_names())
+        .unwrap_or_default()
 }
 
 fn short_key(key: &str) -> String {
@@ -191,3 +290,36 @@ fn short_key(key: &str) -> String {
         key.to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dump() {
+        let dump = "cHJpdg==\tc2VydmVy\t51820\toff\n\
+                    aVBob25l\t(none)\t203.0.113.7:41230\t10.8.0.2/32\t1760000000\t1048576\t4096\t25\n\
+                    bGFwdG9w\tcHNr\t(none)\t10.8.0.3/32,192.168.5.0/24\t0\t0\t0\toff\n";
+        let now = DateTime::from_timestamp(1760000100, 0).unwrap();
+        let status = parse_dump("wg0", dump, now);
+
+        assert_eq!(status.listening_port, Some(51820));
+        assert_eq!(status.public_key, "c2VydmVy");
+        assert_eq!(status.peers.len(), 2);
+
+        let phone = &status.peers[0];
+        assert_eq!(phone.endpoint.as_deref(), Some("203.0.113.7:41230"));
+        assert_eq!(phone.last_handshake.unwrap().timestamp(), 1760000000);
+        assert_eq!((phone.transfer_rx, phone.transfer_tx), (1048576, 4096));
+        assert!(phone.connected);
+
+        let laptop = &status.peers[1];
+        assert_eq!(laptop.endpoint, None);
+        assert_eq!(laptop.last_handshake, None);
+        assert_eq!(laptop.allowed_ips, "10.8.0.3/32, 192.168.5.0/24");
+        assert!(!laptop.connected);
+
+        let later = parse_dump("wg0", dump, now + chrono::Duration::minutes(5));
+        assert!(!later.peers[0].connected);
+    }
+}
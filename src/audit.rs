@@ -0,0 +1,137 @@
+//! Append-only audit log of changes made through shannon
+//!
+//! Every mutation — from the CLI, the web dashboard or an AI agent — appends
+//! one JSON line to `/var/log/shannon-audit.jsonl` on SHANNON, including the
+//! file diffs and commands journaled by `location` while it ran.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::IsTerminal;
+
+use crate::cli::Mutation;
+use crate::error::ShannonError;
+use crate::location::{execute_shell, read_file, take_changes, Change};
+
+const AUDIT_LOG: &str = "/var/log/shannon-audit.jsonl";
+
+/// Where a change came from
+#[derive(Debug, Serialize, JsonSchema, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Cli,
+    Web,
+    Agent,
+}
+
+impl Origin {
+    /// Origin of a CLI invocation
+    ///
+    /// `SHANNON_ORIGIN=cli|web|agent` takes precedence; otherwise `--json`
+    /// output to something other than a terminal is assumed to be an agent.
+    pub fn detect(json: bool) -> Self {
+        match std::env::var("SHANNON_ORIGIN").as_deref() {
+            Ok("cli") => return Origin::Cli,
+            Ok("web") => return Origin::Web,
+            Ok("agent") => return Origin::Agent,
+            _ => {}
+        }
+
+        if json && !std::io::stdout().is_terminal() {
+            Origin::Agent
+        } else {
+            Origin::Cli
+        }
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Cli => write!(f, "cli"),
+            Origin::Web => write!(f, "web"),
+            Origin::Agent => write!(f, "agent"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Ok,
+    Error,
+}
+
+/// One line of the audit log
+#[derive(Debug, Serialize, JsonSchema, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub origin: Origin,
+    pub user: String,
+    pub kind: String,
+    pub command: String,
+    pub args: serde_json::Value,
+    /// Snapshot taken just before the change, for `shannon snapshot restore`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// File diffs (before → after) and commands applied
+    pub changes: Vec<Change>,
+    pub result: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// User running this process (the invoking user when run via sudo)
+pub fn current_user() -> String {
+    std::env::var("SUDO_USER")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Append an entry for a finished mutation, with the changes journaled on
+/// this thread since the last call
+pub fn record(
+    origin: Origin,
+    user: &str,
+    mutation: Mutation,
+    snapshot: Option<String>,
+    error: Option<String>,
+) -> Result<()> {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        origin,
+        user: user.to_string(),
+        kind: mutation.kind.to_string(),
+        command: mutation.command.to_string(),
+        args: mutation.args,
+        snapshot,
+        changes: take_changes(),
+        result: if error.is_some() {
+            Outcome::Error
+        } else {
+            Outcome::Ok
+        },
+        error,
+    };
+
+    // Not written through `append_file`: the log itself isn't router state
+    let line = serde_json::to_string(&entry)?;
+    let escaped = line.replace('\'', "'\\''");
+    let output = execute_shell(&format!("printf '%s\\n' '{}' >> {}", escaped, AUDIT_LOG))?;
+    if !output.status.success() {
+        return Err(
+            ShannonError::command_failed(format!("append to {}", AUDIT_LOG), &output).into(),
+        );
+    }
+    Ok(())
+}
+
+/// Read all audit entries, oldest first (empty if nothing was logged yet)
+pub fn read_entries() -> Result<Vec<AuditEntry>> {
+    let content = read_file(AUDIT_LOG).unwrap_or_default();
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -1,7 +1,14 @@
 //! CLI argument definitions using Clap derive macros
 
-use clap::{Parser, Subcommand};
-use std::net::IpAddr;
+use clap::{ArgGroup, Parser, Subcommand};
+use serde_json::json;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+
+use crate::adapters::crowdsec::HUB_KINDS;
+use crate::adapters::dnsmasq::{is_lease_time, IPV6_MODES, RECORD_TYPES};
+use crate::adapters::egress::EGRESS_INTERFACE;
+use crate::adapters::wireguard::DEFAULT_INTERFACE;
 
 #[derive(Parser)]
 #[command(
@@ -21,12 +28,20 @@ pub struct Cli {
     /// Skip confirmation prompts
     #[arg(long, short = 'y', global = true)]
     pub yes: bool,
+
+    /// Show the file diffs and commands a change would make, without applying it
+    #[arg(long, global = true)]
+    pub dry_run: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// System health overview (WAN IP, memory, services)
-    Status,
+    Status {
+        /// Also send a test query to each AdGuard upstream
+        #[arg(long)]
+        upstreams: bool,
+    },
 
     /// Run diagnostic checks (DNS, gateway, internet, services)
     Doctor,
@@ -43,6 +58,12 @@ pub enum Commands {
         action: DhcpAction,
     },
 
+    /// Device inventory (DHCP, reservations, neighbours, WireGuard peers)
+    Device {
+        #[command(subcommand)]
+        action: DeviceAction,
+    },
+
     /// Firewall and port forwarding
     Fw {
         #[command(subcommand)]
@@ -57,6 +78,10 @@ pub enum Commands {
 
     /// VPN (WireGuard) management
     Vpn {
+        /// WireGuard interface (default: wg0; `status`, `sample` and `usage` cover all)
+        #[arg(long, global = true)]
+        interface: Option<String>,
+
         #[command(subcommand)]
         action: VpnAction,
     },
@@ -67,6 +92,33 @@ pub enum Commands {
         action: DdnsAction,
     },
 
+    /// Configuration snapshots (create, list, diff, restore)
+    Snapshot {
+        #[command(subcommand)]
+        action: SnapshotAction,
+    },
+
+    /// Audit log of changes made through shannon (CLI, dashboard, agents)
+    Audit {
+        /// Only show entries newer than this (e.g. 30m, 24h, 7d)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<chrono::Duration>,
+
+        /// Only show one kind of change (dns, dhcp, device, fw, sec, vpn, ddns, snapshot, service, system)
+        #[arg(long)]
+        kind: Option<String>,
+
+        /// Maximum number of entries to show (most recent first)
+        #[arg(short = 'n', long, default_value = "50")]
+        limit: usize,
+    },
+
+    /// JSON Schema of the --json output of every command (or just one)
+    Schema {
+        /// Command to show, e.g. `dns list` (default: all)
+        command: Vec<String>,
+    },
+
     /// Start the web dashboard (default: 0.0.0.0:8080)
     Web {
         /// Port to listen on
@@ -85,27 +137,114 @@ pub enum DnsAction {
     /// List all DNS records
     List,
 
-    /// Add a DNS record
+    /// Add a DNS record (A records also get a PTR record)
     Add {
-        /// Hostname to add
+        /// Name to add (`*.domain` for a wildcard A/AAAA record, or an
+        /// address for a PTR record)
         hostname: String,
-        /// IP address to point to
-        ip: IpAddr,
+        /// Address for A/AAAA, target for CNAME/PTR, text for TXT,
+        /// `target,port[,priority[,weight]]` for SRV, `host[,preference]` for MX
+        value: String,
+        /// Record type (default: A or AAAA, from the address)
+        #[arg(long = "type", value_parser = RECORD_TYPES)]
+        record_type: Option<String>,
     },
 
-    /// Remove a DNS record
+    /// Remove all records for a name (and PTR records pointing at it)
     Rm {
         /// Hostname to remove
         hostname: String,
     },
+
+    /// Conditional forwarding: resolve a domain via its own DNS server
+    Forward {
+        #[command(subcommand)]
+        action: ForwardAction,
+    },
+
+    /// Upstream DNS servers AdGuard Home forwards to
+    Upstream {
+        #[command(subcommand)]
+        action: UpstreamAction,
+    },
+
+    /// Resolve a name at each layer separately (dnsmasq, AdGuard Home,
+    /// upstreams) to see where an answer comes from or gets blocked
+    Test {
+        /// Name to resolve
+        name: String,
+        /// Record type to ask for
+        #[arg(long = "type", value_parser = RECORD_TYPES, default_value = "a")]
+        record_type: String,
+        /// Check filtering with this client's settings and show its last query
+        #[arg(long)]
+        client: Option<IpAddr>,
+    },
+}
+
+// DNS upstream subcommands
+#[derive(Subcommand)]
+pub enum UpstreamAction {
+    /// List upstreams and bootstrap servers
+    List,
+
+    /// Replace the upstreams and/or bootstrap servers (each upstream is
+    /// test-queried first)
+    Set {
+        /// Upstreams: 9.9.9.9, tcp://9.9.9.9, https://… (DoH), tls://… (DoT),
+        /// quic://… (DoQ)
+        #[arg(required_unless_present = "bootstrap")]
+        upstreams: Vec<String>,
+        /// Plain DNS servers for resolving encrypted upstreams' hostnames,
+        /// comma-separated
+        #[arg(long, value_delimiter = ',')]
+        bootstrap: Option<Vec<IpAddr>>,
+        /// Apply even if an upstream does not answer now
+        #[arg(long)]
+        no_check: bool,
+    },
+
+    /// Benchmark each upstream: latency and failure rate
+    Test {
+        /// Test queries per upstream
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..=20))]
+        count: u32,
+    },
+}
+
+// DNS forward subcommands
+#[derive(Subcommand)]
+pub enum ForwardAction {
+    /// List per-domain upstreams in dnsmasq and AdGuard Home
+    List,
+
+    /// Forward a domain (checks that the upstream answers first)
+    Add {
+        /// Domain, including all names under it (e.g. corp.example.com)
+        domain: String,
+        /// ip or ip:port, or `local` for names only dnsmasq answers
+        upstream: String,
+        /// Add it even if the upstream does not answer now
+        #[arg(long)]
+        no_check: bool,
+    },
+
+    /// Stop forwarding a domain
+    Rm {
+        /// Domain to remove
+        domain: String,
+    },
 }
 
 // DHCP subcommands
 #[derive(Subcommand)]
 pub enum DhcpAction {
-    /// List all DHCP leases
+    /// List DHCP leases with expiry, active ones first
     Leases,
 
+    /// List static reservations (dhcp-host=)
+    Reservations,
+
     /// Add a static DHCP reservation
     Reserve {
         /// MAC address (format: aa:bb:cc:dd:ee:ff)
@@ -117,11 +256,70 @@ pub enum DhcpAction {
         hostname: Option<String>,
     },
 
+    /// Reserve the address a device is leasing right now
+    Pin {
+        /// Hostname, MAC or IP of the current lease
+        device: String,
+        /// Hostname for the reservation (default: the one the device sent)
+        #[arg(long)]
+        hostname: Option<String>,
+        /// Also add an address= DNS record for the hostname
+        #[arg(long)]
+        dns: bool,
+    },
+
     /// Remove a DHCP reservation
     Unreserve {
         /// MAC address or IP to unreserve
         target: String,
     },
+
+    /// Dynamic pool, lease time, gateway, DNS servers and domain
+    Scope {
+        #[command(subcommand)]
+        action: ScopeAction,
+    },
+}
+
+// DHCP scope subcommands
+#[derive(Subcommand)]
+pub enum ScopeAction {
+    /// Show the scope, pool utilisation and reservations inside the pool
+    Show,
+
+    /// Change scope settings (restarts dnsmasq)
+    #[command(group(
+        ArgGroup::new("setting")
+            .required(true)
+            .multiple(true)
+            .args(["range", "lease_time", "gateway", "dns", "domain", "ipv6"])
+    ))]
+    Set {
+        /// Dynamic pool as START-END (e.g. 192.168.4.100-192.168.4.199)
+        #[arg(long, value_parser = parse_ip_range)]
+        range: Option<(Ipv4Addr, Ipv4Addr)>,
+
+        /// Lease time (e.g. 12h, 7d, infinite)
+        #[arg(long, value_parser = parse_lease_time)]
+        lease_time: Option<String>,
+
+        /// Default gateway handed to clients
+        #[arg(long)]
+        gateway: Option<Ipv4Addr>,
+
+        /// DNS servers handed to clients, comma-separated
+        #[arg(long, value_delimiter = ',')]
+        dns: Option<Vec<Ipv4Addr>>,
+
+        /// Local domain (e.g. lan)
+        #[arg(long)]
+        domain: Option<String>,
+
+        /// IPv6 for clients: off, ra-only, stateless (SLAAC + DHCPv6
+        /// options), slaac (SLAAC + DHCPv6 addresses) or stateful
+        #[arg(long, value_parser = IPV6_MODES)]
+        ipv6: Option<String>,
+    },
 }
 
 // Firewall subcommands
@@ -169,6 +367,43 @@ pub enum SecAction {
     /// Show active CrowdSec blocks (banned IPs)
     Blocks,
 
+    /// CrowdSec alerts with source country/AS and the resulting decision
+    Alerts {
+        /// Window to look back over (e.g. 1h, 24h, 7d; default: 24h)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<chrono::Duration>,
+
+        /// Only alerts from this scenario (e.g. crowdsecurity/ssh-bf)
+        #[arg(long)]
+        scenario: Option<String>,
+
+        /// Only alerts from this source IP
+        #[arg(long)]
+        ip: Option<IpAddr>,
+    },
+
+    /// Attacks per scenario, per country and per hour
+    Stats {
+        /// Window to aggregate over (e.g. 1h, 24h, 7d; default: 24h)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<chrono::Duration>,
+    },
+
+    /// Everything shannon knows about an address (CrowdSec, firewall, SSH, DHCP, VPN, DNS, GeoIP)
+    Whois { ip: IpAddr },
+
+    /// Manage CrowdSec hub collections, parsers and scenarios
+    Hub {
+        #[command(subcommand)]
+        action: HubAction,
+    },
+
+    /// Addresses CrowdSec must never ban (office IP, VPN subnets)
+    Allowlist {
+        #[command(subcommand)]
+        action: AllowlistAction,
+    },
+
     /// Run security analysis on logs
     Scan,
 
@@ -180,14 +415,290 @@ pub enum SecAction {
     },
 }
 
+// Device inventory subcommands
+#[derive(Subcommand)]
+pub enum DeviceAction {
+    /// List known devices, most recently seen first
+    List {
+        /// Only devices with this tag
+        #[arg(long)]
+        tag: Option<String>,
+
+        /// Only devices present right now
+        #[arg(long)]
+        online: bool,
+    },
+
+    /// Everything known about one device
+    Show {
+        /// MAC, IP, hostname, friendly name or WireGuard peer name
+        device: String,
+    },
+
+    /// Set a device's friendly name and/or owner
+    Name {
+        /// MAC, IP, hostname, friendly name or WireGuard peer name
+        device: String,
+
+        /// Friendly name (e.g. "Living room TV")
+        #[arg(required_unless_present = "owner")]
+        name: Option<String>,
+
+        #[arg(long)]
+        owner: Option<String>,
+    },
+
+    /// Add tags to a device (or remove them with --rm)
+    Tag {
+        /// MAC, IP, hostname, friendly name or WireGuard peer name
+        device: String,
+
+        #[arg(required = true)]
+        tags: Vec<String>,
+
+        #[arg(long)]
+        rm: bool,
+    },
+
+    /// Cut a device off from forwarding (internet, VPN, other subnets)
+    Quarantine {
+        /// MAC, IP, hostname or friendly name
+        device: String,
+    },
+
+    /// Lift a device's quarantine
+    Release {
+        /// MAC, IP, hostname or friendly name
+        device: String,
+    },
+
+    /// Alert on never-seen devices getting a DHCP lease (dnsmasq dhcp-script)
+    Watch {
+        /// Also quarantine them until released
+        #[arg(long, conflicts_with = "off")]
+        quarantine: bool,
+
+        /// Stop watching
+        #[arg(long)]
+        off: bool,
+    },
+
+    /// dnsmasq dhcp-script entry point (installed by `device watch`)
+    #[command(hide = true)]
+    Hook {
+        /// add, old or del
+        event: String,
+        mac: String,
+        ip: String,
+        hostname: Option<String>,
+
+        #[arg(long)]
+        quarantine: bool,
+    },
+}
+
+// CrowdSec hub subcommands
+#[derive(Subcommand)]
+pub enum HubAction {
+    /// List installed hub items (with --all, everything in the hub index)
+    List {
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Install a hub item and reload CrowdSec
+    Install {
+        #[arg(value_parser = HUB_KINDS)]
+        kind: String,
+        /// Item name, e.g. crowdsecurity/nginx
+        name: String,
+    },
+
+    /// Remove a hub item and reload CrowdSec
+    Remove {
+        #[arg(value_parser = HUB_KINDS)]
+        kind: String,
+        name: String,
+    },
+
+    /// Upgrade one item, or every installed item when none is given
+    Upgrade {
+        #[arg(value_parser = HUB_KINDS, requires = "name")]
+        kind: Option<String>,
+        name: Option<String>,
+    },
+}
+
+// CrowdSec allowlist subcommands
+#[derive(Subcommand)]
+pub enum AllowlistAction {
+    /// List allowlisted addresses and ranges
+    List,
+
+    /// Never ban this IP or CIDR range (lifts any current ban)
+    Add {
+        /// IP address or CIDR range, e.g. 198.51.100.20 or 10.8.0.0/24
+        entry: String,
+
+        /// Why it is allowlisted (stored alongside the entry)
+        #[arg(long)]
+        note: Option<String>,
+    },
+
+    /// Remove an IP or range from the allowlist
+    Rm { entry: String },
+}
+
 // VPN subcommands
 #[derive(Subcommand)]
 pub enum VpnAction {
     /// List WireGuard peers with connection status
     Peers,
 
-    /// Show WireGuard interface status
+    /// Show WireGuard interface status (every interface unless --interface)
     Status,
+
+    /// Add a peer and print its client config and QR code
+    AddPeer {
+        /// Peer name (stored as a comment in the interface's config file)
+        name: String,
+
+        /// Route all client traffic through the tunnel (default)
+        #[arg(long, conflicts_with = "split")]
+        full_tunnel: bool,
+
+        /// Route only the tunnel and LAN subnets through the tunnel
+        #[arg(long)]
+        split: bool,
+    },
+
+    /// Connect a remote site: route its LAN through the tunnel and print the
+    /// remote router's config
+    AddSite {
+        /// Site name (stored as a comment in the interface's config file)
+        name: String,
+
+        /// LAN behind the remote router, e.g. 192.168.5.0/24 (repeatable)
+        #[arg(long = "remote-subnet", required = true)]
+        remote_subnets: Vec<String>,
+
+        /// Remote router's public host:port, if we should dial it
+        #[arg(long)]
+        endpoint: Option<String>,
+
+        /// Keepalive interval in seconds, from both ends
+        #[arg(long, default_value = "25")]
+        keepalive: u16,
+    },
+
+    /// Revoke a peer (removes it from the config file and the running interface)
+    RmPeer {
+        /// Peer name or public key
+        name: String,
+    },
+
+    /// Rename a peer
+    RenamePeer {
+        /// Peer name or public key
+        name: String,
+        /// New name
+        new_name: String,
+    },
+
+    /// Disable a peer, keeping it in the config file commented out
+    DisablePeer {
+        /// Peer name or public key
+        name: String,
+    },
+
+    /// Re-enable a disabled peer
+    EnablePeer {
+        /// Peer name or public key
+        name: String,
+    },
+
+    /// Issue a peer new keys and print its new client config and QR code
+    RotateKey {
+        /// Peer name or public key
+        name: String,
+
+        /// Route only the tunnel and LAN subnets through the tunnel
+        #[arg(long)]
+        split: bool,
+    },
+
+    /// Record every peer's traffic counters (run each minute by shannon-wg-traffic.timer)
+    Sample,
+
+    /// Per-peer traffic over a window, with endpoint countries
+    Usage {
+        /// Only show this peer (name or public key)
+        #[arg(long)]
+        peer: Option<String>,
+
+        /// Window to sum over (e.g. 1h, 24h, 7d; default: 24h)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<chrono::Duration>,
+    },
+
+    /// Route chosen LAN devices out through a VPN provider (client mode)
+    Egress {
+        #[command(subcommand)]
+        action: EgressAction,
+    },
+}
+
+// VPN egress subcommands
+#[derive(Subcommand)]
+pub enum EgressAction {
+    /// Import a provider's WireGuard config as the egress tunnel (default: wgc0)
+    Setup {
+        /// Client config file from the provider
+        config: PathBuf,
+    },
+
+    /// Send a device's internet traffic through the tunnel (kill switch included)
+    Add {
+        /// MAC address, or hostname/IP of a DHCP client
+        device: String,
+    },
+
+    /// Send a device through the WAN again
+    Rm {
+        /// MAC address, name, or hostname/IP of a DHCP client
+        device: String,
+    },
+
+    /// Devices routed through the tunnel
+    List,
+}
+
+// Snapshot subcommands
+#[derive(Subcommand)]
+pub enum SnapshotAction {
+    /// Capture the managed configuration now
+    Create {
+        /// Optional label describing the snapshot
+        #[arg(short, long)]
+        label: Option<String>,
+    },
+
+    /// List snapshots (newest first)
+    List,
+
+    /// Show a unified diff between two snapshots, or a snapshot and live state
+    Diff {
+        /// Snapshot id (or unique prefix, or "latest")
+        from: String,
+        /// Snapshot to compare against (default: live state)
+        to: Option<String>,
+    },
+
+    /// Restore a snapshot and reload the affected services
+    Restore {
+        /// Snapshot id (or unique prefix, or "latest")
+        id: String,
+    },
 }
 
 // DDNS subcommands
@@ -203,3 +714,351 @@ pub enum DdnsAction {
         force: bool,
     },
 }
+
+/// A state-changing invocation, as recorded in the audit log
+pub struct Mutation {
+    pub kind: &'static str,
+    pub command: &'static str,
+    pub args: serde_json::Value,
+}
+
+impl Commands {
+    /// Describe this command if it changes router state (None for read-only commands)
+    pub fn mutation(&self) -> Option<Mutation> {
+        let (kind, command, args) = match self {
+            Commands::Dns { action } => match action {
+                DnsAction::Add {
+                    hostname,
+                    value,
+                    record_type,
+                } => (
+                    "dns",
+                    "dns add",
+                    json!({ "hostname": hostname, "value": value, "type": record_type }),
+                ),
+                DnsAction::Rm { hostname } => ("dns", "dns rm", json!({ "hostname": hostname })),
+                DnsAction::Forward { action } => match action {
+                    ForwardAction::Add {
+                        domain, upstream, ..
+                    } => (
+                        "dns",
+                        "dns forward add",
+                        json!({ "domain": domain, "upstream": upstream }),
+                    ),
+                    ForwardAction::Rm { domain } => {
+                        ("dns", "dns forward rm", json!({ "domain": domain }))
+                    }
+                    ForwardAction::List => return None,
+                },
+                DnsAction::Upstream { action } => match action {
+                    UpstreamAction::Set {
+                        upstreams,
+                        bootstrap,
+                        ..
+                    } => (
+                        "dns",
+                        "dns upstream set",
+                        json!({ "upstreams": upstreams, "bootstrap": bootstrap }),
+                    ),
+                    UpstreamAction::List | UpstreamAction::Test { .. } => return None,
+                },
+                DnsAction::List | DnsAction::Test { .. } => return None,
+            },
+            Commands::Dhcp { action } => match action {
+                DhcpAction::Reserve { mac, ip, hostname } => (
+                    "dhcp",
+                    "dhcp reserve",
+                    json!({ "mac": mac, "ip": ip, "hostname": hostname }),
+                ),
+                DhcpAction::Pin {
+                    device,
+                    hostname,
+                    dns,
+                } => (
+                    "dhcp",
+                    "dhcp pin",
+                    json!({ "device": device, "hostname": hostname, "dns": dns }),
+                ),
+                DhcpAction::Unreserve { target } => {
+                    ("dhcp", "dhcp unreserve", json!({ "target": target }))
+                }
+                DhcpAction::Scope { action } => match action {
+                    ScopeAction::Set {
+                        range,
+                        lease_time,
+                        gateway,
+                        dns,
+                        domain,
+                        ipv6,
+                    } => (
+                        "dhcp",
+                        "dhcp scope set",
+                        json!({
+                            "range": range.map(|(start, end)| format!("{}-{}", start, end)),
+                            "lease_time": lease_time,
+                            "gateway": gateway,
+                            "dns": dns,
+                            "domain": domain,
+                            "ipv6": ipv6,
+                        }),
+                    ),
+                    ScopeAction::Show => return None,
+                },
+                DhcpAction::Leases | DhcpAction::Reservations => return None,
+            },
+            Commands::Device { action } => match action {
+                DeviceAction::Name {
+                    device,
+                    name,
+                    owner,
+                } => (
+                    "device",
+                    "device name",
+                    json!({ "device": device, "name": name, "owner": owner }),
+                ),
+                DeviceAction::Tag { device, tags, rm } => (
+                    "device",
+                    "device tag",
+                    json!({ "device": device, "tags": tags, "rm": rm }),
+                ),
+                DeviceAction::Quarantine { device } => {
+                    ("device", "device quarantine", json!({ "device": device }))
+                }
+                DeviceAction::Release { device } => {
+                    ("device", "device release", json!({ "device": device }))
+                }
+                DeviceAction::Watch { quarantine, off } => (
+                    "device",
+                    "device watch",
+                    json!({ "quarantine": quarantine, "off": off }),
+                ),
+                // Runs on every lease; records its own audit entry when it
+                // quarantines, rather than snapshotting each time
+                DeviceAction::List { .. }
+                | DeviceAction::Show { .. }
+                | DeviceAction::Hook { .. } => return None,
+            },
+            Commands::Fw { action } => match action {
+                FwAction::Forward {
+                    external_port,
+                    internal,
+                    proto,
+                } => (
+                    "fw",
+                    "fw forward",
+                    json!({ "external_port": external_port, "internal": internal, "proto": proto }),
+                ),
+                FwAction::Unforward { external_port } => (
+                    "fw",
+                    "fw unforward",
+                    json!({ "external_port": external_port }),
+                ),
+                FwAction::Block { target } => ("fw", "fw block", json!({ "target": target })),
+                FwAction::Unblock { target } => ("fw", "fw unblock", json!({ "target": target })),
+                FwAction::List => return None,
+            },
+            Commands::Sec { action } => match action {
+                SecAction::Hub { action } => match action {
+                    HubAction::Install { kind, name } => (
+                        "sec",
+                        "sec hub install",
+                        json!({ "kind": kind, "name": name }),
+                    ),
+                    HubAction::Remove { kind, name } => (
+                        "sec",
+                        "sec hub remove",
+                        json!({ "kind": kind, "name": name }),
+                    ),
+                    HubAction::Upgrade { kind, name } => (
+                        "sec",
+                        "sec hub upgrade",
+                        json!({ "kind": kind, "name": name }),
+                    ),
+                    HubAction::List { .. } => return None,
+                },
+                SecAction::Allowlist { action } => match action {
+                    AllowlistAction::Add { entry, note } => (
+                        "sec",
+                        "sec allowlist add",
+                        json!({ "entry": entry, "note": note }),
+                    ),
+                    AllowlistAction::Rm { entry } => {
+                        ("sec", "sec allowlist rm", json!({ "entry": entry }))
+                    }
+                    AllowlistAction::List => return None,
+                },
+                _ => return None,
+            },
+            Commands::Ddns {
+                action: DdnsAction::Update { force },
+            } => ("ddns", "ddns update", json!({ "force": force })),
+            Commands::Vpn { interface, action } => {
+                let iface = interface.as_deref().unwrap_or(DEFAULT_INTERFACE);
+                match action {
+                    VpnAction::AddPeer { name, split, .. } => (
+                        "vpn",
+                        "vpn add-peer",
+                        json!({ "interface": iface, "name": name, "split": split }),
+                    ),
+                    VpnAction::AddSite {
+                        name,
+                        remote_subnets,
+                        endpoint,
+                        keepalive,
+                    } => (
+                        "vpn",
+                        "vpn add-site",
+                        json!({
+                            "interface": iface,
+                            "name": name,
+                            "remote_subnets": remote_subnets,
+                            "endpoint": endpoint,
+                            "keepalive": keepalive,
+                        }),
+                    ),
+                    VpnAction::RmPeer { name } => (
+                        "vpn",
+                        "vpn rm-peer",
+                        json!({ "interface": iface, "name": name }),
+                    ),
+                    VpnAction::RenamePeer { name, new_name } => (
+                        "vpn",
+                        "vpn rename-peer",
+                        json!({ "interface": iface, "name": name, "new_name": new_name }),
+                    ),
+                    VpnAction::DisablePeer { name } => (
+                        "vpn",
+                        "vpn disable-peer",
+                        json!({ "interface": iface, "name": name }),
+                    ),
+                    VpnAction::EnablePeer { name } => (
+                        "vpn",
+                        "vpn enable-peer",
+                        json!({ "interface": iface, "name": name }),
+                    ),
+                    VpnAction::RotateKey { name, split } => (
+                        "vpn",
+                        "vpn rotate-key",
+                        json!({ "interface": iface, "name": name, "split": split }),
+                    ),
+                    VpnAction::Egress { action } => match action {
+                        EgressAction::Setup { config } => (
+                            "vpn",
+                            "vpn egress setup",
+                            json!({
+                                "interface": interface.as_deref().unwrap_or(EGRESS_INTERFACE),
+                                "config": config,
+                            }),
+                        ),
+                        EgressAction::Add { device } => {
+                            ("vpn", "vpn egress add", json!({ "device": device }))
+                        }
+                        EgressAction::Rm { device } => {
+                            ("vpn", "vpn egress rm", json!({ "device": device }))
+                        }
+                        EgressAction::List => return None,
+                    },
+                    VpnAction::Peers
+                    | VpnAction::Status
+                    | VpnAction::Sample
+                    | VpnAction::Usage { .. } => return None,
+                }
+            }
+            Commands::Snapshot {
+                action: SnapshotAction::Restore { id },
+            } => ("snapshot", "snapshot restore", json!({ "id": id })),
+            _ => return None,
+        };
+
+        Some(Mutation {
+            kind,
+            command,
+            args,
+        })
+    }
+}
+
+/// Parse an inclusive IPv4 range: "192.168.4.100-192.168.4.199"
+pub fn parse_ip_range(s: &str) -> Result<(Ipv4Addr, Ipv4Addr), String> {
+    let invalid = || format!("invalid range '{}' (expected START-END)", s);
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let start: Ipv4Addr = start.trim().parse().map_err(|_| invalid())?;
+    let end: Ipv4Addr = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(format!("range '{}' ends before it starts", s));
+    }
+    Ok((start, end))
+}
+
+/// Check a dnsmasq lease time; it refuses anything under two minutes
+pub fn parse_lease_time(s: &str) -> Result<String, String> {
+    if s == "infinite" {
+        return Ok(s.to_string());
+    }
+    if !is_lease_time(s) {
+        return Err(format!(
+            "invalid lease time '{}' (expected e.g. 12h, 7d, infinite)",
+            s
+        ));
+    }
+    // A bare number is seconds, as for dnsmasq
+    let seconds = match parse_duration(s) {
+        Ok(duration) => duration.num_seconds(),
+        Err(_) => s.parse().unwrap_or(0),
+    };
+    if seconds < 120 {
+        return Err(format!(
+            "lease time '{}' is below dnsmasq's minimum of 2m",
+            s
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// Parse a duration like `30m`, `24h` or `7d`
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let n: i64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}' (expected e.g. 30m, 24h, 7d)", s))?;
+
+    let duration = match unit {
+        "s" => chrono::Duration::try_seconds(n),
+        "m" => chrono::Duration::try_minutes(n),
+        "h" => chrono::Duration::try_hours(n),
+        "d" => chrono::Duration::try_days(n),
+        "w" => chrono::Duration::try_weeks(n),
+        _ => {
+            return Err(format!(
+                "invalid duration unit in '{}' (use s, m, h, d or w)",
+                s
+            ))
+        }
+    };
+    // Durations are subtracted from the current time (--since), so they
+    // must also fit in the range of a timestamp
+    duration
+        .filter(|d| {
+            chrono::DateTime::UNIX_EPOCH
+                .checked_sub_signed(*d)
+                .is_some()
+        })
+        .ok_or_else(|| format!("duration '{}' is too long", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m"), Ok(chrono::Duration::minutes(30)));
+        assert_eq!(parse_duration("7d"), Ok(chrono::Duration::days(7)));
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("999999999999999d").is_err());
+        assert!(parse_duration("99999999999w").is_err());
+    }
+}
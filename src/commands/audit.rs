@@ -0,0 +1,70 @@
+//! Audit log queries
+
+use anyhow::Result;
+use chrono::{Duration, Local, Utc};
+
+use crate::audit::{self, AuditEntry, Outcome};
+use crate::output::{print_output, TableOutput, TableRow};
+
+impl TableRow for AuditEntry {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            self.origin.to_string(),
+            self.user.clone(),
+            format!("{} {}", self.command, args_summary(&self.args))
+                .trim_end()
+                .to_string(),
+            match (&self.result, &self.error) {
+                (Outcome::Ok, _) => "ok".to_string(),
+                (Outcome::Error, Some(e)) => format!("error: {}", e),
+                (Outcome::Error, None) => "error".to_string(),
+            },
+        ]
+    }
+}
+
+/// Arguments as `key=value` pairs, e.g. `external_port=8080 proto=tcp`
+fn args_summary(args: &serde_json::Value) -> String {
+    let Some(map) = args.as_object() else {
+        return String::new();
+    };
+    map.iter()
+        .filter_map(|(k, v)| match v {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => None,
+            serde_json::Value::String(s) => Some(format!("{}={}", k, s)),
+            other => Some(format!("{}={}", k, other)),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Show audit log entries, most recent first
+pub fn list(since: Option<Duration>, kind: Option<&str>, limit: usize, json: bool) -> Result<()> {
+    let cutoff = since.map(|d| Utc::now() - d);
+
+    let mut entries: Vec<AuditEntry> = audit::read_entries()?
+        .into_iter()
+        .filter(|e| cutoff.is_none_or(|c| e.timestamp >= c))
+        .filter(|e| kind.is_none_or(|k| e.kind == k))
+        .collect();
+    entries.reverse();
+    entries.truncate(limit);
+
+    let output = TableOutput {
+        headers: vec![
+            "Time".to_string(),
+            "Origin".to_string(),
+            "User".to_string(),
+            "Command".to_string(),
+            "Result".to_string(),
+        ],
+        rows: entries,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
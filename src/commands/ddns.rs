@@ -5,10 +5,13 @@
 //! Provides `shannon ddns status` and `shannon ddns update` commands.
 
 use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::process::Command;
 
+use crate::error::ShannonError;
+use crate::location::{execute_mutation, is_dry_run};
 use crate::output::print_output;
 
 const STATE_FILE: &str = "/var/cache/shannon-ddns-state.json";
@@ -18,6 +21,9 @@ const WAN_INTERFACE: &str = "enxc84d4421f975";
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DdnsState {
     pub ip: String,
+    /// AAAA record, absent in state written before IPv6 support
+    #[serde(default)]
+    pub ipv6: Option<String>,
     pub status: String,
     pub message: String,
     pub timestamp: String,
@@ -26,10 +32,14 @@ pub struct DdnsState {
     pub ttl: u32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DdnsStatus {
     pub wan_ip: String,
     pub dns_record: String,
+    /// Stable global IPv6 address on the WAN interface, if any
+    pub wan_ipv6: Option<String>,
+    /// Address in the AAAA record, if one has been published
+    pub dns_record_v6: Option<String>,
     pub fqdn: String,
     pub status: String,
     pub last_check: String,
@@ -43,6 +53,12 @@ impl Display for DdnsStatus {
         writeln!(f, "Dynamic DNS: {}", self.fqdn)?;
         writeln!(f, "  WAN IP:      {}", self.wan_ip)?;
         writeln!(f, "  DNS record:  {}", self.dns_record)?;
+        if let Some(ipv6) = &self.wan_ipv6 {
+            writeln!(f, "  WAN IPv6:    {}", ipv6)?;
+        }
+        if let Some(record) = &self.dns_record_v6 {
+            writeln!(f, "  AAAA record: {}", record)?;
+        }
         writeln!(f, "  Status:      {}", self.status)?;
         writeln!(f, "  Last check:  {}", self.last_check)?;
         writeln!(f, "  Timer:       {}", if self.timer_active { "active" } else { "inactive" })?;
@@ -52,6 +68,9 @@ impl Display for DdnsStatus {
         if !self.ip_match && self.dns_record != "never updated" {
             writeln!(f, "  WARNING:     IP mismatch — DNS needs update")?;
         }
+        if self.wan_ipv6.is_some() && self.wan_ipv6 != self.dns_record_v6 {
+            writeln!(f, "  WARNING:     IPv6 mismatch — AAAA needs update")?;
+        }
         Ok(())
     }
 }
@@ -74,6 +93,30 @@ fn get_wan_ip() -> Option<String> {
     None
 }
 
+/// Get the stable global IPv6 address from the WAN interface
+///
+/// Temporary (privacy), deprecated and tentative addresses are skipped, the
+/// same as the updater script does.
+fn get_wan_ipv6() -> Option<String> {
+    let output = Command::new("ip")
+        .args(["-6", "-o", "addr", "show", WAN_INTERFACE, "scope", "global"])
+        .output()
+        .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields
+            .iter()
+            .any(|f| matches!(*f, "temporary" | "deprecated" | "tentative"))
+        {
+            return None;
+        }
+        let i = fields.iter().position(|f| *f == "inet6")?;
+        Some(fields.get(i + 1)?.split('/').next()?.to_string())
+    })
+}
+
 /// Check if systemd timer is active
 fn timer_active() -> bool {
     Command::new("systemctl")
@@ -113,6 +156,8 @@ pub fn status(json: bool) -> Result<()> {
     let result = DdnsStatus {
         wan_ip,
         dns_record,
+        wan_ipv6: get_wan_ipv6(),
+        dns_record_v6: state.as_ref().and_then(|s| s.ipv6.clone()),
         fqdn,
         status: status_str,
         last_check,
@@ -127,6 +172,16 @@ pub fn status(json: bool) -> Result<()> {
 
 /// Trigger a DDNS update
 pub fn update(force: bool, json: bool) -> Result<()> {
+    if is_dry_run() {
+        let script = if force {
+            format!("{} --force", DDNS_SCRIPT)
+        } else {
+            DDNS_SCRIPT.to_string()
+        };
+        execute_mutation(&script)?;
+        return status(json);
+    }
+
     let mut cmd = Command::new(DDNS_SCRIPT);
     if force {
         cmd.arg("--force");
@@ -136,13 +191,11 @@ pub fn update(force: bool, json: bool) -> Result<()> {
         .context("Failed to run shannon-ddns script")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        if json {
-            println!("{{\"error\": \"{}\"}}", stderr.trim().replace('"', "\\\""));
-        } else {
-            eprintln!("{}", stderr);
+        return Err(ShannonError::CommandFailed {
+            command: DDNS_SCRIPT.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
         }
-        std::process::exit(1);
+        .into());
     }
 
     // Show updated status
@@ -0,0 +1,386 @@
+//! Device inventory commands
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::net::Ipv6Addr;
+
+use crate::adapters::nftables::NftablesAdapter;
+use crate::audit::{self, Origin};
+use crate::cli::Mutation;
+use crate::error::ShannonError;
+use crate::inventory::{self, Device};
+use crate::location::{self, execute_mutation, systemctl, write_file};
+use crate::notify::ntfy::{notify_ntfy, SECURITY_TOPIC};
+use crate::output::{print_output, TableOutput, TableRow};
+
+/// dnsmasq drop-in enabling the lease hook
+const WATCH_CONFIG: &str = "/etc/dnsmasq.d/shannon-devices.conf";
+/// Wrapper dnsmasq runs as its `dhcp-script` (it passes event, MAC, IP, hostname)
+const HOOK_SCRIPT: &str = "/usr/local/lib/shannon/dhcp-hook";
+
+fn local_time(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+impl TableRow for Device {
+    fn cells(&self) -> Vec<String> {
+        let id = match self.id.strip_prefix("wg:") {
+            Some(key) => format!("wg:{}…", key.chars().take(8).collect::<String>()),
+            None => self.id.clone(),
+        };
+        let label = if self.quarantined {
+            format!("{} (quarantined)", self.label())
+        } else {
+            self.label().to_string()
+        };
+        vec![
+            label,
+            self.ip.clone().unwrap_or_default(),
+            id,
+            self.vendor.clone().unwrap_or_default(),
+            self.owner.clone().unwrap_or_default(),
+            self.tags.join(", "),
+            if self.online {
+                "online".to_string()
+            } else {
+                local_time(self.last_seen)
+            },
+        ]
+    }
+}
+
+impl Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.label())?;
+        writeln!(f, "{}", "=".repeat(self.label().chars().count()))?;
+        let or_dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        writeln!(f, "  ID:          {}", self.id)?;
+        writeln!(f, "  Hostname:    {}", or_dash(&self.hostname))?;
+        writeln!(f, "  Vendor:      {}", or_dash(&self.vendor))?;
+        writeln!(f, "  IP:          {}", or_dash(&self.ip))?;
+        writeln!(f, "  Owner:       {}", or_dash(&self.owner))?;
+        writeln!(
+            f,
+            "  Tags:        {}",
+            if self.tags.is_empty() {
+                "-".to_string()
+            } else {
+                self.tags.join(", ")
+            }
+        )?;
+        if self.online {
+            writeln!(f, "  Status:      online ({})", self.sources.join(", "))?;
+        } else if self.sources.is_empty() {
+            writeln!(f, "  Status:      offline")?;
+        } else {
+            writeln!(f, "  Status:      offline ({})", self.sources.join(", "))?;
+        }
+        if self.quarantined {
+            writeln!(
+                f,
+                "  Quarantined: yes (release with `shannon device release`)"
+            )?;
+        }
+        writeln!(f, "  First seen:  {}", local_time(self.first_seen))?;
+        writeln!(f, "  Last seen:   {}", local_time(self.last_seen))
+    }
+}
+
+/// Id of the device matching a MAC, IP, hostname, friendly name or peer name
+fn resolve(devices: &BTreeMap<String, Device>, query: &str) -> Result<String> {
+    if let Some(device) = devices.get(&query.to_ascii_lowercase()) {
+        return Ok(device.id.clone());
+    }
+
+    let matches: Vec<&Device> = devices
+        .values()
+        .filter(|d| {
+            d.ip.as_deref() == Some(query)
+                || d.name
+                    .as_deref()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(query))
+                || d.hostname
+                    .as_deref()
+                    .is_some_and(|h| h.eq_ignore_ascii_case(query))
+        })
+        .collect();
+    match matches[..] {
+        [device] => Ok(device.id.clone()),
+        [] => Err(ShannonError::NotFound(format!("Device '{}'", query)).into()),
+        _ => Err(ShannonError::InvalidInput(format!(
+            "'{}' matches several devices ({}); use the MAC address",
+            query,
+            matches
+                .iter()
+                .map(|d| d.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .into()),
+    }
+}
+
+/// List known devices, present ones first, then by last seen
+pub fn list(tag: Option<&str>, online: bool, json: bool) -> Result<()> {
+    let mut rows: Vec<Device> = inventory::refresh()?
+        .into_values()
+        .filter(|d| tag.is_none_or(|tag| d.tags.iter().any(|t| t == tag)))
+        .filter(|d| !online || d.online)
+        .collect();
+    rows.sort_by(|a, b| b.online.cmp(&a.online).then(b.last_seen.cmp(&a.last_seen)));
+
+    let output = TableOutput {
+        headers: vec![
+            "Name".to_string(),
+            "IP".to_string(),
+            "MAC / Peer".to_string(),
+            "Vendor".to_string(),
+            "Owner".to_string(),
+            "Tags".to_string(),
+            "Last seen".to_string(),
+        ],
+        rows,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+pub fn show(query: &str, json: bool) -> Result<()> {
+    let mut devices = inventory::refresh()?;
+    let id = resolve(&devices, query)?;
+    let device = devices.remove(&id).expect("resolved id exists");
+
+    print_output(&device, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DeviceUpdateResult {
+    pub success: bool,
+    pub device: Device,
+}
+
+impl Display for DeviceUpdateResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "✓ Updated {}", self.device.id)?;
+        writeln!(f)?;
+        write!(f, "{}", self.device)
+    }
+}
+
+fn update(query: &str, json: bool, change: impl FnOnce(&mut Device)) -> Result<()> {
+    let mut devices = inventory::current()?;
+    let id = resolve(&devices, query)?;
+    let device = devices.get_mut(&id).expect("resolved id exists");
+    change(device);
+    let device = device.clone();
+    inventory::save(&devices)?;
+
+    let result = DeviceUpdateResult {
+        success: true,
+        device,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Set the friendly name and/or owner; an empty string clears either
+pub fn name(query: &str, name: Option<&str>, owner: Option<&str>, json: bool) -> Result<()> {
+    let clean = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    update(query, json, |device| {
+        if let Some(name) = name {
+            device.name = clean(name);
+        }
+        if let Some(owner) = owner {
+            device.owner = clean(owner);
+        }
+    })
+}
+
+/// Add tags (or remove them with `rm`)
+pub fn tag(query: &str, tags: &[String], rm: bool, json: bool) -> Result<()> {
+    if let Some(bad) = tags
+        .iter()
+        .find(|t| t.is_empty() || t.contains(char::is_whitespace))
+    {
+        return Err(
+            ShannonError::InvalidInput(format!("Invalid tag '{}' (no spaces)", bad)).into(),
+        );
+    }
+
+    update(query, json, |device| {
+        if rm {
+            device.tags.retain(|t| !tags.contains(t));
+        } else {
+            for tag in tags {
+                if !device.tags.contains(tag) {
+                    device.tags.push(tag.clone());
+                }
+            }
+            device.tags.sort();
+        }
+    })
+}
+
+/// Quarantine a device, or release it when `on` is false
+pub fn quarantine(query: &str, on: bool, json: bool) -> Result<()> {
+    let mut devices = inventory::current()?;
+    let id = resolve(&devices, query)?;
+    if id.starts_with("wg:") {
+        return Err(ShannonError::InvalidInput(format!(
+            "'{}' is a WireGuard peer; use `shannon vpn disable-peer` instead",
+            query
+        ))
+        .into());
+    }
+    let device = devices.get_mut(&id).expect("resolved id exists");
+    if device.quarantined == on {
+        let state = if on {
+            "already quarantined"
+        } else {
+            "not quarantined"
+        };
+        return Err(ShannonError::InvalidInput(format!("{} is {}", device.label(), state)).into());
+    }
+
+    let firewall = NftablesAdapter::new();
+    if on {
+        firewall.quarantine(&id)?;
+    } else {
+        firewall.release(&id)?;
+    }
+    device.quarantined = on;
+
+    let result = DeviceUpdateResult {
+        success: true,
+        device: device.clone(),
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DeviceWatchResult {
+    pub success: bool,
+    pub enabled: bool,
+    /// New devices are quarantined until released
+    pub quarantine: bool,
+}
+
+impl Display for DeviceWatchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.enabled {
+            return writeln!(f, "✓ No longer watching for new devices");
+        }
+        writeln!(
+            f,
+            "✓ Watching for new devices (ntfy topic {})",
+            SECURITY_TOPIC
+        )?;
+        if self.quarantine {
+            writeln!(
+                f,
+                "  New devices are quarantined until `shannon device release`"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Install (or remove) the dnsmasq lease hook
+pub fn watch(enabled: bool, quarantine: bool, json: bool) -> Result<()> {
+    if enabled {
+        let flag = if quarantine { " --quarantine" } else { "" };
+        execute_mutation(&format!("mkdir -p $(dirname {})", HOOK_SCRIPT))?;
+        write_file(
+            HOOK_SCRIPT,
+            &format!(
+                "#!/bin/sh\n# Installed by `shannon device watch`\nexec /usr/local/bin/shannon device hook{} \"$@\"\n",
+                flag
+            ),
+        )?;
+        execute_mutation(&format!("chmod 755 {}", HOOK_SCRIPT))?;
+        // Root, so the hook can write the inventory and firewall rules
+        write_file(
+            WATCH_CONFIG,
+            &format!(
+                "# Managed by `shannon device watch`\ndhcp-script={}\ndhcp-scriptuser=root\n",
+                HOOK_SCRIPT
+            ),
+        )?;
+    } else {
+        execute_mutation(&format!("rm -f {} {}", WATCH_CONFIG, HOOK_SCRIPT))?;
+    }
+    // dhcp-script is only read at startup
+    systemctl("restart", "dnsmasq")?;
+
+    let result = DeviceWatchResult {
+        success: true,
+        enabled,
+        quarantine: enabled && quarantine,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// dnsmasq `dhcp-script` event: record the lease, and alert (and optionally
+/// quarantine) if the MAC has never been seen
+///
+/// Only "add" alerts; dnsmasq replays existing leases as "old" at startup.
+/// DHCPv6 events are ignored: dnsmasq passes the client DUID where the MAC
+/// would be.
+pub fn hook(
+    event: &str,
+    mac: &str,
+    ip: &str,
+    hostname: Option<&str>,
+    quarantine: bool,
+) -> Result<()> {
+    if !matches!(event, "add" | "old") {
+        return Ok(());
+    }
+    if ip.parse::<Ipv6Addr>().is_ok() || std::env::var_os("DNSMASQ_IAID").is_some() {
+        return Ok(());
+    }
+    let (device, is_new) = inventory::record_lease(mac, ip, hostname)?;
+    if !is_new || event != "add" {
+        return Ok(());
+    }
+    // The inventory write isn't part of the audited change
+    location::take_changes();
+
+    let mut message = format!(
+        "New device on the network: {} {} {} ({})",
+        hostname.unwrap_or("(no hostname)"),
+        ip,
+        device.id,
+        device.vendor.as_deref().unwrap_or("unknown vendor")
+    );
+    if quarantine {
+        let result = NftablesAdapter::new().quarantine(&device.id);
+        let mutation = Mutation {
+            kind: "device",
+            command: "device quarantine",
+            args: serde_json::json!({ "device": device.id, "automatic": true }),
+        };
+        let error = result.as_ref().err().map(|e| format!("{:#}", e));
+        if let Err(e) = audit::record(Origin::Cli, &audit::current_user(), mutation, None, error) {
+            tracing::warn!("Failed to write audit log: {:#}", e);
+        }
+        match result {
+            Ok(()) => message.push_str(" — quarantined until `shannon device release`"),
+            Err(e) => message.push_str(&format!(" — quarantine failed: {:#}", e)),
+        }
+    }
+
+    notify_ntfy(SECURITY_TOPIC, &message)
+}
@@ -1,36 +1,62 @@
 //! DHCP lease and reservation management
 
 use anyhow::Result;
+use chrono::Utc;
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::fmt::Display;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
-use crate::adapters::dnsmasq::{DhcpLease, DnsmasqAdapter};
-use crate::output::{confirm, print_output, TableOutput, TableRow};
+use crate::adapters::dnsmasq::{
+    address_type, check_pin, dynamic_range_of, DhcpLease, DhcpReservation, DhcpScope,
+    DnsmasqAdapter, ScopeChange,
+};
+use crate::adapters::nftables::NftablesAdapter;
+use crate::error::ShannonError;
+use crate::output::{confirm, format_span, print_output, TableOutput, TableRow};
 
 impl TableRow for DhcpLease {
     fn cells(&self) -> Vec<String> {
+        let expires = match self.expires {
+            None => "never".to_string(),
+            Some(t) if self.active => format!("in {}", format_span(t - Utc::now())),
+            Some(t) => format!("expired {} ago", format_span(Utc::now() - t)),
+        };
         vec![
-            self.mac.clone(),
+            self.hostname.clone().unwrap_or_else(|| "-".to_string()),
             self.ip.clone(),
-            self.hostname.clone(),
-            self.expires.clone(),
+            self.mac.clone(),
+            expires,
+            self.client_id.clone().unwrap_or_default(),
+            if self.reserved { "yes" } else { "" }.to_string(),
         ]
     }
 }
 
+impl TableRow for DhcpReservation {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.hostname.clone().unwrap_or_else(|| "-".to_string()),
+            self.ip.clone(),
+            self.mac.clone(),
+        ]
+    }
+}
 
-/// List DHCP leases
+/// List DHCP leases, active ones first
 pub fn leases(json: bool) -> Result<()> {
     let adapter = DnsmasqAdapter::new();
-    let leases = adapter.list_leases()?;
+    let mut leases = adapter.list_leases()?;
+    leases.sort_by_key(|l| !l.active);
 
     let output = TableOutput {
         headers: vec![
-            "MAC".to_string(),
-            "IP".to_string(),
             "Hostname".to_string(),
+            "IP".to_string(),
+            "MAC".to_string(),
             "Expires".to_string(),
+            "Client ID".to_string(),
+            "Reserved".to_string(),
         ],
         rows: leases,
     };
@@ -39,30 +65,43 @@ pub fn leases(json: bool) -> Result<()> {
     Ok(())
 }
 
+/// List static reservations
+pub fn reservations(json: bool) -> Result<()> {
+    let adapter = DnsmasqAdapter::new();
+
+    let output = TableOutput {
+        headers: vec!["Hostname".to_string(), "IP".to_string(), "MAC".to_string()],
+        rows: adapter.list_reservations()?,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DhcpReserveResult {
+    pub success: bool,
+    pub mac: String,
+    pub ip: String,
+}
+
+impl Display for DhcpReserveResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Added reservation: {} -> {}", self.mac, self.ip)
+    }
+}
+
 /// Add a DHCP reservation
 pub fn reserve(mac: &str, ip: IpAddr, hostname: Option<&str>, yes: bool, json: bool) -> Result<()> {
     let adapter = DnsmasqAdapter::new();
 
     if !yes && !confirm(&format!("Add reservation for {} -> {}?", mac, ip), yes) {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     adapter.add_reservation(mac, ip, hostname)?;
 
-    #[derive(Serialize)]
-    struct ReserveResult {
-        success: bool,
-        mac: String,
-        ip: String,
-    }
-
-    impl Display for ReserveResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Added reservation: {} -> {}", self.mac, self.ip)
-        }
-    }
-
-    let result = ReserveResult {
+    let result = DhcpReserveResult {
         success: true,
         mac: mac.to_string(),
         ip: ip.to_string(),
@@ -72,33 +111,321 @@ pub fn reserve(mac: &str, ip: IpAddr, hostname: Option<&str>, yes: bool, json: b
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DhcpUnreserveResult {
+    pub success: bool,
+    pub target: String,
+}
+
+impl Display for DhcpUnreserveResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Removed reservation for {}", self.target)
+    }
+}
+
 /// Remove a DHCP reservation
 pub fn unreserve(target: &str, yes: bool, json: bool) -> Result<()> {
     let adapter = DnsmasqAdapter::new();
 
     if !yes && !confirm(&format!("Remove reservation for {}?", target), yes) {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     adapter.remove_reservation(target)?;
 
-    #[derive(Serialize)]
-    struct UnreserveResult {
-        success: bool,
-        target: String,
+    let result = DhcpUnreserveResult {
+        success: true,
+        target: target.to_string(),
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DhcpPinResult {
+    pub success: bool,
+    pub mac: String,
+    pub ip: String,
+    pub hostname: Option<String>,
+    /// An `address=` record was created for the hostname
+    pub dns_record: bool,
+    pub warnings: Vec<String>,
+}
+
+impl Display for DhcpPinResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Pinned {} -> {}{}",
+            self.mac,
+            self.ip,
+            self.hostname
+                .as_ref()
+                .map(|h| format!(" ({})", h))
+                .unwrap_or_default()
+        )?;
+        if self.dns_record {
+            if let Some(hostname) = &self.hostname {
+                writeln!(f, "Added DNS record: {} -> {}", hostname, self.ip)?;
+            }
+        }
+        for warning in &self.warnings {
+            writeln!(f, "⚠ {}", warning)?;
+        }
+        Ok(())
     }
+}
 
-    impl Display for UnreserveResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Removed reservation for {}", self.target)
+/// Turn a device's current lease into a reservation for the same address
+pub fn pin(device: &str, hostname: Option<&str>, dns: bool, yes: bool, json: bool) -> Result<()> {
+    let adapter = DnsmasqAdapter::new();
+    let leases = adapter.list_leases()?;
+    let lease = leases
+        .iter()
+        .find(|l| {
+            l.mac.eq_ignore_ascii_case(device)
+                || l.ip == device
+                || l.hostname.as_deref() == Some(device)
+        })
+        .ok_or_else(|| ShannonError::NotFound(format!("Lease for '{}'", device)))?;
+    let ip: IpAddr = lease.ip.parse()?;
+    let hostname = hostname
+        .map(str::to_string)
+        .or_else(|| lease.hostname.clone());
+
+    let dns_name = if dns {
+        let name = hostname.as_deref().ok_or_else(|| {
+            ShannonError::InvalidInput(format!(
+                "{} sent no hostname; pass --hostname to create a DNS record",
+                lease.mac
+            ))
+        })?;
+        Some(name)
+    } else {
+        None
+    };
+    let create_record = check_pin(
+        &adapter.list_reservations()?,
+        &adapter.list_dns_entries()?,
+        &lease.mac,
+        ip,
+        dns_name,
+    )?;
+
+    let mut warnings = Vec::new();
+    if !lease.active {
+        warnings.push(format!("The lease for {} has expired", lease.ip));
+    }
+    if let Some((start, end)) = dynamic_range_of(&adapter.dynamic_ranges()?, ip) {
+        warnings.push(format!(
+            "{} is inside the dynamic range {}-{}; dnsmasq won't lease it to others, \
+             but the pool is one address smaller",
+            ip, start, end
+        ));
+    }
+
+    if !yes && !confirm(&format!("Pin {} -> {}?", lease.mac, ip), yes) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    adapter.add_reservation(&lease.mac, ip, hostname.as_deref())?;
+    if create_record {
+        if let Some(name) = &hostname {
+            adapter.add_dns_record(name, address_type(ip), &ip.to_string())?;
         }
     }
 
-    let result = UnreserveResult {
+    let result = DhcpPinResult {
         success: true,
-        target: target.to_string(),
+        mac: lease.mac.clone(),
+        ip: lease.ip.clone(),
+        hostname,
+        dns_record: create_record,
+        warnings,
     };
 
     print_output(&result, json);
     Ok(())
 }
+
+/// Active leases in the dynamic pool
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PoolUsage {
+    pub leased: u32,
+    pub size: u32,
+    pub percent: f32,
+}
+
+impl Display for PoolUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} leased ({:.0}%)",
+            self.leased, self.size, self.percent
+        )
+    }
+}
+
+fn usage(scope: &DhcpScope, leases: &[DhcpLease]) -> Option<PoolUsage> {
+    let size = scope.size()?;
+    let leased = leases
+        .iter()
+        .filter(|l| l.active && l.ip.parse().is_ok_and(|ip| scope.contains(ip)))
+        .count() as u32;
+    Some(PoolUsage {
+        leased,
+        size,
+        percent: leased as f32 * 100.0 / size as f32,
+    })
+}
+
+/// Pool utilisation for `shannon status` (None without a dynamic range)
+pub fn pool_usage() -> Result<Option<PoolUsage>> {
+    let adapter = DnsmasqAdapter::new();
+    Ok(usage(&adapter.scope()?, &adapter.list_leases()?))
+}
+
+/// Reservations whose address dnsmasq could also hand out dynamically
+fn scope_warnings(scope: &DhcpScope, reservations: &[DhcpReservation]) -> Vec<String> {
+    reservations
+        .iter()
+        .filter(|r| r.ip.parse().is_ok_and(|ip| scope.contains(ip)))
+        .map(|r| {
+            format!(
+                "Reservation {} ({}) is inside the dynamic pool",
+                r.hostname.as_deref().unwrap_or(&r.mac),
+                r.ip
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DhcpScopeResult {
+    pub success: bool,
+    pub scope: DhcpScope,
+    pub utilisation: Option<PoolUsage>,
+    pub warnings: Vec<String>,
+}
+
+impl Display for DhcpScopeResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scope = &self.scope;
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        writeln!(f, "DHCP Scope")?;
+        writeln!(f, "==========")?;
+        match (scope.range_start, scope.range_end, scope.size()) {
+            (Some(start), Some(end), Some(size)) => {
+                writeln!(f, "Pool:         {} - {} ({} addresses)", start, end, size)?
+            }
+            _ => writeln!(f, "Pool:         none (static only)")?,
+        }
+        writeln!(
+            f,
+            "Netmask:      {}",
+            or_dash(scope.netmask.map(|m| m.to_string()))
+        )?;
+        writeln!(
+            f,
+            "Lease time:   {}",
+            scope.lease_time.as_deref().unwrap_or("1h (default)")
+        )?;
+        writeln!(
+            f,
+            "Gateway:      {}",
+            or_dash(scope.gateway.map(|g| g.to_string()))
+        )?;
+        let dns: Vec<String> = scope.dns_servers.iter().map(|s| s.to_string()).collect();
+        writeln!(
+            f,
+            "DNS:          {}",
+            if dns.is_empty() {
+                "this router (default)".to_string()
+            } else {
+                dns.join(", ")
+            }
+        )?;
+        writeln!(f, "Domain:       {}", or_dash(scope.domain.clone()))?;
+        let ipv6 = match (&scope.ipv6_mode, &scope.ipv6_interface) {
+            (Some(mode), Some(interface)) => format!("{} on {}", mode, interface),
+            (Some(mode), None) => mode.clone(),
+            (None, _) => "off".to_string(),
+        };
+        writeln!(f, "IPv6:         {}", ipv6)?;
+        writeln!(
+            f,
+            "Utilisation:  {}",
+            or_dash(self.utilisation.as_ref().map(|u| u.to_string()))
+        )?;
+        for warning in &self.warnings {
+            writeln!(f, "⚠ {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+fn scope_result(adapter: &DnsmasqAdapter, scope: DhcpScope) -> Result<DhcpScopeResult> {
+    Ok(DhcpScopeResult {
+        success: true,
+        utilisation: usage(&scope, &adapter.list_leases()?),
+        warnings: scope_warnings(&scope, &adapter.list_reservations()?),
+        scope,
+    })
+}
+
+pub fn scope_show(json: bool) -> Result<()> {
+    let adapter = DnsmasqAdapter::new();
+    let result = scope_result(&adapter, adapter.scope()?)?;
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Change scope settings; other lines in dnsmasq.conf are left as they are
+pub fn scope_set(change: ScopeChange, yes: bool, json: bool) -> Result<()> {
+    if let Some(domain) = &change.domain {
+        let valid = domain.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+        if !valid {
+            return Err(ShannonError::InvalidInput(format!("Invalid domain '{}'", domain)).into());
+        }
+    }
+
+    let adapter = DnsmasqAdapter::new();
+    if let Some(gateway) = change.gateway {
+        let scope = adapter.scope()?;
+        let in_pool = |ip: Ipv4Addr| {
+            change.range.map_or(scope.contains(ip), |(start, end)| {
+                (start..=end).contains(&ip)
+            })
+        };
+        if in_pool(gateway) {
+            return Err(ShannonError::InvalidInput(format!(
+                "Gateway {} is inside the dynamic pool",
+                gateway
+            ))
+            .into());
+        }
+    }
+
+    if !yes && !confirm("Change the DHCP scope and restart dnsmasq?", yes) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    // RA hands every LAN host a global address, reachable unless IPv6
+    // forwarding is default-deny
+    if change
+        .ipv6_mode
+        .as_deref()
+        .is_some_and(|mode| mode != "off")
+    {
+        NftablesAdapter::new().ensure_ipv6_forward_guard()?;
+    }
+    let scope = adapter.set_scope(&change)?;
+    let result = scope_result(&adapter, scope)?;
+
+    print_output(&result, json);
+    Ok(())
+}
@@ -1,18 +1,24 @@
 //! DNS record management
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::fmt::Display;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use tracing::warn;
 
-use crate::adapters::dnsmasq::{DnsmasqAdapter, DnsRecord};
+use crate::adapters::adguard;
+use crate::adapters::dnsmasq::{address_type, DnsRecord, DnsmasqAdapter};
+use crate::adapters::resolver;
+use crate::error::ShannonError;
 use crate::output::{print_output, TableOutput, TableRow};
 
 impl TableRow for DnsRecord {
     fn cells(&self) -> Vec<String> {
         vec![
             self.hostname.clone(),
-            self.ip.to_string(),
+            self.record_type.clone(),
+            self.value.clone(),
             self.source.clone(),
         ]
     }
@@ -27,7 +33,8 @@ pub fn list(json: bool) -> Result<()> {
     let output = TableOutput {
         headers: vec![
             "Hostname".to_string(),
-            "IP".to_string(),
+            "Type".to_string(),
+            "Value".to_string(),
             "Source".to_string(),
         ],
         rows: records,
@@ -37,54 +44,871 @@ pub fn list(json: bool) -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsAddResult {
+    pub success: bool,
+    /// The record, followed by its automatic PTR record if one was added
+    pub records: Vec<DnsRecord>,
+}
+
+impl Display for DnsAddResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for record in &self.records {
+            writeln!(
+                f,
+                "Added {} record: {} -> {}",
+                record.record_type, record.hostname, record.value
+            )?;
+        }
+        Ok(())
+    }
+}
+
 /// Add a DNS record
-pub fn add(hostname: &str, ip: IpAddr, json: bool) -> Result<()> {
+///
+/// Without `--type`, the value must be an address and the record is A or
+/// AAAA accordingly.
+pub fn add(hostname: &str, value: &str, record_type: Option<&str>, json: bool) -> Result<()> {
+    let record_type = match record_type {
+        Some(record_type) => record_type.to_string(),
+        None => match value.parse::<IpAddr>() {
+            Ok(ip) => address_type(ip).to_string(),
+            Err(_) => {
+                return Err(ShannonError::InvalidInput(format!(
+                    "{} is not an IP address; pass --type for other records",
+                    value
+                ))
+                .into())
+            }
+        },
+    };
+
     let adapter = DnsmasqAdapter::new();
-    adapter.add_dns_entry(hostname, ip)?;
+    let records = adapter.add_dns_record(hostname, &record_type, value)?;
+
+    let result = DnsAddResult {
+        success: true,
+        records,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsRemoveResult {
+    pub success: bool,
+    pub hostname: String,
+}
+
+impl Display for DnsRemoveResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Removed DNS record: {}", self.hostname)
+    }
+}
+
+/// Remove all records for a name, and PTR records pointing at it
+pub fn remove(hostname: &str, json: bool) -> Result<()> {
+    let adapter = DnsmasqAdapter::new();
+    adapter.remove_dns_entry(hostname)?;
+
+    let result = DnsRemoveResult {
+        success: true,
+        hostname: hostname.to_string(),
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
 
-    #[derive(Serialize)]
-    struct AddResult {
-        success: bool,
-        hostname: String,
-        ip: String,
+/// A domain resolved by its own upstream instead of the general ones
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsForward {
+    pub domain: String,
+    /// `ip` or `ip:port`, or "local" for names only dnsmasq answers
+    pub upstream: String,
+    /// Has a `server=` / `local=` line in dnsmasq
+    pub dnsmasq: bool,
+    /// Has a `[/domain/]` upstream in AdGuard Home
+    pub adguard: bool,
+}
+
+impl TableRow for DnsForward {
+    fn cells(&self) -> Vec<String> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+        vec![
+            self.domain.clone(),
+            self.upstream.clone(),
+            yes_no(self.dnsmasq),
+            yes_no(self.adguard),
+        ]
     }
+}
 
-    impl Display for AddResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Added DNS record: {} -> {}", self.hostname, self.ip)
+/// Upstream as written in AdGuard (`ip`, `ip:port`, `[v6]:port`)
+fn upstream_string(addr: SocketAddr) -> String {
+    if addr.port() == 53 {
+        addr.ip().to_string()
+    } else {
+        addr.to_string()
+    }
+}
+
+/// dnsmasq and AdGuard forwards merged by domain
+fn forwards(adapter: &DnsmasqAdapter) -> Result<Vec<DnsForward>> {
+    // What AdGuard sends "local" domains to
+    let local = adapter
+        .dns_port()
+        .ok()
+        .map(|port| format!("127.0.0.1:{}", port));
+
+    let mut forwards: Vec<DnsForward> = Vec::new();
+    for (domain, upstream) in adapter.list_forwards()? {
+        forwards.push(DnsForward {
+            domain,
+            upstream: upstream.map_or("local".to_string(), upstream_string),
+            dnsmasq: true,
+            adguard: false,
+        });
+    }
+
+    let info = adguard::dns_info().unwrap_or_else(|e| {
+        warn!("AdGuard upstreams unavailable: {:#}", e);
+        adguard::DnsInfo::default()
+    });
+    for (domains, upstream) in info
+        .upstream_dns
+        .iter()
+        .filter_map(|line| adguard::parse_domain_upstream(line))
+    {
+        for domain in domains {
+            match forwards.iter_mut().find(|f| f.domain == domain) {
+                Some(forward) => forward.adguard = true,
+                None => forwards.push(DnsForward {
+                    domain,
+                    upstream: if local.as_deref() == Some(upstream.as_str()) {
+                        "local".to_string()
+                    } else {
+                        upstream.clone()
+                    },
+                    dnsmasq: false,
+                    adguard: true,
+                }),
+            }
         }
     }
+    Ok(forwards)
+}
 
-    let result = AddResult {
+/// List per-domain upstreams
+pub fn forward_list(json: bool) -> Result<()> {
+    let adapter = DnsmasqAdapter::new();
+    let output = TableOutput {
+        headers: vec![
+            "Domain".to_string(),
+            "Upstream".to_string(),
+            "dnsmasq".to_string(),
+            "AdGuard".to_string(),
+        ],
+        rows: forwards(&adapter)?,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsForwardResult {
+    pub success: bool,
+    pub forward: DnsForward,
+    /// The upstream answered AdGuard's test query before it was added
+    pub checked: bool,
+}
+
+impl Display for DnsForwardResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Forwarding {} to {} (dnsmasq and AdGuard)",
+            self.forward.domain, self.forward.upstream
+        )?;
+        if self.checked {
+            write!(f, "; upstream answered")?;
+        }
+        Ok(())
+    }
+}
+
+/// Resolve a domain via its own upstream, in dnsmasq and in AdGuard (which
+/// sits in front and would otherwise send it to the general upstreams)
+pub fn forward_add(domain: &str, upstream: &str, no_check: bool, json: bool) -> Result<()> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    if domain.is_empty()
+        || domain.starts_with('.')
+        || !domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+    {
+        return Err(ShannonError::InvalidInput(format!("Invalid domain: {}", domain)).into());
+    }
+
+    let adapter = DnsmasqAdapter::new();
+    if forwards(&adapter)?.iter().any(|f| f.domain == domain) {
+        return Err(ShannonError::AlreadyExists(format!("DNS forward for {}", domain)).into());
+    }
+
+    // "local": dnsmasq answers from its own records, and AdGuard asks dnsmasq
+    let target: Option<SocketAddr> = match upstream {
+        "local" => None,
+        _ => Some(
+            upstream
+                .parse::<SocketAddr>()
+                .or_else(|_| upstream.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| {
+                    ShannonError::InvalidInput(format!(
+                        "Upstream must be ip, ip:port or local, got {}",
+                        upstream
+                    ))
+                })?,
+        ),
+    };
+    let adguard_upstream = match target {
+        Some(addr) => upstream_string(addr),
+        None => match adapter.dns_port()? {
+            0 => {
+                return Err(ShannonError::InvalidInput(
+                    "dnsmasq's DNS is off (port=0 in /etc/dnsmasq.conf); give it a port such as 5353 so AdGuard can forward local names to it".to_string(),
+                )
+                .into())
+            }
+            port => format!("127.0.0.1:{}", port),
+        },
+    };
+
+    if !no_check {
+        let bootstrap = adguard::dns_info()?.bootstrap_dns;
+        for (_, error) in
+            adguard::test_upstreams(std::slice::from_ref(&adguard_upstream), &bootstrap)?
+        {
+            if let Some(error) = error {
+                return Err(ShannonError::InvalidInput(format!(
+                    "{} did not answer: {}; pass --no-check to add it anyway",
+                    adguard_upstream, error
+                ))
+                .into());
+            }
+        }
+    }
+
+    // AdGuard first: its API is the likelier to fail, and its previous
+    // upstreams can be put back if dnsmasq then fails
+    let previous = adguard::dns_info()?.upstream_dns;
+    let mut upstreams = previous.clone();
+    upstreams.push(format!("[/{}/]{}", domain, adguard_upstream));
+    adguard::set_dns_config(Some(&upstreams), None)?;
+    if let Err(e) = adapter.add_forward(&domain, target) {
+        adguard::set_dns_config(Some(&previous), None)
+            .context("Failed to roll back AdGuard upstreams")?;
+        return Err(e);
+    }
+
+    let result = DnsForwardResult {
         success: true,
-        hostname: hostname.to_string(),
-        ip: ip.to_string(),
+        forward: DnsForward {
+            domain,
+            upstream: target.map_or("local".to_string(), upstream_string),
+            dnsmasq: true,
+            adguard: true,
+        },
+        checked: !no_check,
     };
 
     print_output(&result, json);
     Ok(())
 }
 
-/// Remove a DNS record
-pub fn remove(hostname: &str, json: bool) -> Result<()> {
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsForwardRemoveResult {
+    pub success: bool,
+    pub domain: String,
+}
+
+impl Display for DnsForwardRemoveResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Stopped forwarding {}", self.domain)
+    }
+}
+
+/// Stop forwarding a domain, in both dnsmasq and AdGuard
+pub fn forward_rm(domain: &str, json: bool) -> Result<()> {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
     let adapter = DnsmasqAdapter::new();
-    adapter.remove_dns_entry(hostname)?;
 
-    #[derive(Serialize)]
-    struct RemoveResult {
-        success: bool,
-        hostname: String,
+    let upstreams = adguard::dns_info()?.upstream_dns;
+    let remaining = adguard::without_domain(&upstreams, &domain);
+    let in_adguard = remaining != upstreams;
+    if in_adguard {
+        adguard::set_dns_config(Some(&remaining), None)?;
+    }
+    let in_dnsmasq = match adapter.remove_forward(&domain) {
+        Ok(found) => found,
+        Err(e) => {
+            if in_adguard {
+                adguard::set_dns_config(Some(&upstreams), None)
+                    .context("Failed to roll back AdGuard upstreams")?;
+            }
+            return Err(e);
+        }
+    };
+    if !in_dnsmasq && !in_adguard {
+        return Err(ShannonError::NotFound(format!("DNS forward for {}", domain)).into());
+    }
+
+    let result = DnsForwardRemoveResult {
+        success: true,
+        domain,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// An upstream or bootstrap server in AdGuard Home
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsUpstream {
+    pub upstream: String,
+    /// plain, TCP, DoH, DoH3, DoT, DoQ or DNSCrypt
+    pub protocol: String,
+    /// Only used to resolve the hostnames of encrypted upstreams
+    pub bootstrap: bool,
+}
+
+impl DnsUpstream {
+    fn new(upstream: &str, bootstrap: bool) -> Self {
+        DnsUpstream {
+            upstream: upstream.to_string(),
+            protocol: adguard::upstream_protocol(upstream).to_string(),
+            bootstrap,
+        }
+    }
+}
+
+impl TableRow for DnsUpstream {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.upstream.clone(),
+            self.protocol.clone(),
+            if self.bootstrap {
+                "bootstrap"
+            } else {
+                "upstream"
+            }
+            .to_string(),
+        ]
+    }
+}
+
+fn upstream_rows(info: &adguard::DnsInfo) -> Vec<DnsUpstream> {
+    let upstreams = info.general_upstreams();
+    let upstreams = upstreams.iter().map(|u| DnsUpstream::new(u, false));
+    let bootstrap = info.bootstrap_dns.iter().map(|u| DnsUpstream::new(u, true));
+    upstreams.chain(bootstrap).collect()
+}
+
+/// List AdGuard's upstreams and bootstrap servers
+pub fn upstream_list(json: bool) -> Result<()> {
+    let output = TableOutput {
+        headers: vec![
+            "Upstream".to_string(),
+            "Protocol".to_string(),
+            "Role".to_string(),
+        ],
+        rows: upstream_rows(&adguard::dns_info()?),
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsUpstreamSetResult {
+    pub success: bool,
+    pub upstreams: Vec<DnsUpstream>,
+    /// Every upstream answered AdGuard's test query before it was applied
+    pub checked: bool,
+}
+
+impl Display for DnsUpstreamSetResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "DNS upstreams:")?;
+        for upstream in &self.upstreams {
+            let role = if upstream.bootstrap {
+                ", bootstrap"
+            } else {
+                ""
+            };
+            writeln!(f, "  {} ({}{})", upstream.upstream, upstream.protocol, role)?;
+        }
+        if self.checked {
+            writeln!(f, "All answered")?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace AdGuard's upstreams and/or bootstrap servers. Per-domain
+/// upstreams from `dns forward` are kept.
+pub fn upstream_set(
+    upstreams: &[String],
+    bootstrap: Option<&[IpAddr]>,
+    no_check: bool,
+    json: bool,
+) -> Result<()> {
+    for upstream in upstreams {
+        if upstream.is_empty() || upstream.contains(char::is_whitespace) {
+            return Err(
+                ShannonError::InvalidInput(format!("Invalid upstream: {:?}", upstream)).into(),
+            );
+        }
+        if upstream.starts_with("[/") {
+            return Err(ShannonError::InvalidInput(
+                "Per-domain upstreams are managed with `shannon dns forward`".to_string(),
+            )
+            .into());
+        }
     }
 
-    impl Display for RemoveResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Removed DNS record: {}", self.hostname)
+    let info = adguard::dns_info()?;
+    let bootstrap: Option<Vec<String>> =
+        bootstrap.map(|servers| servers.iter().map(|ip| ip.to_string()).collect());
+    let new_bootstrap = bootstrap
+        .clone()
+        .unwrap_or_else(|| info.bootstrap_dns.clone());
+    let new_upstreams = if upstreams.is_empty() {
+        info.general_upstreams()
+    } else {
+        upstreams.to_vec()
+    };
+
+    if !no_check {
+        let failed: Vec<String> = adguard::test_upstreams(&new_upstreams, &new_bootstrap)?
+            .into_iter()
+            .filter_map(|(upstream, error)| Some(format!("{}: {}", upstream, error?)))
+            .collect();
+        if !failed.is_empty() {
+            return Err(ShannonError::InvalidInput(format!(
+                "Upstreams did not answer ({}); pass --no-check to apply anyway",
+                failed.join("; ")
+            ))
+            .into());
         }
     }
 
-    let result = RemoveResult {
+    // Per-domain lines go after the general upstreams, as AdGuard lists them
+    let lines: Option<Vec<String>> = (!upstreams.is_empty()).then(|| {
+        let per_domain = info
+            .upstream_dns
+            .iter()
+            .filter(|line| adguard::parse_domain_upstream(line).is_some())
+            .cloned();
+        new_upstreams.iter().cloned().chain(per_domain).collect()
+    });
+    adguard::set_dns_config(lines.as_deref(), bootstrap.as_deref())?;
+
+    let result = DnsUpstreamSetResult {
         success: true,
-        hostname: hostname.to_string(),
+        upstreams: upstream_rows(&adguard::DnsInfo {
+            upstream_dns: new_upstreams,
+            bootstrap_dns: new_bootstrap,
+        }),
+        checked: !no_check,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// How an upstream answered AdGuard's test queries
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct UpstreamHealth {
+    pub upstream: String,
+    pub protocol: String,
+    pub queries: u32,
+    pub failures: u32,
+    /// Median time of the answered queries, including connection setup;
+    /// None when not timed or nothing answered
+    pub latency_ms: Option<u64>,
+    /// Last error AdGuard reported
+    pub error: Option<String>,
+}
+
+impl TableRow for UpstreamHealth {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.upstream.clone(),
+            self.protocol.clone(),
+            self.latency_ms
+                .map(|ms| format!("{} ms", ms))
+                .unwrap_or_else(|| "-".to_string()),
+            format!("{}/{}", self.failures, self.queries),
+            self.error.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+/// Configured upstreams without testing them (`queries` is 0)
+pub fn configured_upstreams() -> Result<Vec<UpstreamHealth>> {
+    let info = adguard::dns_info()?;
+    Ok(info
+        .general_upstreams()
+        .into_iter()
+        .map(|upstream| UpstreamHealth {
+            protocol: adguard::upstream_protocol(&upstream).to_string(),
+            upstream,
+            queries: 0,
+            failures: 0,
+            latency_ms: None,
+            error: None,
+        })
+        .collect())
+}
+
+/// Test every upstream once, all at the same time (for status)
+pub fn check_upstreams() -> Result<Vec<UpstreamHealth>> {
+    let info = adguard::dns_info()?;
+    Ok(
+        adguard::test_upstreams(&info.general_upstreams(), &info.bootstrap_dns)?
+            .into_iter()
+            .map(|(upstream, error)| UpstreamHealth {
+                protocol: adguard::upstream_protocol(&upstream).to_string(),
+                upstream,
+                queries: 1,
+                failures: u32::from(error.is_some()),
+                latency_ms: None,
+                error,
+            })
+            .collect(),
+    )
+}
+
+/// Benchmark each upstream with `count` test queries, one at a time
+pub fn upstream_test(count: u32, json: bool) -> Result<()> {
+    let info = adguard::dns_info()?;
+    let mut rows = Vec::new();
+    for upstream in info.general_upstreams() {
+        let mut times = Vec::new();
+        let mut failures = 0;
+        let mut last_error = None;
+        for _ in 0..count {
+            match adguard::time_upstream(&upstream, &info.bootstrap_dns)? {
+                (None, elapsed) => times.push(elapsed.as_millis() as u64),
+                (Some(error), _) => {
+                    failures += 1;
+                    last_error = Some(error);
+                }
+            }
+        }
+        times.sort_unstable();
+        rows.push(UpstreamHealth {
+            protocol: adguard::upstream_protocol(&upstream).to_string(),
+            upstream,
+            queries: count,
+            failures,
+            latency_ms: times.get(times.len() / 2).copied(),
+            error: last_error,
+        });
+    }
+
+    let output = TableOutput {
+        headers: vec![
+            "Upstream".to_string(),
+            "Protocol".to_string(),
+            "Latency".to_string(),
+            "Failed".to_string(),
+            "Error".to_string(),
+        ],
+        rows,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+/// What one resolver answered for the tested name
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsLayerAnswer {
+    /// dnsmasq, AdGuard, forward (the domain's own upstream) or upstream
+    pub layer: String,
+    pub server: String,
+    /// Response code, e.g. NOERROR or NXDOMAIN; None when not answered
+    pub status: Option<String>,
+    /// Answer section as `TYPE data`
+    pub answers: Vec<String>,
+    pub latency_ms: Option<u64>,
+    /// An answer from dnsmasq or AdGuard matches one of dnsmasq's own
+    /// records
+    pub local: bool,
+    /// Why the server wasn't queried or didn't answer
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DnsTestResult {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub client: Option<IpAddr>,
+    /// dnsmasq records (`address=`, `cname=`, ...) that cover the name
+    pub local_records: Vec<DnsRecord>,
+    /// Per-domain upstream from `dns forward`, if the name is under one
+    pub forward: Option<DnsForward>,
+    pub layers: Vec<DnsLayerAnswer>,
+    /// AdGuard's filtering verdict, with the client's settings when given
+    pub filter: Option<adguard::FilterCheck>,
+    /// The client's last query for the name in AdGuard's query log
+    pub last_query: Option<adguard::LoggedQuery>,
+}
+
+impl Display for DnsTestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.name, self.record_type)?;
+        if let Some(client) = self.client {
+            write!(f, " for {}", client)?;
+        }
+        writeln!(f)?;
+        if !self.local_records.is_empty() {
+            writeln!(f, "Local records:")?;
+            for record in &self.local_records {
+                writeln!(
+                    f,
+                    "  {} {} {} ({})",
+                    record.hostname, record.record_type, record.value, record.source
+                )?;
+            }
+        }
+        if let Some(forward) = &self.forward {
+            writeln!(f, "Forwarded: {} -> {}", forward.domain, forward.upstream)?;
+        }
+
+        writeln!(f)?;
+        let width = self
+            .layers
+            .iter()
+            .map(|l| l.server.len())
+            .max()
+            .unwrap_or(0);
+        for layer in &self.layers {
+            let answer = match (&layer.status, &layer.error) {
+                (_, Some(error)) => error.clone(),
+                (Some(status), None) if layer.answers.is_empty() => status.clone(),
+                _ => layer.answers.join(", "),
+            };
+            let latency = layer
+                .latency_ms
+                .map(|ms| format!("{} ms", ms))
+                .unwrap_or_else(|| "-".to_string());
+            let local = if layer.local { " (local record)" } else { "" };
+            writeln!(
+                f,
+                "  {:<8} {:<width$} {:>6}  {}{}",
+                layer.layer,
+                layer.server,
+                latency,
+                answer,
+                local,
+                width = width
+            )?;
+        }
+
+        if let Some(filter) = &self.filter {
+            writeln!(f)?;
+            if filter.blocked {
+                writeln!(f, "Filtering: blocked ({})", filter.reason)?;
+            } else {
+                writeln!(f, "Filtering: not blocked ({})", filter.reason)?;
+            }
+            for rule in &filter.rules {
+                writeln!(f, "  {}", rule)?;
+            }
+        }
+        if let Some(client) = self.client {
+            match &self.last_query {
+                Some(query) => {
+                    writeln!(
+                        f,
+                        "Last query from {}: {} ({})",
+                        client, query.time, query.reason
+                    )?;
+                    if !query.answers.is_empty() {
+                        writeln!(f, "  {}", query.answers.join(", "))?;
+                    }
+                    let source = match &query.upstream {
+                        _ if query.cached => "cache".to_string(),
+                        Some(upstream) => upstream.clone(),
+                        None => "AdGuard".to_string(),
+                    };
+                    let elapsed = query
+                        .elapsed_ms
+                        .map(|ms| format!(" in {:.1} ms", ms))
+                        .unwrap_or_default();
+                    writeln!(f, "  answered by {}{}", source, elapsed)?;
+                }
+                None => writeln!(f, "No query from {} in AdGuard's query log", client)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Does a dnsmasq record answer for `name`? `address=/x/` covers x and
+/// every name under it, `*.x` only the names under it.
+fn record_covers(record: &DnsRecord, name: &str) -> bool {
+    let hostname = record.hostname.to_ascii_lowercase();
+    if let Some(domain) = hostname.strip_prefix("*.") {
+        return name.ends_with(&format!(".{}", domain));
+    }
+    name == hostname
+        || (matches!(record.record_type.as_str(), "A" | "AAAA")
+            && name.ends_with(&format!(".{}", hostname)))
+}
+
+/// Is a dig answer (`TYPE data`) one of the local records?
+fn is_local(answer: &str, records: &[DnsRecord]) -> bool {
+    let Some((rtype, data)) = answer.split_once(' ') else {
+        return false;
+    };
+    let data = data.trim_matches('"').trim_end_matches('.');
+    records.iter().any(|record| {
+        record.record_type.eq_ignore_ascii_case(rtype)
+            && record
+                .value
+                .trim_end_matches('.')
+                .eq_ignore_ascii_case(data)
+    })
+}
+
+/// Query one layer; `dig` is the reason instead when dig can't reach it
+fn query_layer(
+    layer: &str,
+    server: &str,
+    dig: Result<String, String>,
+    name: &str,
+    qtype: &str,
+    local_records: &[DnsRecord],
+) -> DnsLayerAnswer {
+    let mut answer = DnsLayerAnswer {
+        layer: layer.to_string(),
+        server: server.to_string(),
+        status: None,
+        answers: Vec::new(),
+        latency_ms: None,
+        local: false,
+        error: None,
+    };
+    match dig.and_then(|dig| resolver::query(&dig, name, qtype).map_err(|e| format!("{:#}", e))) {
+        Ok(dig) => {
+            answer.local = dig.records.iter().any(|r| is_local(r, local_records));
+            answer.status = Some(dig.status);
+            answer.answers = dig.records;
+            answer.latency_ms = dig.latency_ms;
+        }
+        Err(error) => answer.error = Some(error),
+    }
+    answer
+}
+
+/// Resolve a name at each layer separately: dnsmasq, AdGuard Home, the
+/// domain's forward upstream and each general upstream
+pub fn test(name: &str, qtype: &str, client: Option<IpAddr>, json: bool) -> Result<()> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    resolver::check_name(&name)?;
+    resolver::ensure_dig()?;
+    let qtype = qtype.to_ascii_uppercase();
+    let adapter = DnsmasqAdapter::new();
+
+    let local_records: Vec<DnsRecord> = adapter
+        .list_dns_entries()?
+        .into_iter()
+        .filter(|record| record_covers(record, &name))
+        .collect();
+    let forward = forwards(&adapter)?
+        .into_iter()
+        .filter(|f| name == f.domain || name.ends_with(&format!(".{}", f.domain)))
+        .max_by_key(|f| f.domain.len());
+
+    let mut layers = Vec::new();
+    let dnsmasq = match adapter.dns_port() {
+        Ok(0) => Err("DNS disabled (port=0)".to_string()),
+        Ok(port) => Ok(port),
+        Err(e) => Err(format!("{:#}", e)),
+    };
+    let server = dnsmasq
+        .as_ref()
+        .map(|port| format!("127.0.0.1:{}", port))
+        .unwrap_or_else(|_| "127.0.0.1".to_string());
+    let dig = dnsmasq.map(|port| format!("@127.0.0.1 -p {}", port));
+    layers.push(query_layer(
+        "dnsmasq",
+        &server,
+        dig,
+        &name,
+        &qtype,
+        &local_records,
+    ));
+    layers.push(query_layer(
+        "AdGuard",
+        "127.0.0.1:53",
+        Ok("@127.0.0.1 -p 53".to_string()),
+        &name,
+        &qtype,
+        &local_records,
+    ));
+
+    let unsupported = |upstream: &str| {
+        format!(
+            "dig can't query {} upstreams",
+            adguard::upstream_protocol(upstream)
+        )
+    };
+    if let Some(forward) = forward.as_ref().filter(|f| f.upstream != "local") {
+        let dig =
+            resolver::dig_server(&forward.upstream).ok_or_else(|| unsupported(&forward.upstream));
+        layers.push(query_layer(
+            "forward",
+            &forward.upstream,
+            dig,
+            &name,
+            &qtype,
+            &[],
+        ));
+    }
+    match adguard::dns_info() {
+        Ok(info) => {
+            for upstream in info.general_upstreams() {
+                let dig = resolver::dig_server(&upstream).ok_or_else(|| unsupported(&upstream));
+                layers.push(query_layer("upstream", &upstream, dig, &name, &qtype, &[]));
+            }
+        }
+        Err(e) => warn!("AdGuard upstreams unavailable: {:#}", e),
+    }
+
+    let filter = adguard::check_host(&name, client, &qtype)
+        .map_err(|e| warn!("AdGuard filter check failed: {:#}", e))
+        .ok();
+    let last_query = match client {
+        Some(client) => adguard::last_query(client, &name)?,
+        None => None,
+    };
+
+    let result = DnsTestResult {
+        name,
+        record_type: qtype,
+        client,
+        local_records,
+        forward,
+        layers,
+        filter,
+        last_query,
     };
 
     print_output(&result, json);
@@ -1,17 +1,25 @@
 //! Firewall and port forwarding management
 
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::fmt::Display;
+use std::net::SocketAddr;
 
 use crate::adapters::nftables::{NftablesAdapter, PortForward};
+use crate::error::ShannonError;
 use crate::output::{confirm, print_output, TableOutput, TableRow};
 
 impl TableRow for PortForward {
     fn cells(&self) -> Vec<String> {
+        let internal = if self.internal_ip.contains(':') {
+            format!("[{}]:{}", self.internal_ip, self.internal_port)
+        } else {
+            format!("{}:{}", self.internal_ip, self.internal_port)
+        };
         vec![
             self.external_port.to_string(),
-            format!("{}:{}", self.internal_ip, self.internal_port),
+            internal,
             self.protocol.clone(),
             self.comment.clone().unwrap_or_default(),
         ]
@@ -38,6 +46,31 @@ pub fn list(json: bool) -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FwForwardResult {
+    pub success: bool,
+    pub external_port: u16,
+    pub internal: String,
+    pub protocol: String,
+    /// IPv6 target: the port was opened in ip6tables instead of DNATed
+    pub pinhole: bool,
+}
+
+impl Display for FwForwardResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.pinhole {
+            "IPv6 pinhole"
+        } else {
+            "port forward"
+        };
+        write!(
+            f,
+            "Added {}: {} -> {} ({})",
+            kind, self.external_port, self.internal, self.protocol
+        )
+    }
+}
+
 /// Add a port forward
 pub fn forward(
     external_port: u16,
@@ -48,25 +81,30 @@ pub fn forward(
 ) -> Result<()> {
     let adapter = NftablesAdapter::new();
 
-    // Parse internal target (ip:port)
-    let parts: Vec<&str> = internal.split(':').collect();
-    if parts.len() != 2 {
-        anyhow::bail!("Internal target must be in ip:port format");
+    // Parse internal target (ip:port, or [ipv6]:port)
+    let target: SocketAddr = internal.parse().map_err(|_| {
+        ShannonError::InvalidInput(
+            "Internal target must be in ip:port format ([ip]:port for IPv6)".to_string(),
+        )
+    })?;
+    let internal_ip = target.ip().to_string();
+    let internal_port = target.port();
+    let pinhole = target.is_ipv6();
+    if pinhole && internal_port != external_port {
+        return Err(ShannonError::InvalidInput(format!(
+            "IPv6 has no NAT: a pinhole opens port {} on the host itself, so the external port must match",
+            internal_port
+        ))
+        .into());
     }
 
-    let internal_ip = parts[0].to_string();
-    let internal_port: u16 = parts[1].parse()?;
-
     if !yes
         && !confirm(
-            &format!(
-                "Forward port {} -> {}:{} ({})?",
-                external_port, internal_ip, internal_port, proto
-            ),
+            &format!("Forward port {} -> {} ({})?", external_port, target, proto),
             yes,
         )
     {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     let rule = PortForward {
@@ -77,60 +115,47 @@ pub fn forward(
         comment: None,
     };
 
-    adapter.add_port_forward(&rule)?;
-
-    #[derive(Serialize)]
-    struct ForwardResult {
-        success: bool,
-        external_port: u16,
-        internal: String,
-        protocol: String,
+    if pinhole {
+        adapter.add_pinhole(&rule)?;
+    } else {
+        adapter.add_port_forward(&rule)?;
     }
 
-    impl Display for ForwardResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(
-                f,
-                "Added port forward: {} -> {} ({})",
-                self.external_port, self.internal, self.protocol
-            )
-        }
-    }
-
-    let result = ForwardResult {
+    let result = FwForwardResult {
         success: true,
         external_port,
-        internal: internal.to_string(),
+        internal: target.to_string(),
         protocol: proto.to_string(),
+        pinhole,
     };
 
     print_output(&result, json);
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FwUnforwardResult {
+    pub success: bool,
+    pub external_port: u16,
+}
+
+impl Display for FwUnforwardResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Removed forward for port {}", self.external_port)
+    }
+}
+
 /// Remove a port forward
 pub fn unforward(external_port: u16, yes: bool, json: bool) -> Result<()> {
     let adapter = NftablesAdapter::new();
 
     if !yes && !confirm(&format!("Remove forward for port {}?", external_port), yes) {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     adapter.remove_port_forward(external_port)?;
 
-    #[derive(Serialize)]
-    struct UnforwardResult {
-        success: bool,
-        external_port: u16,
-    }
-
-    impl Display for UnforwardResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Removed forward for port {}", self.external_port)
-        }
-    }
-
-    let result = UnforwardResult {
+    let result = FwUnforwardResult {
         success: true,
         external_port,
     };
@@ -139,29 +164,29 @@ pub fn unforward(external_port: u16, yes: bool, json: bool) -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FwBlockResult {
+    pub success: bool,
+    pub target: String,
+}
+
+impl Display for FwBlockResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Blocked {}", self.target)
+    }
+}
+
 /// Block an IP
 pub fn block(target: &str, yes: bool, json: bool) -> Result<()> {
     let adapter = NftablesAdapter::new();
 
     if !yes && !confirm(&format!("Block {}?", target), yes) {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     adapter.block_ip(target)?;
 
-    #[derive(Serialize)]
-    struct BlockResult {
-        success: bool,
-        target: String,
-    }
-
-    impl Display for BlockResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Blocked {}", self.target)
-        }
-    }
-
-    let result = BlockResult {
+    let result = FwBlockResult {
         success: true,
         target: target.to_string(),
     };
@@ -170,29 +195,29 @@ pub fn block(target: &str, yes: bool, json: bool) -> Result<()> {
     Ok(())
 }
 
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FwUnblockResult {
+    pub success: bool,
+    pub target: String,
+}
+
+impl Display for FwUnblockResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unblocked {}", self.target)
+    }
+}
+
 /// Unblock an IP
 pub fn unblock(target: &str, yes: bool, json: bool) -> Result<()> {
     let adapter = NftablesAdapter::new();
 
     if !yes && !confirm(&format!("Unblock {}?", target), yes) {
-        anyhow::bail!("Operation cancelled");
+        return Err(ShannonError::Cancelled.into());
     }
 
     adapter.unblock_ip(target)?;
 
-    #[derive(Serialize)]
-    struct UnblockResult {
-        success: bool,
-        target: String,
-    }
-
-    impl Display for UnblockResult {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "Unblocked {}", self.target)
-        }
-    }
-
-    let result = UnblockResult {
+    let result = FwUnblockResult {
         success: true,
         target: target.to_string(),
     };
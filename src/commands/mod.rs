@@ -1,7 +1,11 @@
+pub mod audit;
 pub mod ddns;
+pub mod device;
 pub mod dhcp;
 pub mod dns;
 pub mod fw;
+pub mod schema;
 pub mod sec;
+pub mod snapshot;
 pub mod status;
 pub mod vpn;
@@ -0,0 +1,45 @@
+//! JSON Schema export of `--json` outputs
+
+use anyhow::Result;
+use schemars::schema::RootSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::error::ShannonError;
+use crate::output::print_output;
+use crate::schema::{command_schemas, SCHEMA_VERSION};
+
+#[derive(Debug, Serialize)]
+pub struct SchemaExport {
+    pub version: u32,
+    pub schemas: BTreeMap<&'static str, RootSchema>,
+}
+
+impl Display for SchemaExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Schemas are JSON documents either way
+        let json = serde_json::to_string_pretty(self).map_err(|_| std::fmt::Error)?;
+        write!(f, "{}", json)
+    }
+}
+
+/// Show the output schema of one command, or of all when `command` is empty
+pub fn show(command: &str, json: bool) -> Result<()> {
+    let mut schemas = command_schemas();
+
+    if !command.is_empty() {
+        let entry = schemas
+            .remove_entry(command)
+            .ok_or_else(|| ShannonError::NotFound(format!("Schema for '{}'", command)))?;
+        schemas = BTreeMap::from([entry]);
+    }
+
+    let result = SchemaExport {
+        version: SCHEMA_VERSION,
+        schemas,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
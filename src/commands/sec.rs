@@ -1,18 +1,28 @@
 //! Security analysis and status commands
 
 use anyhow::Result;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use ipnetwork::IpNetwork;
+use schemars::JsonSchema;
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
+use std::net::IpAddr;
 
-use crate::adapters::{adguard, crowdsec, wireguard};
-use crate::output::print_output;
+use crate::adapters::dnsmasq::{DhcpLease, DhcpReservation, DnsmasqAdapter};
+use crate::adapters::nftables::NftablesAdapter;
+use crate::adapters::{adguard, crowdsec, geoip, system, wireguard};
+use crate::error::ShannonError;
+use crate::output::{confirm, print_output, sparkline, TableOutput, TableRow};
+use crate::traffic;
 
 /// Combined security stack status
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct SecurityStatus {
     pub adguard: adguard::AdguardStatus,
     pub crowdsec: crowdsec::CrowdsecStatus,
-    pub wireguard: wireguard::WireguardStatus,
+    /// One entry per WireGuard interface
+    pub wireguard: Vec<wireguard::WireguardStatus>,
 }
 
 impl Display for SecurityStatus {
@@ -21,7 +31,9 @@ impl Display for SecurityStatus {
         writeln!(f, "=====================")?;
         write!(f, "{}", self.adguard)?;
         write!(f, "{}", self.crowdsec)?;
-        write!(f, "{}", self.wireguard)?;
+        for interface in &self.wireguard {
+            write!(f, "{}", interface)?;
+        }
         Ok(())
     }
 }
@@ -31,7 +43,7 @@ pub fn status(json: bool) -> Result<()> {
     let result = SecurityStatus {
         adguard: adguard::get_status()?,
         crowdsec: crowdsec::get_status()?,
-        wireguard: wireguard::get_status()?,
+        wireguard: wireguard::get_all_status()?,
     };
 
     print_output(&result, json);
@@ -51,7 +63,7 @@ pub fn blocks(json: bool) -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct BlocksResult {
     pub count: u32,
     pub decisions: Vec<crowdsec::CrowdsecDecision>,
@@ -74,8 +86,879 @@ impl Display for BlocksResult {
     }
 }
 
+impl TableRow for crowdsec::CrowdsecAlert {
+    fn cells(&self) -> Vec<String> {
+        let now = Utc::now();
+        let decision = match self.decisions.first() {
+            Some(d) => match d.until {
+                Some(until) if until > now => {
+                    let left = until - now;
+                    format!(
+                        "{} ({}h{:02}m left)",
+                        d.action,
+                        left.num_hours(),
+                        left.num_minutes() % 60
+                    )
+                }
+                Some(_) => format!("{} (expired)", d.action),
+                None => d.action.clone(),
+            },
+            None => "-".to_string(),
+        };
+        vec![
+            self.started_at
+                .map(|t| {
+                    t.with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_default(),
+            self.source.clone(),
+            self.country.clone().unwrap_or_else(|| "-".to_string()),
+            match (&self.as_number, &self.as_name) {
+                (Some(number), Some(name)) => {
+                    format!("AS{} {}", number, name.chars().take(24).collect::<String>())
+                }
+                (Some(number), None) => format!("AS{}", number),
+                _ => "-".to_string(),
+            },
+            self.scenario.clone(),
+            self.events_count.to_string(),
+            decision,
+        ]
+    }
+}
+
+/// Show CrowdSec alerts from the last `since` (default 24h), newest first
+pub fn alerts(
+    since: Option<Duration>,
+    scenario: Option<String>,
+    ip: Option<IpAddr>,
+    json: bool,
+) -> Result<()> {
+    let query = crowdsec::AlertQuery {
+        since: Some(since.unwrap_or(Duration::hours(24))),
+        scenario,
+        ip: ip.map(|ip| ip.to_string()),
+        active_only: false,
+    };
+    let rows = crowdsec::LapiClient::connect()?.alerts(&query)?;
+
+    let output = TableOutput {
+        headers: vec![
+            "Time".to_string(),
+            "Source".to_string(),
+            "Country".to_string(),
+            "AS".to_string(),
+            "Scenario".to_string(),
+            "Events".to_string(),
+            "Decision".to_string(),
+        ],
+        rows,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+/// Alerts, events and distinct sources sharing one scenario or country
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AttackCount {
+    pub key: String,
+    pub alerts: u32,
+    pub events: u64,
+    pub sources: u32,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct HourCount {
+    /// Start of the hour
+    pub hour: DateTime<Utc>,
+    pub alerts: u32,
+}
+
+/// CrowdSec alerts aggregated over a window
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SecStats {
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub alerts: u32,
+    pub events: u64,
+    /// Distinct source IPs
+    pub sources: u32,
+    /// Busiest first
+    pub by_scenario: Vec<AttackCount>,
+    /// Busiest first; sources GeoIP doesn't know are counted under "unknown"
+    pub by_country: Vec<AttackCount>,
+    /// One entry per hour of the window, oldest first
+    pub by_hour: Vec<HourCount>,
+}
+
+impl SecStats {
+    /// Aggregate alerts started between `since` and `until`
+    pub fn from_alerts(
+        alerts: &[crowdsec::CrowdsecAlert],
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Self {
+        let first_hour = since.duration_trunc(Duration::hours(1)).unwrap_or(since);
+        let mut by_hour: Vec<HourCount> =
+            std::iter::successors(Some(first_hour), |h| Some(*h + Duration::hours(1)))
+                .take_while(|h| *h <= until)
+                .map(|hour| HourCount { hour, alerts: 0 })
+                .collect();
+
+        let mut by_scenario: HashMap<&str, (u32, u64, HashSet<&str>)> = HashMap::new();
+        let mut by_country: HashMap<&str, (u32, u64, HashSet<&str>)> = HashMap::new();
+        let mut sources = HashSet::new();
+        let mut events = 0;
+        let mut count = 0;
+
+        for alert in alerts {
+            let Some(started) = alert.started_at.filter(|t| *t >= since && *t <= until) else {
+                continue;
+            };
+            count += 1;
+            events += alert.events_count as u64;
+            sources.insert(alert.source.as_str());
+            let country = alert.country.as_deref().unwrap_or("unknown");
+            for (map, key) in [
+                (&mut by_scenario, alert.scenario.as_str()),
+                (&mut by_country, country),
+            ] {
+                let entry = map.entry(key).or_default();
+                entry.0 += 1;
+                entry.1 += alert.events_count as u64;
+                entry.2.insert(alert.source.as_str());
+            }
+            let hour =
+                ((started - first_hour).num_hours() as usize).min(by_hour.len().saturating_sub(1));
+            if let Some(slot) = by_hour.get_mut(hour) {
+                slot.alerts += 1;
+            }
+        }
+
+        let ranked = |map: HashMap<&str, (u32, u64, HashSet<&str>)>| {
+            let mut counts: Vec<AttackCount> = map
+                .into_iter()
+                .map(|(key, (alerts, events, sources))| AttackCount {
+                    key: key.to_string(),
+                    alerts,
+                    events,
+                    sources: sources.len() as u32,
+                })
+                .collect();
+            counts.sort_by(|a, b| b.alerts.cmp(&a.alerts).then_with(|| a.key.cmp(&b.key)));
+            counts
+        };
+
+        SecStats {
+            since,
+            until,
+            alerts: count,
+            events,
+            sources: sources.len() as u32,
+            by_scenario: ranked(by_scenario),
+            by_country: ranked(by_country),
+            by_hour,
+        }
+    }
+}
+
+fn write_counts(
+    f: &mut std::fmt::Formatter<'_>,
+    title: &str,
+    counts: &[AttackCount],
+) -> std::fmt::Result {
+    writeln!(f, "{}", title)?;
+    let width = counts
+        .iter()
+        .map(|c| c.key.chars().count())
+        .max()
+        .unwrap_or(0);
+    for count in counts {
+        writeln!(
+            f,
+            "  {:width$}  {:>5} alerts  {:>7} events  {:>5} sources",
+            count.key,
+            count.alerts,
+            count.events,
+            count.sources,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+impl Display for SecStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "CrowdSec Attack Statistics")?;
+        writeln!(f, "==========================")?;
+        writeln!(
+            f,
+            "Since {}: {} alerts, {} events from {} sources",
+            self.since
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M"),
+            self.alerts,
+            self.events,
+            self.sources
+        )?;
+        if self.alerts == 0 {
+            return Ok(());
+        }
+        let hourly: Vec<u64> = self.by_hour.iter().map(|h| h.alerts as u64).collect();
+        writeln!(f, "Per hour: {}", sparkline(&hourly))?;
+        writeln!(f)?;
+        write_counts(f, "By scenario:", &self.by_scenario)?;
+        writeln!(f)?;
+        write_counts(f, "By country:", &self.by_country)
+    }
+}
+
+/// Aggregate CrowdSec alerts from the last `since` (default 24h)
+pub fn collect_stats(since: Option<Duration>) -> Result<SecStats> {
+    let since = since.unwrap_or(Duration::hours(24));
+    let query = crowdsec::AlertQuery {
+        since: Some(since),
+        ..Default::default()
+    };
+    let alerts = crowdsec::LapiClient::connect()?.alerts(&query)?;
+    let until = Utc::now();
+    Ok(SecStats::from_alerts(&alerts, until - since, until))
+}
+
+/// Show attacks per scenario, country and hour
+pub fn stats(since: Option<Duration>, json: bool) -> Result<()> {
+    let result = collect_stats(since)?;
+    print_output(&result, json);
+    Ok(())
+}
+
+fn check_hub_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c));
+    if !valid {
+        return Err(ShannonError::InvalidInput(format!("Invalid hub item name '{}'", name)).into());
+    }
+    Ok(())
+}
+
+fn is_installed(kind: &str, name: &str) -> Result<bool> {
+    Ok(crowdsec::hub(false)?
+        .items(kind)
+        .iter()
+        .any(|item| item.name == name))
+}
+
+/// Reload CrowdSec, then list the bouncers still registered with it
+fn reload_and_check() -> Result<Vec<String>> {
+    crowdsec::reload()?;
+    crowdsec::bouncers()
+}
+
+fn write_bouncers(f: &mut std::fmt::Formatter<'_>, bouncers: &[String]) -> std::fmt::Result {
+    if bouncers.is_empty() {
+        writeln!(
+            f,
+            "  ⚠ No bouncer is registered with CrowdSec: bans are not being enforced"
+        )
+    } else {
+        writeln!(f, "  Bouncers: {}", bouncers.join(", "))
+    }
+}
+
+/// A hub item of any kind, for `sec hub list`
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct HubEntry {
+    /// "collection", "parser", "scenario" or "postoverflow"
+    pub kind: String,
+    #[serde(flatten)]
+    pub item: crowdsec::HubItem,
+}
+
+impl TableRow for HubEntry {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.kind.clone(),
+            self.item.name.clone(),
+            self.item.status.clone(),
+            self.item
+                .local_version
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        ]
+    }
+}
+
+/// List hub items, installed only unless `all`
+pub fn hub_list(all: bool, json: bool) -> Result<()> {
+    let mut hub = crowdsec::hub(all)?;
+    let mut rows = Vec::new();
+    for (kind, items) in [
+        ("collection", std::mem::take(&mut hub.collections)),
+        ("parser", std::mem::take(&mut hub.parsers)),
+        ("scenario", std::mem::take(&mut hub.scenarios)),
+        ("postoverflow", std::mem::take(&mut hub.postoverflows)),
+    ] {
+        rows.extend(items.into_iter().map(|item| HubEntry {
+            kind: kind.to_string(),
+            item,
+        }));
+    }
+
+    let output = TableOutput {
+        headers: vec![
+            "Kind".to_string(),
+            "Name".to_string(),
+            "Status".to_string(),
+            "Version".to_string(),
+        ],
+        rows,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SecHubResult {
+    pub success: bool,
+    /// "installed", "removed" or "upgraded"
+    pub action: String,
+    /// Unset when every installed item was upgraded
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    /// Bouncers registered after the reload; empty means bans aren't enforced
+    pub bouncers: Vec<String>,
+}
+
+impl Display for SecHubResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.kind, &self.name) {
+            (Some(kind), Some(name)) => {
+                writeln!(f, "✓ {} {} {}", capitalize(&self.action), kind, name)?
+            }
+            _ => writeln!(f, "✓ Upgraded all installed hub items")?,
+        }
+        writeln!(f, "  CrowdSec reloaded")?;
+        write_bouncers(f, &self.bouncers)
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars
+        .next()
+        .map(|c| c.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Install a collection, parser, scenario or postoverflow
+pub fn hub_install(kind: &str, name: &str, json: bool) -> Result<()> {
+    check_hub_name(name)?;
+    if is_installed(kind, name)? {
+        return Err(ShannonError::AlreadyExists(format!("{} {}", kind, name)).into());
+    }
+
+    crowdsec::hub_install(kind, name)?;
+    let result = SecHubResult {
+        success: true,
+        action: "installed".to_string(),
+        kind: Some(kind.to_string()),
+        name: Some(name.to_string()),
+        bouncers: reload_and_check()?,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+pub fn hub_remove(kind: &str, name: &str, yes: bool, json: bool) -> Result<()> {
+    check_hub_name(name)?;
+    if !is_installed(kind, name)? {
+        return Err(ShannonError::NotFound(format!("Installed {} {}", kind, name)).into());
+    }
+    if !confirm(
+        &format!(
+            "Remove {} {}? CrowdSec stops using it after the reload.",
+            kind, name
+        ),
+        yes,
+    ) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    crowdsec::hub_remove(kind, name)?;
+    let result = SecHubResult {
+        success: true,
+        action: "removed".to_string(),
+        kind: Some(kind.to_string()),
+        name: Some(name.to_string()),
+        bouncers: reload_and_check()?,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Upgrade one installed item, or all of them
+pub fn hub_upgrade(kind: Option<&str>, name: Option<&str>, json: bool) -> Result<()> {
+    let item = kind.zip(name);
+    if let Some((kind, name)) = item {
+        check_hub_name(name)?;
+        if !is_installed(kind, name)? {
+            return Err(ShannonError::NotFound(format!("Installed {} {}", kind, name)).into());
+        }
+    }
+
+    crowdsec::hub_upgrade(item)?;
+    let result = SecHubResult {
+        success: true,
+        action: "upgraded".to_string(),
+        kind: kind.map(str::to_string),
+        name: name.map(str::to_string),
+        bouncers: reload_and_check()?,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Canonical form of an allowlist entry: a bare IP for single hosts,
+/// otherwise the network address and prefix
+fn normalize_entry(entry: &str) -> Result<String> {
+    if let Ok(ip) = entry.parse::<IpAddr>() {
+        return Ok(ip.to_string());
+    }
+    let network: IpNetwork = entry.parse().map_err(|_| {
+        ShannonError::InvalidInput(format!("'{}' is not an IP address or CIDR range", entry))
+    })?;
+    let host_prefix = if network.is_ipv4() { 32 } else { 128 };
+    Ok(if network.prefix() == host_prefix {
+        network.ip().to_string()
+    } else {
+        format!("{}/{}", network.network(), network.prefix())
+    })
+}
+
+impl TableRow for crowdsec::AllowlistEntry {
+    fn cells(&self) -> Vec<String> {
+        vec![self.value.clone(), self.note.clone().unwrap_or_default()]
+    }
+}
+
+pub fn allowlist_list(json: bool) -> Result<()> {
+    let output = TableOutput {
+        headers: vec!["Address".to_string(), "Note".to_string()],
+        rows: crowdsec::read_allowlist()?,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SecAllowlistResult {
+    pub success: bool,
+    /// "added" or "removed"
+    pub action: String,
+    pub entry: crowdsec::AllowlistEntry,
+    /// Bouncers registered after the reload; empty means bans aren't enforced
+    pub bouncers: Vec<String>,
+}
+
+impl Display for SecAllowlistResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = if self.action == "added" {
+            "Allowlisted"
+        } else {
+            "Removed from allowlist:"
+        };
+        match &self.entry.note {
+            Some(note) => writeln!(f, "✓ {} {} ({})", verb, self.entry.value, note)?,
+            None => writeln!(f, "✓ {} {}", verb, self.entry.value)?,
+        }
+        writeln!(f, "  CrowdSec reloaded")?;
+        write_bouncers(f, &self.bouncers)
+    }
+}
+
+/// Allowlist an IP or range and lift any ban on it
+pub fn allowlist_add(entry: &str, note: Option<&str>, json: bool) -> Result<()> {
+    let value = normalize_entry(entry)?;
+    let mut entries = crowdsec::read_allowlist()?;
+    if entries.iter().any(|e| e.value == value) {
+        return Err(ShannonError::AlreadyExists(format!("Allowlist entry {}", value)).into());
+    }
+
+    let entry = crowdsec::AllowlistEntry {
+        value,
+        note: note
+            .map(|n| n.replace(['\n', '\r'], " ").trim().to_string())
+            .filter(|n| !n.is_empty()),
+    };
+    entries.push(entry.clone());
+    crowdsec::write_allowlist(&entries)?;
+    crowdsec::delete_decisions(&entry.value)?;
+
+    let result = SecAllowlistResult {
+        success: true,
+        action: "added".to_string(),
+        entry,
+        bouncers: reload_and_check()?,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+pub fn allowlist_remove(entry: &str, json: bool) -> Result<()> {
+    let value = normalize_entry(entry)?;
+    let mut entries = crowdsec::read_allowlist()?;
+    let index = entries
+        .iter()
+        .position(|e| e.value == value)
+        .ok_or_else(|| ShannonError::NotFound(format!("Allowlist entry {}", value)))?;
+
+    let entry = entries.remove(index);
+    crowdsec::write_allowlist(&entries)?;
+
+    let result = SecAllowlistResult {
+        success: true,
+        action: "removed".to_string(),
+        entry,
+        bouncers: reload_and_check()?,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Days of sshd logs `sec whois` searches
+const WHOIS_SSH_DAYS: u32 = 7;
+/// AdGuard query log entries `sec whois` shows for a LAN client
+const WHOIS_DNS_QUERIES: u32 = 20;
+
+/// A WireGuard peer the address belongs to or connects from
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WhoisPeer {
+    pub interface: String,
+    pub name: String,
+    /// "tunnel address" or "endpoint"
+    pub matched: String,
+}
+
+/// Everything shannon knows about one address
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct WhoisReport {
+    pub ip: String,
+    pub reverse_dns: Vec<String>,
+    pub geo: geoip::GeoInfo,
+    /// CrowdSec decisions in force against the address
+    pub decisions: Vec<crowdsec::CrowdsecDecision>,
+    pub alerts: Vec<crowdsec::CrowdsecAlert>,
+    /// `fw block` rules covering the address
+    pub firewall_blocks: Vec<String>,
+    /// sshd attempts over the last `WHOIS_SSH_DAYS` days
+    pub ssh: system::SshActivity,
+    /// DHCP leases for the address
+    pub dhcp: Vec<DhcpLease>,
+    /// Static reservations for the address
+    pub dhcp_reservations: Vec<DhcpReservation>,
+    pub wireguard: Vec<WhoisPeer>,
+    /// Recent AdGuard Home queries, for LAN clients
+    pub dns_queries: Vec<adguard::DnsQuery>,
+    /// Sources that couldn't be queried, with the reason
+    pub unavailable: Vec<String>,
+}
+
+impl Display for WhoisReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let title = format!("Whois {}", self.ip);
+        writeln!(f, "{}", title)?;
+        writeln!(f, "{}", "=".repeat(title.chars().count()))?;
+        let or_none = |items: Vec<String>| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+
+        writeln!(f, "Reverse DNS:  {}", or_none(self.reverse_dns.clone()))?;
+        let location: Vec<String> = [
+            self.geo.country.clone(),
+            self.geo.as_number.map(|n| format!("AS{}", n)),
+            self.geo.as_org.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        writeln!(
+            f,
+            "Location:     {}",
+            if location.is_empty() {
+                "unknown (private or not in GeoIP)".to_string()
+            } else {
+                location.join(" ")
+            }
+        )?;
+        writeln!(
+            f,
+            "Firewall:     {}",
+            if self.firewall_blocks.is_empty() {
+                "not blocked".to_string()
+            } else {
+                format!("blocked by fw block ({})", self.firewall_blocks.join(", "))
+            }
+        )?;
+        writeln!(
+            f,
+            "DHCP:         {}",
+            or_none(
+                self.dhcp
+                    .iter()
+                    .map(|l| {
+                        let state = if l.active { "lease" } else { "expired lease" };
+                        format!(
+                            "{} {} {}",
+                            state,
+                            l.mac,
+                            l.hostname.as_deref().unwrap_or("-")
+                        )
+                    })
+                    .chain(self.dhcp_reservations.iter().map(|r| {
+                        format!(
+                            "reservation {} {}",
+                            r.mac,
+                            r.hostname.as_deref().unwrap_or("-")
+                        )
+                    }))
+                    .collect()
+            )
+        )?;
+        writeln!(
+            f,
+            "WireGuard:    {}",
+            or_none(
+                self.wireguard
+                    .iter()
+                    .map(|p| { format!("peer {} on {} ({})", p.name, p.interface, p.matched) })
+                    .collect()
+            )
+        )?;
+        writeln!(
+            f,
+            "SSH ({}d):     {} failed, {} accepted{}{}",
+            WHOIS_SSH_DAYS,
+            self.ssh.failed,
+            self.ssh.accepted,
+            if self.ssh.users.is_empty() {
+                String::new()
+            } else {
+                format!("; users: {}", self.ssh.users.join(", "))
+            },
+            self.ssh
+                .last_seen
+                .as_ref()
+                .map(|t| format!("; last {}", t))
+                .unwrap_or_default()
+        )?;
+
+        writeln!(f)?;
+        if self.decisions.is_empty() {
+            writeln!(
+                f,
+                "CrowdSec: no active decisions, {} alerts",
+                self.alerts.len()
+            )?;
+        } else {
+            writeln!(
+                f,
+                "CrowdSec: {} active decisions, {} alerts",
+                self.decisions.len(),
+                self.alerts.len()
+            )?;
+            for decision in &self.decisions {
+                write!(f, "{}", decision)?;
+            }
+        }
+        for alert in &self.alerts {
+            writeln!(
+                f,
+                "  {}  {} ({} events)",
+                alert
+                    .started_at
+                    .map(|t| t
+                        .with_timezone(&chrono::Local)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string())
+                    .unwrap_or_default(),
+                alert.scenario,
+                alert.events_count
+            )?;
+        }
+
+        if !self.dns_queries.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "Recent DNS queries:")?;
+            for query in &self.dns_queries {
+                writeln!(
+                    f,
+                    "  {}  {} {}{}",
+                    query.time,
+                    query.qtype,
+                    query.domain,
+                    if query.blocked { " [blocked]" } else { "" }
+                )?;
+            }
+        }
+
+        if !self.unavailable.is_empty() {
+            writeln!(f)?;
+            for source in &self.unavailable {
+                writeln!(f, "⚠ {}", source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Correlate everything shannon knows about an address
+pub fn whois(ip: IpAddr, json: bool) -> Result<()> {
+    let mut unavailable = Vec::new();
+    let mut note =
+        |source: &str, err: anyhow::Error| unavailable.push(format!("{}: {:#}", source, err));
+
+    let (alerts, decisions) = match crowdsec::LapiClient::connect().and_then(|client| {
+        client.alerts(&crowdsec::AlertQuery {
+            ip: Some(ip.to_string()),
+            ..Default::default()
+        })
+    }) {
+        Ok(alerts) => {
+            let now = Utc::now();
+            let decisions = alerts
+                .iter()
+                .flat_map(|a| &a.decisions)
+                .filter(|d| d.until.is_none_or(|t| t > now))
+                .cloned()
+                .collect();
+            (alerts, decisions)
+        }
+        Err(e) => {
+            note("CrowdSec", e);
+            (Vec::new(), Vec::new())
+        }
+    };
+
+    let firewall_blocks = match NftablesAdapter::new().list_blocks() {
+        Ok(blocks) => blocks
+            .into_iter()
+            .filter(|source| {
+                source
+                    .parse::<IpNetwork>()
+                    .is_ok_and(|net| net.contains(ip))
+            })
+            .collect(),
+        Err(e) => {
+            note("firewall", e);
+            Vec::new()
+        }
+    };
+
+    let dnsmasq = DnsmasqAdapter::new();
+    let dhcp = match dnsmasq.list_leases() {
+        Ok(leases) => leases
+            .into_iter()
+            .filter(|l| l.ip.parse() == Ok(ip))
+            .collect(),
+        Err(e) => {
+            note("DHCP", e);
+            Vec::new()
+        }
+    };
+    let dhcp_reservations = match dnsmasq.list_reservations() {
+        Ok(reservations) => reservations
+            .into_iter()
+            .filter(|r| r.ip.parse() == Ok(ip))
+            .collect(),
+        Err(e) => {
+            note("DHCP reservations", e);
+            Vec::new()
+        }
+    };
+
+    let wireguard = match wireguard::get_all_status() {
+        Ok(interfaces) => interfaces
+            .iter()
+            .flat_map(|status| status.peers.iter().map(move |peer| (status, peer)))
+            .filter_map(|(status, peer)| {
+                let in_tunnel = peer
+                    .allowed_ips
+                    .split(", ")
+                    .any(|net| net.parse::<IpNetwork>().is_ok_and(|net| net.contains(ip)));
+                let from_endpoint = peer
+                    .endpoint
+                    .as_deref()
+                    .is_some_and(|e| traffic::endpoint_ip(e).parse() == Ok(ip));
+                let matched = match (in_tunnel, from_endpoint) {
+                    (true, _) => "tunnel address",
+                    (false, true) => "endpoint",
+                    (false, false) => return None,
+                };
+                Some(WhoisPeer {
+                    interface: status.interface.clone(),
+                    name: peer.name.clone(),
+                    matched: matched.to_string(),
+                })
+            })
+            .collect(),
+        Err(e) => {
+            note("WireGuard", e);
+            Vec::new()
+        }
+    };
+
+    let ssh = system::ssh_activity(ip, WHOIS_SSH_DAYS).unwrap_or_else(|e| {
+        note("SSH logs", e);
+        system::SshActivity::default()
+    });
+
+    // Only LAN (and VPN) clients resolve through AdGuard Home
+    let dns_queries = if geoip::is_public(&ip) {
+        Vec::new()
+    } else {
+        adguard::client_queries(&ip.to_string(), WHOIS_DNS_QUERIES).unwrap_or_else(|e| {
+            note("AdGuard Home", e);
+            Vec::new()
+        })
+    };
+
+    let report = WhoisReport {
+        ip: ip.to_string(),
+        reverse_dns: system::reverse_dns(ip).unwrap_or_default(),
+        geo: geoip::lookup(ip).unwrap_or_default(),
+        decisions,
+        alerts,
+        firewall_blocks,
+        ssh,
+        dhcp,
+        dhcp_reservations,
+        wireguard,
+        dns_queries,
+        unavailable,
+    };
+
+    print_output(&report, json);
+    Ok(())
+}
+
 /// Security finding from log analysis
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, JsonSchema, Clone)]
 pub struct SecurityFinding {
     pub timestamp: String,
     pub severity: String,
@@ -91,7 +974,13 @@ impl Display for SecurityFinding {
             "medium" => "🟡",
             _ => "🟢",
         };
-        writeln!(f, "{} [{}] {}", indicator, self.severity.to_uppercase(), self.summary)?;
+        writeln!(
+            f,
+            "{} [{}] {}",
+            indicator,
+            self.severity.to_uppercase(),
+            self.summary
+        )?;
         writeln!(f, "   Category: {}", self.category)?;
         writeln!(f, "   Time: {}", self.timestamp)?;
         if !self.details.is_empty() {
@@ -101,7 +990,7 @@ impl Display for SecurityFinding {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ScanResult {
     pub findings: Vec<SecurityFinding>,
     pub logs_analyzed: usize,
@@ -112,7 +1001,11 @@ impl Display for ScanResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Security Scan Results")?;
         writeln!(f, "=====================")?;
-        writeln!(f, "Analyzed {} log entries (last {} hours)", self.logs_analyzed, self.time_window_hours)?;
+        writeln!(
+            f,
+            "Analyzed {} log entries (last {} hours)",
+            self.logs_analyzed, self.time_window_hours
+        )?;
         writeln!(f)?;
 
         if self.findings.is_empty() {
@@ -138,8 +1031,11 @@ pub fn scan(json: bool) -> Result<()> {
     };
 
     print_output(&result, json);
-    println!("\nNote: LLM-based security analysis not yet implemented.");
-    println!("Run 'shannon sec blocks' to view active CrowdSec decisions.");
+    // Notes would break the JSON envelope, so they're text-only
+    if !json {
+        println!("\nNote: LLM-based security analysis not yet implemented.");
+        println!("Run 'shannon sec blocks' to view active CrowdSec decisions.");
+    }
 
     Ok(())
 }
@@ -153,7 +1049,73 @@ pub fn report(hours: u32, json: bool) -> Result<()> {
     };
 
     print_output(&result, json);
-    println!("\nNote: No findings stored yet. Run 'shannon sec scan' first.");
+    if !json {
+        println!("\nNote: No findings stored yet. Run 'shannon sec scan' first.");
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(
+        minutes: i64,
+        scenario: &str,
+        source: &str,
+        country: Option<&str>,
+        events: u32,
+    ) -> crowdsec::CrowdsecAlert {
+        crowdsec::CrowdsecAlert {
+            id: 0,
+            scenario: scenario.to_string(),
+            message: String::new(),
+            source: source.to_string(),
+            country: country.map(str::to_string),
+            as_number: None,
+            as_name: None,
+            events_count: events,
+            started_at: Some(DateTime::from_timestamp(1760000400 + minutes * 60, 0).unwrap()),
+            stopped_at: None,
+            decisions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_stats_group_by_scenario_country_and_hour() {
+        let since = DateTime::from_timestamp(1760000400, 0).unwrap(); // on the hour
+        let until = since + Duration::hours(3);
+        let alerts = vec![
+            alert(5, "crowdsecurity/ssh-bf", "203.0.113.9", Some("NL"), 6),
+            alert(70, "crowdsecurity/ssh-bf", "203.0.113.9", Some("NL"), 8),
+            alert(75, "crowdsecurity/http-probing", "198.51.100.4", None, 12),
+            // Outside the window
+            alert(-30, "crowdsecurity/ssh-bf", "192.0.2.1", Some("US"), 5),
+        ];
+
+        let stats = SecStats::from_alerts(&alerts, since, until);
+        assert_eq!((stats.alerts, stats.events, stats.sources), (3, 26, 2));
+        assert_eq!(stats.by_scenario[0].key, "crowdsecurity/ssh-bf");
+        assert_eq!(
+            (stats.by_scenario[0].alerts, stats.by_scenario[0].sources),
+            (2, 1)
+        );
+        let countries: Vec<&str> = stats.by_country.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(countries, vec!["NL", "unknown"]);
+        let hourly: Vec<u32> = stats.by_hour.iter().map(|h| h.alerts).collect();
+        assert_eq!(hourly, vec![1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_normalize_allowlist_entry() {
+        assert_eq!(normalize_entry("198.51.100.20").unwrap(), "198.51.100.20");
+        assert_eq!(
+            normalize_entry("198.51.100.20/32").unwrap(),
+            "198.51.100.20"
+        );
+        assert_eq!(normalize_entry("10.8.0.5/24").unwrap(), "10.8.0.0/24");
+        assert_eq!(normalize_entry("2001:db8::1/64").unwrap(), "2001:db8::/64");
+        assert!(normalize_entry("office").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Configuration snapshot commands
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::adapters::crowdsec;
+use crate::adapters::nftables::NftablesAdapter;
+use crate::adapters::wireguard;
+use crate::error::ShannonError;
+use crate::location::{redact_secrets, systemctl, write_files};
+use crate::output::{confirm, print_output, unified_diff, TableOutput, TableRow};
+use crate::snapshot::{self, SnapshotInfo, FIREWALL6_RULES, FIREWALL_RULES};
+
+impl TableRow for SnapshotInfo {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.clone(),
+            self.created
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            self.label.clone().unwrap_or_default(),
+        ]
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SnapshotCreateResult {
+    pub success: bool,
+    pub snapshot: SnapshotInfo,
+}
+
+impl Display for SnapshotCreateResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Created snapshot {}", self.snapshot.id)
+    }
+}
+
+/// Take a snapshot now
+pub fn create(label: Option<&str>, json: bool) -> Result<()> {
+    let info = snapshot::create(label)?;
+
+    print_output(
+        &SnapshotCreateResult {
+            success: true,
+            snapshot: info,
+        },
+        json,
+    );
+    Ok(())
+}
+
+/// List snapshots
+pub fn list(json: bool) -> Result<()> {
+    let output = TableOutput {
+        headers: vec!["ID".to_string(), "Created".to_string(), "Label".to_string()],
+        rows: snapshot::list()?,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FileDiff {
+    pub name: String,
+    pub path: String,
+    pub diff: String,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SnapshotDiff {
+    pub from: String,
+    pub to: String,
+    pub files: Vec<FileDiff>,
+}
+
+impl Display for SnapshotDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.files.is_empty() {
+            return writeln!(f, "No differences between {} and {}.", self.from, self.to);
+        }
+        for file in &self.files {
+            write!(f, "{}", file.diff)?;
+        }
+        Ok(())
+    }
+}
+
+/// Show a unified diff between two snapshots, or a snapshot and live state
+pub fn diff(from: &str, to: Option<&str>, json: bool) -> Result<()> {
+    let from = snapshot::resolve(from)?;
+    let old = snapshot::read_files(&from.id)?;
+
+    let (to_label, new) = match to {
+        Some(id) => {
+            let to = snapshot::resolve(id)?;
+            let files = snapshot::read_files(&to.id)?;
+            (to.id, files)
+        }
+        None => ("live".to_string(), snapshot::read_live()?),
+    };
+
+    let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let files = names
+        .into_iter()
+        .filter_map(|name| {
+            let before = old.get(name).map(String::as_str).unwrap_or("");
+            let after = new.get(name).map(String::as_str).unwrap_or("");
+            (before != after).then(|| {
+                let path = snapshot::live_path(name);
+                FileDiff {
+                    name: name.clone(),
+                    path: path.to_string(),
+                    diff: redact_secrets(&unified_diff(
+                        &format!("{}/{}", from.id, name),
+                        &format!("{}/{}", to_label, name),
+                        before,
+                        after,
+                    )),
+                }
+            })
+        })
+        .collect();
+
+    let result = SnapshotDiff {
+        from: from.id,
+        to: to_label,
+        files,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct SnapshotRestoreResult {
+    pub success: bool,
+    pub snapshot: String,
+    pub restored: Vec<String>,
+    /// Files removed because they didn't exist when the snapshot was taken
+    pub removed: Vec<String>,
+    pub reloaded: Vec<String>,
+}
+
+impl Display for SnapshotRestoreResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.restored.is_empty() && self.removed.is_empty() {
+            return write!(f, "Live state already matches snapshot {}", self.snapshot);
+        }
+        write!(f, "Restored snapshot {}:", self.snapshot)?;
+        if !self.restored.is_empty() {
+            write!(f, " {}", self.restored.join(", "))?;
+        }
+        if !self.removed.is_empty() {
+            write!(f, " (removed {})", self.removed.join(", "))?;
+        }
+        write!(f, " (reloaded {})", self.reloaded.join(", "))
+    }
+}
+
+/// Restore the IPv4 and/or IPv6 rulesets; if IPv6 fails after IPv4 was
+/// restored, the live IPv4 ruleset is put back
+fn restore_rulesets(
+    target: &BTreeMap<String, String>,
+    live: &BTreeMap<String, String>,
+    v4: bool,
+    v6: bool,
+) -> Result<()> {
+    let firewall = NftablesAdapter::new();
+    if v4 {
+        firewall.restore_ruleset(&target[FIREWALL_RULES])?;
+    }
+    if v6 {
+        if let Err(e) = firewall.restore_ruleset_v6(&target[FIREWALL6_RULES]) {
+            if v4 {
+                firewall
+                    .restore_ruleset(&live[FIREWALL_RULES])
+                    .context("Failed to roll back iptables ruleset")?;
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Restore a snapshot and reload the affected services
+///
+/// All managed files are replaced together, and put back if that or the
+/// firewall restore fails.
+pub fn restore(id: &str, yes: bool, json: bool) -> Result<()> {
+    let info = snapshot::resolve(id)?;
+
+    // Read everything up front so a broken archive fails before any write
+    let target = snapshot::read_files(&info.id)?;
+    let live = snapshot::read_live()?;
+
+    let plan = snapshot::restore_plan(&info, &target, &live);
+
+    if !plan.is_empty()
+        && !confirm(
+            &format!(
+                "Restore snapshot {} ({} changed files)?",
+                info.id,
+                plan.len()
+            ),
+            yes,
+        )
+    {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    let paths: Vec<(String, Option<&str>)> = plan
+        .iter()
+        .filter(|(name, _)| !snapshot::is_ruleset(name))
+        .map(|(name, content)| (snapshot::live_path(name), *content))
+        .collect();
+    let files: Vec<(&str, Option<&str>)> = paths
+        .iter()
+        .map(|(path, content)| (path.as_str(), *content))
+        .collect();
+    write_files(&files)?;
+
+    let touched = |names: &[&str]| plan.iter().any(|(name, _)| names.contains(name));
+
+    let v4 = touched(&[FIREWALL_RULES]);
+    let v6 = touched(&[FIREWALL6_RULES]);
+    if let Err(e) = restore_rulesets(&target, &live, v4, v6) {
+        let undo: Vec<(&str, Option<&str>)> = paths
+            .iter()
+            .zip(plan.iter().filter(|(name, _)| !snapshot::is_ruleset(name)))
+            .map(|((path, _), (name, _))| (path.as_str(), live.get(*name).map(String::as_str)))
+            .collect();
+        write_files(&undo).context("Failed to roll back restored files")?;
+        return Err(e.context("Failed to restore firewall ruleset; files rolled back"));
+    }
+
+    let mut reloaded = Vec::new();
+    if v4 {
+        reloaded.push("iptables".to_string());
+    }
+    if v6 {
+        reloaded.push("ip6tables".to_string());
+    }
+    if touched(&["dnsmasq.conf", "dnsmasq-custom.conf"]) {
+        systemctl("restart", "dnsmasq")?;
+        reloaded.push("dnsmasq".to_string());
+    }
+    for (name, content) in &plan {
+        let Some(interface) = snapshot::wireguard_interface(name) else {
+            continue;
+        };
+        let unit = format!("wg-quick@{}", interface);
+        if content.is_none() {
+            systemctl("disable", &unit)?;
+            systemctl("stop", &unit)?;
+        } else if wireguard::is_client(interface) || !wireguard::get_status(interface)?.interface_up
+        {
+            // Client hooks and Table = off only take effect through wg-quick
+            systemctl("restart", &unit)?;
+        } else {
+            wireguard::sync_config(interface)?;
+        }
+        reloaded.push(interface.to_string());
+    }
+    if touched(&["AdGuardHome.yaml"]) {
+        systemctl("restart", "AdGuardHome")?;
+        reloaded.push("AdGuardHome".to_string());
+    }
+    if touched(&["crowdsec-allowlist.yaml"]) {
+        crowdsec::reload()?;
+        reloaded.push("crowdsec".to_string());
+    }
+
+    let (restored, removed): (Vec<_>, Vec<_>) =
+        plan.iter().partition(|(_, content)| content.is_some());
+    let result = SnapshotRestoreResult {
+        success: true,
+        snapshot: info.id,
+        restored: restored.iter().map(|(name, _)| name.to_string()).collect(),
+        removed: removed.iter().map(|(name, _)| name.to_string()).collect(),
+        reloaded,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
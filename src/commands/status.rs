@@ -1,24 +1,38 @@
 //! System status and diagnostics
 
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::Serialize;
+use std::collections::BTreeSet;
 use std::fmt::Display;
 
-use crate::adapters::system::{get_service_status, get_system_metrics, get_wan_ip};
+use crate::adapters::system::{
+    get_service_status, get_system_metrics, get_wan_ip, ipv6_status, Ipv6Status,
+};
+use crate::adapters::wireguard;
+use crate::commands::dhcp::{self, PoolUsage};
+use crate::commands::dns::{self, UpstreamHealth};
+use crate::error::ShannonError;
 use crate::location::execute_shell;
-use crate::output::{print_output, LineStatus, StatusLine, StatusReport};
+use crate::output::{print_failure, print_output};
 
 /// System status overview
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct SystemStatus {
     pub wan_ip: String,
+    pub ipv6: Ipv6Status,
     pub uptime: String,
     pub memory_used_percent: f32,
     pub cpu_load: f32,
+    /// Active leases in the DHCP dynamic pool
+    pub dhcp_pool: Option<PoolUsage>,
+    /// AdGuard's upstreams (empty when AdGuard is unreachable); only tested
+    /// with --upstreams, otherwise each has 0 queries
+    pub dns_upstreams: Vec<UpstreamHealth>,
     pub services: Vec<ServiceStatus>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ServiceStatus {
     pub name: String,
     pub active: bool,
@@ -29,9 +43,79 @@ impl Display for SystemStatus {
         writeln!(f, "SHANNON Router Status")?;
         writeln!(f, "=====================")?;
         writeln!(f, "WAN IP:     {}", self.wan_ip)?;
+        let v6 = &self.ipv6;
+        match (&v6.wan_address, v6.default_route) {
+            (None, false) => writeln!(f, "IPv6:       none")?,
+            (address, route) => {
+                let mut details = Vec::new();
+                if !route {
+                    details.push("no default route".to_string());
+                }
+                if v6.delegated_prefixes.is_empty() {
+                    details.push("no prefix delegated".to_string());
+                } else {
+                    details.push(format!("delegated {}", v6.delegated_prefixes.join(", ")));
+                }
+                if !v6.lan_prefixes.is_empty() {
+                    details.push(format!("LAN {}", v6.lan_prefixes.join(", ")));
+                }
+                writeln!(
+                    f,
+                    "IPv6:       {} ({})",
+                    address.as_deref().unwrap_or("no WAN address"),
+                    details.join("; ")
+                )?
+            }
+        }
         writeln!(f, "Uptime:     {}", self.uptime)?;
         writeln!(f, "Memory:     {:.1}%", self.memory_used_percent)?;
         writeln!(f, "CPU Load:   {:.2}", self.cpu_load)?;
+        if let Some(pool) = &self.dhcp_pool {
+            writeln!(f, "DHCP pool:  {}", pool)?;
+        }
+        let tested = self.dns_upstreams.iter().any(|u| u.queries > 0);
+        if !self.dns_upstreams.is_empty() && !tested {
+            let protocols: BTreeSet<&str> = self
+                .dns_upstreams
+                .iter()
+                .map(|u| u.protocol.as_str())
+                .collect();
+            let protocols: Vec<&str> = protocols.into_iter().collect();
+            writeln!(
+                f,
+                "DNS:        {} upstreams ({}), untested (--upstreams)",
+                self.dns_upstreams.len(),
+                protocols.join(", ")
+            )?;
+            for upstream in &self.dns_upstreams {
+                writeln!(f, "            {}", upstream.upstream)?;
+            }
+        }
+        if tested {
+            let answering = self.dns_upstreams.iter().filter(|u| u.failures == 0);
+            let healthy = answering.clone().count();
+            let protocols: BTreeSet<&str> = answering.map(|u| u.protocol.as_str()).collect();
+            write!(
+                f,
+                "DNS:        {}/{} upstreams answering",
+                healthy,
+                self.dns_upstreams.len()
+            )?;
+            if protocols.is_empty() {
+                writeln!(f)?;
+            } else {
+                let protocols: Vec<&str> = protocols.into_iter().collect();
+                writeln!(f, " ({})", protocols.join(", "))?;
+            }
+            for upstream in self.dns_upstreams.iter().filter(|u| u.failures > 0) {
+                writeln!(
+                    f,
+                    "            ✗ {}: {}",
+                    upstream.upstream,
+                    upstream.error.as_deref().unwrap_or("no answer")
+                )?;
+            }
+        }
         writeln!(f)?;
         writeln!(f, "Services:")?;
         for svc in &self.services {
@@ -42,24 +126,39 @@ impl Display for SystemStatus {
     }
 }
 
-/// Run `shannon status`
-pub fn status(json: bool) -> Result<()> {
+/// Run `shannon status`; `upstreams` adds a test query to each DNS upstream
+pub fn status(upstreams: bool, json: bool) -> Result<()> {
     let wan_ip = get_wan_ip().unwrap_or_else(|_| "unknown".to_string());
     let metrics = get_system_metrics()?;
 
-    let services = vec!["dnsmasq", "ssh", "crowdsec", "AdGuardHome", "wg-quick@wg0"]
+    // One wg-quick unit per WireGuard interface
+    let tunnels = wireguard::interfaces()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|iface| format!("wg-quick@{}", iface));
+    let services = ["dnsmasq", "ssh", "crowdsec", "AdGuardHome"]
         .into_iter()
+        .map(str::to_string)
+        .chain(tunnels)
         .map(|name| ServiceStatus {
-            name: name.to_string(),
-            active: get_service_status(name).unwrap_or(false),
+            active: get_service_status(&name).unwrap_or(false),
+            name,
         })
         .collect();
 
     let status = SystemStatus {
         wan_ip,
+        ipv6: ipv6_status().unwrap_or_default(),
         uptime: metrics.uptime,
         memory_used_percent: metrics.memory_used_percent,
         cpu_load: metrics.cpu_load,
+        dhcp_pool: dhcp::pool_usage().unwrap_or(None),
+        dns_upstreams: if upstreams {
+            dns::check_upstreams()
+        } else {
+            dns::configured_upstreams()
+        }
+        .unwrap_or_default(),
         services,
     };
 
@@ -68,13 +167,13 @@ pub fn status(json: bool) -> Result<()> {
 }
 
 /// Doctor diagnostics
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DiagnosticResult {
     pub checks: Vec<DiagnosticCheck>,
     pub all_passed: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct DiagnosticCheck {
     pub name: String,
     pub passed: bool,
@@ -129,14 +228,17 @@ pub fn doctor(json: bool) -> Result<()> {
 
     let all_passed = checks.iter().all(|c| c.passed);
 
+    let failed = checks.iter().filter(|c| !c.passed).count();
+    let total = checks.len();
     let result = DiagnosticResult { checks, all_passed };
 
-    print_output(&result, json);
-
     if !all_passed {
-        std::process::exit(1);
+        let err = ShannonError::ChecksFailed { failed, total }.into();
+        print_failure(&result, &err, json);
+        return Err(err);
     }
 
+    print_output(&result, json);
     Ok(())
 }
 
@@ -187,3 +289,41 @@ fn check_dns() -> DiagnosticCheck {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::adguard;
+
+    fn upstream(address: &str, failures: u32) -> UpstreamHealth {
+        UpstreamHealth {
+            upstream: address.to_string(),
+            protocol: adguard::upstream_protocol(address).to_string(),
+            queries: 1,
+            failures,
+            latency_ms: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_upstream_protocols_listed_once() {
+        let status = SystemStatus {
+            wan_ip: "203.0.113.1".to_string(),
+            ipv6: Ipv6Status::default(),
+            uptime: "1 day".to_string(),
+            memory_used_percent: 0.0,
+            cpu_load: 0.0,
+            dhcp_pool: None,
+            dns_upstreams: vec![
+                upstream("https://dns.quad9.net/dns-query", 0),
+                upstream("9.9.9.9", 0),
+                upstream("https://cloudflare-dns.com/dns-query", 0),
+                upstream("tls://1.1.1.1", 1),
+            ],
+            services: Vec::new(),
+        };
+        let text = status.to_string();
+        assert!(text.contains("3/4 upstreams answering (DoH, plain)\n"));
+    }
+}
@@ -1,14 +1,37 @@
 //! VPN (WireGuard) management commands
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use ipnetwork::Ipv4Network;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
+use schemars::JsonSchema;
 use serde::Serialize;
 use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
 
-use crate::adapters::wireguard;
-use crate::output::print_output;
+use crate::adapters::dnsmasq::DnsmasqAdapter;
+use crate::adapters::egress::{self, EgressDevice};
+use crate::adapters::wgconf::{self, Section, SectionKind, WgConfig};
+use crate::adapters::{geoip, wireguard};
+use crate::error::ShannonError;
+use crate::location::systemctl;
+use crate::output::{confirm, format_bytes, print_output, sparkline, TableOutput, TableRow};
+use crate::traffic::{self, PeerUsage};
 
-#[derive(Debug, Serialize)]
+/// Public name clients connect to (kept current by `shannon ddns`)
+const ENDPOINT_HOST: &str = "shannon.fredrikbranstrom.se";
+const DEFAULT_LISTEN_PORT: &str = "51820";
+const LAN_SUBNET: &str = "192.168.4.0/24";
+/// Keepalive for site-to-site peers, so NAT mappings on either side stay open
+const DEFAULT_KEEPALIVE: u16 = 25;
+/// Slices of the `vpn usage` window in the trend sparkline
+const USAGE_BUCKETS: usize = 24;
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct VpnPeersResult {
+    pub interface: String,
     pub interface_up: bool,
     pub listening_port: Option<u16>,
     pub peers: Vec<wireguard::WireguardPeer>,
@@ -17,10 +40,15 @@ pub struct VpnPeersResult {
 impl Display for VpnPeersResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if !self.interface_up {
-            return writeln!(f, "WireGuard interface is down.");
+            return writeln!(f, "WireGuard interface {} is down.", self.interface);
         }
-        writeln!(f, "WireGuard Peers (port {})", self.listening_port.unwrap_or(0))?;
-        writeln!(f, "=========================")?;
+        let title = format!(
+            "WireGuard Peers on {} (port {})",
+            self.interface,
+            self.listening_port.unwrap_or(0)
+        );
+        writeln!(f, "{}", title)?;
+        writeln!(f, "{}", "=".repeat(title.chars().count()))?;
         if self.peers.is_empty() {
             writeln!(f, "No peers configured.")?;
         } else {
@@ -32,11 +60,12 @@ impl Display for VpnPeersResult {
     }
 }
 
-/// List WireGuard peers with status
-pub fn peers(json: bool) -> Result<()> {
-    let status = wireguard::get_status()?;
+/// List an interface's WireGuard peers with status
+pub fn peers(interface: &str, json: bool) -> Result<()> {
+    let status = wireguard::get_status(interface)?;
 
     let result = VpnPeersResult {
+        interface: status.interface,
         interface_up: status.interface_up,
         listening_port: status.listening_port,
         peers: status.peers,
@@ -46,9 +75,1030 @@ pub fn peers(json: bool) -> Result<()> {
     Ok(())
 }
 
-/// Show WireGuard status summary
-pub fn status(json: bool) -> Result<()> {
-    let status = wireguard::get_status()?;
-    print_output(&status, json);
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnStatusResult {
+    pub interfaces: Vec<wireguard::WireguardStatus>,
+}
+
+impl Display for VpnStatusResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.interfaces.is_empty() {
+            return writeln!(f, "No WireGuard interfaces configured.");
+        }
+        for status in &self.interfaces {
+            write!(f, "{}", status)?;
+        }
+        Ok(())
+    }
+}
+
+/// Show the status of one interface, or of every interface
+pub fn status(interface: Option<&str>, json: bool) -> Result<()> {
+    let interfaces = match interface {
+        Some(interface) => vec![wireguard::get_status(interface)?],
+        None => wireguard::get_all_status()?,
+    };
+
+    print_output(&VpnStatusResult { interfaces }, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnAddPeerResult {
+    pub success: bool,
+    pub name: String,
+    pub address: String,
+    pub public_key: String,
+    pub full_tunnel: bool,
+    /// wg-quick config for the client, including its private key
+    pub client_config: String,
+    #[serde(skip)]
+    pub qr_code: String,
+}
+
+impl Display for VpnAddPeerResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Added peer {} ({}, {} tunnel)",
+            self.name,
+            self.address,
+            if self.full_tunnel { "full" } else { "split" }
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.client_config)?;
+        write!(f, "{}", self.qr_code)
+    }
+}
+
+/// What the other end of a tunnel needs to know about this one
+struct ServerEnd {
+    public_key: String,
+    /// `host:port` to connect to
+    endpoint: String,
+    /// The tunnel subnet
+    subnet: Ipv4Network,
+}
+
+fn server_end(interface: &str, config: &WgConfig) -> Result<ServerEnd> {
+    let section = config
+        .interface()
+        .with_context(|| format!("{}.conf has no [Interface] section", interface))?;
+    let private_key = section
+        .get("PrivateKey")
+        .with_context(|| format!("{}.conf has no PrivateKey", interface))?;
+    let listen_port = section.get("ListenPort").unwrap_or(DEFAULT_LISTEN_PORT);
+
+    Ok(ServerEnd {
+        public_key: wireguard::public_key(private_key)?,
+        endpoint: format!("{}:{}", ENDPOINT_HOST, listen_port),
+        subnet: config
+            .subnet()
+            .with_context(|| format!("{}.conf has no IPv4 Address", interface))?,
+    })
+}
+
+/// Client-side wg-quick config for a peer of this server
+fn client_config(
+    server: &ServerEnd,
+    private_key: &str,
+    psk: &str,
+    address: Ipv4Addr,
+    split: bool,
+) -> String {
+    let allowed_ips = if split {
+        format!(
+            "{}/{}, {}",
+            server.subnet.network(),
+            server.subnet.prefix(),
+            LAN_SUBNET
+        )
+    } else {
+        "0.0.0.0/0, ::/0".to_string()
+    };
+
+    format!(
+        "[Interface]\n\
+         PrivateKey = {private_key}\n\
+         Address = {address}/32\n\
+         DNS = {dns}\n\
+         \n\
+         [Peer]\n\
+         PublicKey = {public_key}\n\
+         PresharedKey = {psk}\n\
+         Endpoint = {endpoint}\n\
+         AllowedIPs = {allowed_ips}\n\
+         PersistentKeepalive = 25\n",
+        dns = server.subnet.ip(),
+        public_key = server.public_key,
+        endpoint = server.endpoint,
+    )
+}
+
+/// wg-quick config for the router at the far end of a site-to-site tunnel.
+/// It routes this tunnel and our LAN through us; `listen_port` is set when
+/// we dial it rather than the other way round.
+fn site_config(
+    server: &ServerEnd,
+    private_key: &str,
+    psk: &str,
+    address: Ipv4Addr,
+    listen_port: Option<&str>,
+    keepalive: u16,
+) -> String {
+    let listen_port = listen_port
+        .map(|port| format!("ListenPort = {}\n", port))
+        .unwrap_or_default();
+
+    format!(
+        "[Interface]\n\
+         PrivateKey = {private_key}\n\
+         Address = {address}/{prefix}\n\
+         {listen_port}\
+         \n\
+         [Peer]\n\
+         PublicKey = {public_key}\n\
+         PresharedKey = {psk}\n\
+         Endpoint = {endpoint}\n\
+         AllowedIPs = {network}/{prefix}, {LAN_SUBNET}\n\
+         PersistentKeepalive = {keepalive}\n",
+        prefix = server.subnet.prefix(),
+        network = server.subnet.network(),
+        public_key = server.public_key,
+        endpoint = server.endpoint,
+    )
+}
+
+/// Subnets a peer routes beyond its own tunnel address (a site's LANs)
+fn remote_subnets(peer: &Section) -> Vec<Ipv4Network> {
+    peer.addresses()
+        .into_iter()
+        .filter(|net| net.prefix() != 32)
+        .collect()
+}
+
+/// Terminal QR code for a client config (empty if it doesn't fit)
+fn qr_code(text: &str) -> String {
+    QrCode::new(text.as_bytes())
+        .map(|code| code.render::<Dense1x2>().quiet_zone(true).build())
+        .unwrap_or_default()
+}
+
+/// Look up a peer in the config by name or public key
+fn find_peer(config: &WgConfig, name: &str) -> Result<usize> {
+    config
+        .find_peer(name)
+        .ok_or_else(|| ShannonError::NotFound(format!("Peer '{}'", name)).into())
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('!') || name.contains(['\n', '\r']) {
+        return Err(ShannonError::InvalidInput(format!("Invalid peer name '{}'", name)).into());
+    }
+    Ok(())
+}
+
+/// Add a peer: generate its keys, allocate an address, apply it live and
+/// print the client config
+pub fn add_peer(interface: &str, name: &str, split: bool, json: bool) -> Result<()> {
+    validate_name(name)?;
+
+    let mut config = wgconf::load(interface)?;
+    if config.find_peer(name).is_some() {
+        return Err(ShannonError::AlreadyExists(format!("Peer '{}'", name)).into());
+    }
+
+    let address = config.next_free_address().with_context(|| {
+        format!(
+            "No free address left in {}",
+            config.subnet().map(|s| s.to_string()).unwrap_or_default()
+        )
+    })?;
+    let (private_key, public_key) = wireguard::generate_keypair()?;
+    let psk = wireguard::generate_psk()?;
+    let allowed_ips = format!("{}/32", address);
+    let server = server_end(interface, &config)?;
+    let client_config = client_config(&server, &private_key, &psk, address, split);
+
+    config.add_peer(Section::peer(
+        name,
+        &[
+            ("PublicKey", &public_key),
+            ("PresharedKey", &psk),
+            ("AllowedIPs", &allowed_ips),
+        ],
+    ));
+    wgconf::save(interface, &config)?;
+    wireguard::set_peer(interface, &public_key, &psk, &allowed_ips)?;
+
+    let result = VpnAddPeerResult {
+        success: true,
+        name: name.to_string(),
+        address: address.to_string(),
+        public_key,
+        full_tunnel: !split,
+        qr_code: qr_code(&client_config),
+        client_config,
+    };
+
+    print_output(&result, json);
     Ok(())
 }
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnAddSiteResult {
+    pub success: bool,
+    pub name: String,
+    pub interface: String,
+    /// Tunnel address of the remote router
+    pub address: String,
+    pub public_key: String,
+    /// Remote LANs routed through the tunnel
+    pub remote_subnets: Vec<String>,
+    /// wg-quick config for the remote router, including its private key
+    pub remote_config: String,
+}
+
+impl Display for VpnAddSiteResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Added site {} on {} ({}, routing {})",
+            self.name,
+            self.interface,
+            self.address,
+            self.remote_subnets.join(", ")
+        )?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "Config for the remote router (it must also forward between the tunnel and its LAN):"
+        )?;
+        writeln!(f)?;
+        write!(f, "{}", self.remote_config)
+    }
+}
+
+/// Parse the LANs behind a new site; routes must not shadow our own
+/// networks, another peer's, or each other
+fn site_subnets(
+    config: &WgConfig,
+    tunnel_subnet: Ipv4Network,
+    requested: &[String],
+) -> Result<Vec<Ipv4Network>> {
+    let mut taken: Vec<Ipv4Network> = config
+        .sections
+        .iter()
+        .filter(|s| s.kind == SectionKind::Peer)
+        .flat_map(|s| s.addresses())
+        .collect();
+    taken.push(tunnel_subnet);
+    taken.push(LAN_SUBNET.parse()?);
+
+    let mut subnets = Vec::new();
+    for subnet in requested {
+        let net: Ipv4Network = subnet
+            .parse()
+            .map_err(|_| ShannonError::InvalidInput(format!("Invalid subnet '{}'", subnet)))?;
+        let net = Ipv4Network::new(net.network(), net.prefix())?;
+        if let Some(other) = taken.iter().find(|t| t.overlaps(net)) {
+            return Err(ShannonError::InvalidInput(format!(
+                "Subnet {} overlaps {}, which is already routed",
+                net, other
+            ))
+            .into());
+        }
+        taken.push(net);
+        subnets.push(net);
+    }
+    Ok(subnets)
+}
+
+/// Add a site-to-site peer: a remote router whose LANs are routed through
+/// the tunnel, kept open with a keepalive from both ends
+pub fn add_site(
+    interface: &str,
+    name: &str,
+    remote_subnets: &[String],
+    endpoint: Option<&str>,
+    keepalive: u16,
+    json: bool,
+) -> Result<()> {
+    validate_name(name)?;
+
+    let mut config = wgconf::load(interface)?;
+    if config.find_peer(name).is_some() {
+        return Err(ShannonError::AlreadyExists(format!("Peer '{}'", name)).into());
+    }
+    let server = server_end(interface, &config)?;
+    let subnets = site_subnets(&config, server.subnet, remote_subnets)?;
+
+    let listen_port = match endpoint {
+        Some(endpoint) => {
+            let port = endpoint
+                .rsplit_once(':')
+                .and_then(|(host, port)| (!host.is_empty()).then_some(port))
+                .filter(|port| port.parse::<u16>().is_ok())
+                .ok_or_else(|| {
+                    ShannonError::InvalidInput(format!("Endpoint '{}' must be host:port", endpoint))
+                })?;
+            Some(port)
+        }
+        None => None,
+    };
+
+    let address = config
+        .next_free_address()
+        .with_context(|| format!("No free address left in {}", server.subnet))?;
+    let (private_key, public_key) = wireguard::generate_keypair()?;
+    let psk = wireguard::generate_psk()?;
+    let remote_config = site_config(&server, &private_key, &psk, address, listen_port, keepalive);
+
+    let allowed_ips = std::iter::once(format!("{}/32", address))
+        .chain(subnets.iter().map(|net| net.to_string()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let keepalive = keepalive.to_string();
+    let mut entries = vec![
+        ("PublicKey", public_key.as_str()),
+        ("PresharedKey", psk.as_str()),
+    ];
+    if let Some(endpoint) = endpoint {
+        entries.push(("Endpoint", endpoint));
+    }
+    entries.push(("AllowedIPs", &allowed_ips));
+    entries.push(("PersistentKeepalive", &keepalive));
+
+    config.add_peer(Section::peer(name, &entries));
+    wgconf::save(interface, &config)?;
+    wireguard::sync_config(interface)?;
+    for net in &subnets {
+        wireguard::add_route(interface, &net.to_string())?;
+    }
+
+    let result = VpnAddSiteResult {
+        success: true,
+        name: name.to_string(),
+        interface: interface.to_string(),
+        address: address.to_string(),
+        public_key,
+        remote_subnets: subnets.iter().map(|net| net.to_string()).collect(),
+        remote_config,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnRemovePeerResult {
+    pub success: bool,
+    pub name: String,
+    pub public_key: String,
+}
+
+impl Display for VpnRemovePeerResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Removed peer {}", self.name)
+    }
+}
+
+/// Revoke a peer: drop it from the config file and the running interface
+pub fn remove_peer(interface: &str, name: &str, yes: bool, json: bool) -> Result<()> {
+    let mut config = wgconf::load(interface)?;
+    let index = find_peer(&config, name)?;
+
+    if !confirm(&format!("Remove peer {}?", name), yes) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    let peer = config.remove_peer(index);
+    wgconf::save(interface, &config)?;
+    wireguard::sync_config(interface)?;
+    for subnet in remote_subnets(&peer) {
+        wireguard::remove_route(interface, &subnet.to_string())?;
+    }
+
+    let result = VpnRemovePeerResult {
+        success: true,
+        name: peer.name().unwrap_or_else(|| name.to_string()),
+        public_key: peer.get("PublicKey").unwrap_or_default().to_string(),
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnRenamePeerResult {
+    pub success: bool,
+    pub name: String,
+    pub new_name: String,
+}
+
+impl Display for VpnRenamePeerResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Renamed peer {} to {}", self.name, self.new_name)
+    }
+}
+
+/// Rename a peer (only its `# name` comment changes)
+pub fn rename_peer(interface: &str, name: &str, new_name: &str, json: bool) -> Result<()> {
+    validate_name(new_name)?;
+
+    let mut config = wgconf::load(interface)?;
+    let index = find_peer(&config, name)?;
+    if config.find_peer(new_name).is_some() {
+        return Err(ShannonError::AlreadyExists(format!("Peer '{}'", new_name)).into());
+    }
+
+    config.sections[index].set_name(new_name);
+    // Comments aren't part of the running config, so nothing to apply
+    wgconf::save(interface, &config)?;
+
+    let result = VpnRenamePeerResult {
+        success: true,
+        name: name.to_string(),
+        new_name: new_name.to_string(),
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnTogglePeerResult {
+    pub success: bool,
+    pub name: String,
+    pub enabled: bool,
+}
+
+impl Display for VpnTogglePeerResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = if self.enabled { "Enabled" } else { "Disabled" };
+        write!(f, "{} peer {}", state, self.name)
+    }
+}
+
+/// Disable or re-enable a peer; disabled peers stay in the config commented out
+pub fn set_peer_enabled(
+    interface: &str,
+    name: &str,
+    enabled: bool,
+    yes: bool,
+    json: bool,
+) -> Result<()> {
+    let mut config = wgconf::load(interface)?;
+    let index = find_peer(&config, name)?;
+
+    if config.sections[index].disabled == enabled {
+        if !enabled && !confirm(&format!("Disable peer {}?", name), yes) {
+            return Err(ShannonError::Cancelled.into());
+        }
+        config.sections[index].disabled = !enabled;
+        wgconf::save(interface, &config)?;
+        wireguard::sync_config(interface)?;
+        for subnet in remote_subnets(&config.sections[index]) {
+            if enabled {
+                wireguard::add_route(interface, &subnet.to_string())?;
+            } else {
+                wireguard::remove_route(interface, &subnet.to_string())?;
+            }
+        }
+    }
+
+    let result = VpnTogglePeerResult {
+        success: true,
+        name: name.to_string(),
+        enabled,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnRotateKeyResult {
+    pub success: bool,
+    pub name: String,
+    pub address: String,
+    pub public_key: String,
+    pub full_tunnel: bool,
+    /// New wg-quick config for the client, including its private key
+    pub client_config: String,
+    #[serde(skip)]
+    pub qr_code: String,
+}
+
+impl Display for VpnRotateKeyResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Rotated keys for {} ({}); the old client config no longer works",
+            self.name, self.address
+        )?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.client_config)?;
+        write!(f, "{}", self.qr_code)
+    }
+}
+
+/// Give a peer a new keypair and preshared key, keeping its address
+pub fn rotate_key(interface: &str, name: &str, split: bool, yes: bool, json: bool) -> Result<()> {
+    let mut config = wgconf::load(interface)?;
+    let index = find_peer(&config, name)?;
+
+    let address = config.sections[index]
+        .get("AllowedIPs")
+        .and_then(|ips| ips.split(',').next())
+        .and_then(|ip| ip.trim().split('/').next()?.parse::<Ipv4Addr>().ok())
+        .with_context(|| format!("Peer '{}' has no IPv4 address", name))?;
+
+    if !confirm(
+        &format!(
+            "Rotate keys for {}? Its current config will stop working.",
+            name
+        ),
+        yes,
+    ) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    let (private_key, public_key) = wireguard::generate_keypair()?;
+    let psk = wireguard::generate_psk()?;
+    let server = server_end(interface, &config)?;
+    let peer = &config.sections[index];
+    let client_config = if remote_subnets(peer).is_empty() {
+        client_config(&server, &private_key, &psk, address, split)
+    } else {
+        let listen_port = peer
+            .get("Endpoint")
+            .and_then(|endpoint| endpoint.rsplit_once(':'))
+            .map(|(_, port)| port);
+        let keepalive = peer
+            .get("PersistentKeepalive")
+            .and_then(|k| k.parse().ok())
+            .unwrap_or(DEFAULT_KEEPALIVE);
+        site_config(&server, &private_key, &psk, address, listen_port, keepalive)
+    };
+
+    let peer = &mut config.sections[index];
+    peer.set("PublicKey", &public_key);
+    peer.set("PresharedKey", &psk);
+    wgconf::save(interface, &config)?;
+    // syncconf drops the old key and adds the new one; other peers are untouched
+    wireguard::sync_config(interface)?;
+
+    let result = VpnRotateKeyResult {
+        success: true,
+        name: name.to_string(),
+        address: address.to_string(),
+        public_key,
+        full_tunnel: !split,
+        qr_code: qr_code(&client_config),
+        client_config,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnSampleResult {
+    /// Interfaces that were up and got sampled
+    pub interfaces: Vec<String>,
+    /// Number of peers recorded, across all interfaces
+    pub peers: usize,
+}
+
+impl Display for VpnSampleResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.interfaces.is_empty() {
+            return writeln!(f, "No WireGuard interface is up; nothing recorded");
+        }
+        writeln!(
+            f,
+            "Recorded traffic of {} peers on {}",
+            self.peers,
+            self.interfaces.join(", ")
+        )
+    }
+}
+
+/// Append every running interface's per-peer counters to the traffic history
+pub fn sample(json: bool) -> Result<()> {
+    let mut result = VpnSampleResult {
+        interfaces: Vec::new(),
+        peers: 0,
+    };
+    for status in wireguard::get_all_status()? {
+        if status.interface_up {
+            result.peers += traffic::record(&status.interface, &status)?.peers.len();
+            result.interfaces.push(status.interface);
+        }
+    }
+
+    print_output(&result, json);
+    Ok(())
+}
+
+impl TableRow for PeerUsage {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.interface.clone(),
+            format_bytes(self.rx_bytes),
+            format_bytes(self.tx_bytes),
+            sparkline(&self.buckets),
+            self.endpoints
+                .iter()
+                .map(|e| match &e.country {
+                    Some(country) => format!("{} ({})", e.ip, country),
+                    None => e.ip.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+        ]
+    }
+}
+
+/// Traffic per peer since `since` (default 24h), with endpoint countries
+pub fn usage(
+    interface: Option<&str>,
+    peer: Option<&str>,
+    since: Option<Duration>,
+    json: bool,
+) -> Result<()> {
+    let until = Utc::now();
+    let since = until - since.unwrap_or(Duration::hours(24));
+
+    let mut rows = traffic::summarize(&traffic::read_since(since)?, since, until, USAGE_BUCKETS);
+    if let Some(interface) = interface {
+        rows.retain(|u| u.interface == interface);
+    }
+    if let Some(peer) = peer {
+        rows.retain(|u| u.name == peer || u.public_key == peer);
+        if rows.is_empty() {
+            // Unknown peer, as opposed to one that was idle
+            let interfaces = match interface {
+                Some(interface) => vec![interface.to_string()],
+                None => wireguard::server_interfaces()?,
+            };
+            let known = interfaces.iter().any(|iface| {
+                wgconf::load(iface).is_ok_and(|config| config.find_peer(peer).is_some())
+            });
+            if !known {
+                return Err(ShannonError::NotFound(format!("Peer '{}'", peer)).into());
+            }
+        }
+    }
+
+    let ips: Vec<IpAddr> = rows
+        .iter()
+        .flat_map(|u| &u.endpoints)
+        .filter_map(|e| e.ip.parse().ok())
+        .collect();
+    let countries = geoip::countries(&ips).unwrap_or_default();
+    for endpoint in rows.iter_mut().flat_map(|u| &mut u.endpoints) {
+        endpoint.country = endpoint
+            .ip
+            .parse()
+            .ok()
+            .and_then(|ip: IpAddr| countries.get(&ip).cloned());
+    }
+
+    let output = TableOutput {
+        headers: vec![
+            "Peer".to_string(),
+            "Interface".to_string(),
+            "RX".to_string(),
+            "TX".to_string(),
+            "Trend".to_string(),
+            "Endpoints".to_string(),
+        ],
+        rows,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnEgressSetupResult {
+    pub success: bool,
+    pub interface: String,
+    /// Provider server the tunnel connects to
+    pub endpoint: Option<String>,
+    /// Our address inside the provider's network
+    pub address: Option<String>,
+    /// DNS servers dropped from the provider config (the LAN keeps using AdGuard Home)
+    pub dropped_dns: Vec<String>,
+}
+
+impl Display for VpnEgressSetupResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Egress tunnel {} up via {}",
+            self.interface,
+            self.endpoint.as_deref().unwrap_or("provider")
+        )?;
+        if !self.dropped_dns.is_empty() {
+            writeln!(
+                f,
+                "Ignored provider DNS {} (LAN DNS stays on AdGuard Home)",
+                self.dropped_dns.join(", ")
+            )?;
+        }
+        writeln!(
+            f,
+            "Route devices through it with: shannon vpn egress add <device>"
+        )
+    }
+}
+
+/// Import a provider's WireGuard client config as the egress tunnel: no
+/// default route of its own (Table = off), policy routing hooks instead, and
+/// the kill switch in place before it comes up
+pub fn egress_setup(interface: &str, path: &Path, yes: bool, json: bool) -> Result<()> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        ShannonError::InvalidInput(format!("Cannot read {}: {}", path.display(), e))
+    })?;
+    let not_client = || {
+        ShannonError::InvalidInput(format!(
+            "{} is not a WireGuard client config (needs [Interface] with PrivateKey and a [Peer] with Endpoint)",
+            path.display()
+        ))
+    };
+    let mut config = WgConfig::parse(&text).map_err(|_| not_client())?;
+    let endpoint = config
+        .sections
+        .iter()
+        .find(|s| s.kind == SectionKind::Peer && !s.disabled)
+        .and_then(|peer| peer.get("Endpoint"))
+        .map(str::to_string)
+        .ok_or_else(not_client)?;
+    let index = config
+        .sections
+        .iter()
+        .position(|s| s.kind == SectionKind::Interface && s.get("PrivateKey").is_some())
+        .ok_or_else(not_client)?;
+
+    let section = &mut config.sections[index];
+    // wg-quick would point the router's own resolver at the provider
+    let dropped_dns = section.remove("DNS");
+    for key in ["Table", "PreUp", "PostUp", "PreDown", "PostDown"] {
+        section.remove(key);
+    }
+    section.set("Table", "off");
+    for (key, command) in egress::hooks() {
+        section.push(key, &command);
+    }
+    let address = section.get("Address").map(str::to_string);
+
+    if wgconf::load(interface).is_ok()
+        && !confirm(&format!("Replace the existing {} config?", interface), yes)
+    {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    wgconf::save(interface, &config)?;
+    egress::ensure_kill_switch()?;
+    let unit = format!("wg-quick@{}", interface);
+    systemctl("enable", &unit)?;
+    systemctl("restart", &unit)?;
+
+    let result = VpnEgressSetupResult {
+        success: true,
+        interface: interface.to_string(),
+        endpoint: Some(endpoint),
+        address,
+        dropped_dns,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// MAC and name of a LAN device given by MAC, hostname or IP
+fn resolve_device(device: &str) -> Result<(String, String)> {
+    let dnsmasq = DnsmasqAdapter::new();
+    let known: Vec<(String, String, Option<String>)> = dnsmasq
+        .list_leases()?
+        .into_iter()
+        .map(|l| (l.mac, l.ip, l.hostname))
+        .chain(
+            dnsmasq
+                .list_reservations()?
+                .into_iter()
+                .map(|r| (r.mac, r.ip, r.hostname)),
+        )
+        .collect();
+    let found = known.iter().find(|(mac, ip, hostname)| {
+        mac.eq_ignore_ascii_case(device) || hostname.as_deref() == Some(device) || ip == device
+    });
+
+    let is_mac = device.len() == 17
+        && device
+            .split(':')
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit()));
+    let (mac, name) = match found {
+        Some((mac, _, Some(hostname))) => (mac.clone(), hostname.clone()),
+        Some((mac, _, None)) => (mac.clone(), mac.clone()),
+        None if is_mac => (device.to_string(), device.to_string()),
+        None => return Err(ShannonError::NotFound(format!("Device '{}'", device)).into()),
+    };
+
+    // The name ends up in an iptables comment
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || ".-_:".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    Ok((mac.to_ascii_lowercase(), name))
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct VpnEgressDeviceResult {
+    pub success: bool,
+    pub mac: String,
+    pub name: String,
+    /// Whether the device now egresses via the tunnel
+    pub via_tunnel: bool,
+}
+
+impl Display for VpnEgressDeviceResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let route = if self.via_tunnel {
+            "the egress tunnel"
+        } else {
+            "the WAN"
+        };
+        write!(
+            f,
+            "{} ({}) now reaches the internet via {}",
+            self.name, self.mac, route
+        )
+    }
+}
+
+/// Route a LAN device's internet traffic through the egress tunnel
+pub fn egress_add(device: &str, json: bool) -> Result<()> {
+    let (mac, name) = resolve_device(device)?;
+    if egress::list_devices()?.iter().any(|d| d.mac == mac) {
+        return Err(ShannonError::AlreadyExists(format!("Egress for {}", device)).into());
+    }
+
+    egress::add_device(&mac, &name)?;
+
+    let result = VpnEgressDeviceResult {
+        success: true,
+        mac,
+        name,
+        via_tunnel: true,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+/// Send a device back out through the WAN
+pub fn egress_remove(device: &str, yes: bool, json: bool) -> Result<()> {
+    let devices = egress::list_devices()?;
+    let found = devices
+        .iter()
+        .find(|d| d.mac.eq_ignore_ascii_case(device) || d.name == device);
+    let existing = match found {
+        Some(existing) => existing,
+        None => {
+            // Also accept the device's current hostname or IP
+            let (mac, _) = resolve_device(device)?;
+            devices
+                .iter()
+                .find(|d| d.mac == mac)
+                .ok_or_else(|| ShannonError::NotFound(format!("Egress for {}", device)))?
+        }
+    };
+
+    if !confirm(&format!("Route {} via the WAN again?", existing.name), yes) {
+        return Err(ShannonError::Cancelled.into());
+    }
+
+    egress::remove_device(&existing.mac, &existing.name)?;
+
+    let result = VpnEgressDeviceResult {
+        success: true,
+        mac: existing.mac.clone(),
+        name: existing.name.clone(),
+        via_tunnel: false,
+    };
+
+    print_output(&result, json);
+    Ok(())
+}
+
+impl TableRow for EgressDevice {
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.mac.clone(),
+            self.ip.clone().unwrap_or_else(|| "-".to_string()),
+        ]
+    }
+}
+
+/// Devices routed through the egress tunnel, with their current addresses
+pub fn egress_list(json: bool) -> Result<()> {
+    let leases = DnsmasqAdapter::new().list_leases().unwrap_or_default();
+    let mut devices = egress::list_devices()?;
+    for device in &mut devices {
+        device.ip = leases
+            .iter()
+            .find(|l| l.mac.eq_ignore_ascii_case(&device.mac))
+            .map(|l| l.ip.clone());
+    }
+
+    let output = TableOutput {
+        headers: vec!["Device".to_string(), "MAC".to_string(), "IP".to_string()],
+        rows: devices,
+    };
+
+    print_output(&output, json);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+
+    const CONFIG: &str = "\
+[Interface]
+Address = 10.8.0.1/24
+ListenPort = 51820
+PrivateKey = c2VydmVy
+
+# phone
+[Peer]
+PublicKey = cGhvbmU=
+AllowedIPs = 10.8.0.2/32
+
+# cabin
+[Peer]
+PublicKey = Y2FiaW4=
+AllowedIPs = 10.8.0.3/32, 192.168.10.0/24
+";
+
+    fn subnets(requested: &[&str]) -> Result<Vec<String>> {
+        let config = WgConfig::parse(CONFIG).unwrap();
+        let requested: Vec<String> = requested.iter().map(|s| s.to_string()).collect();
+        site_subnets(&config, "10.8.0.0/24".parse().unwrap(), &requested)
+            .map(|nets| nets.iter().map(|net| net.to_string()).collect())
+    }
+
+    #[test]
+    fn test_site_subnets_accepts_disjoint() {
+        assert_eq!(
+            subnets(&["192.168.20.0/24", "10.20.0.7/16"]).unwrap(),
+            vec!["192.168.20.0/24", "10.20.0.0/16"]
+        );
+    }
+
+    #[test]
+    fn test_site_subnets_rejects_overlaps() {
+        // Our LAN, the tunnel, and another site's LAN
+        for subnet in ["192.168.4.128/25", "10.0.0.0/8", "192.168.10.0/23"] {
+            let err = subnets(&[subnet]).unwrap_err();
+            assert!(matches!(
+                error::find(&err),
+                Some(ShannonError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_site_subnets_rejects_duplicates() {
+        let err = subnets(&["192.168.20.0/24", "192.168.20.1/24"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Subnet 192.168.20.0/24 overlaps 192.168.20.0/24, which is already routed"
+        );
+    }
+
+    #[test]
+    fn test_remote_subnets_skips_tunnel_address() {
+        let config = WgConfig::parse(CONFIG).unwrap();
+        let cabin = &config.sections[config.find_peer("cabin").unwrap()];
+        let phone = &config.sections[config.find_peer("phone").unwrap()];
+        assert_eq!(
+            remote_subnets(cabin),
+            vec!["192.168.10.0/24".parse::<Ipv4Network>().unwrap()]
+        );
+        assert!(remote_subnets(phone).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Typed errors with stable codes for the `--json` error envelope
+//!
+//! Codes and exit statuses are part of the agent-facing interface: add new
+//! variants rather than changing existing ones.
+//!
+//! | Exit | Code             | Meaning                                      |
+//! |------|------------------|----------------------------------------------|
+//! | 0    | —                | Success                                      |
+//! | 1    | `internal`       | Unexpected error                             |
+//! | 2    | `usage`          | Invalid command line                         |
+//! | 3    | `cancelled`      | Confirmation prompt declined                 |
+//! | 4    | `not_found`      | Record, rule or snapshot doesn't exist       |
+//! | 5    | `conflict`       | Record or rule already exists                |
+//! | 6    | `invalid_input`  | Argument rejected                            |
+//! | 7    | `command_failed` | Command on SHANNON failed (or SSH failed)    |
+//! | 8    | `checks_failed`  | `doctor` ran, but some checks failed         |
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ShannonError {
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    #[error("{0} not found")]
+    NotFound(String),
+
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+
+    #[error("{0}")]
+    InvalidInput(String),
+
+    #[error("{command} failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+
+    /// Reported by the command itself, together with the check results
+    #[error("{failed} of {total} checks failed")]
+    ChecksFailed { failed: usize, total: usize },
+}
+
+impl ShannonError {
+    /// A command that ran but exited unsuccessfully
+    pub fn command_failed(command: impl Into<String>, output: &std::process::Output) -> Self {
+        ShannonError::CommandFailed {
+            command: command.into(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+    }
+
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            ShannonError::Cancelled => "cancelled",
+            ShannonError::NotFound(_) => "not_found",
+            ShannonError::AlreadyExists(_) => "conflict",
+            ShannonError::InvalidInput(_) => "invalid_input",
+            ShannonError::CommandFailed { .. } => "command_failed",
+            ShannonError::ChecksFailed { .. } => "checks_failed",
+        }
+    }
+
+    /// Process exit status for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ShannonError::Cancelled => 3,
+            ShannonError::NotFound(_) => 4,
+            ShannonError::AlreadyExists(_) => 5,
+            ShannonError::InvalidInput(_) => 6,
+            ShannonError::CommandFailed { .. } => 7,
+            ShannonError::ChecksFailed { .. } => 8,
+        }
+    }
+
+    /// Suggested next step, if there is an obvious one
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            ShannonError::Cancelled => {
+                Some("Re-run with --yes to skip the confirmation prompt".to_string())
+            }
+            ShannonError::NotFound(_) => {
+                Some("Use the matching list command to see what exists".to_string())
+            }
+            ShannonError::AlreadyExists(_) => {
+                Some("Remove the existing entry first, or choose another name".to_string())
+            }
+            ShannonError::InvalidInput(_) => None,
+            ShannonError::CommandFailed { .. } => Some(
+                "Check that SHANNON is reachable ('ssh shannon') and shannon runs as root"
+                    .to_string(),
+            ),
+            ShannonError::ChecksFailed { .. } => {
+                Some("See the failed checks in data.checks".to_string())
+            }
+        }
+    }
+}
+
+/// Exit status for the usage errors clap reports
+pub const USAGE_EXIT_CODE: i32 = 2;
+
+/// Exit status for errors without a `ShannonError` in their chain
+pub const INTERNAL_EXIT_CODE: i32 = 1;
+
+/// Find the typed error in an error chain, if there is one
+pub fn find(err: &anyhow::Error) -> Option<&ShannonError> {
+    err.chain().find_map(|e| e.downcast_ref::<ShannonError>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_find_sees_through_context() {
+        let err = anyhow::Error::from(ShannonError::NotFound("Record nas".to_string()))
+            .context("Failed to remove DNS record");
+        let typed = find(&err).unwrap();
+        assert_eq!(typed.code(), "not_found");
+        assert_eq!(typed.exit_code(), 4);
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct_and_match_table() {
+        let errors = [
+            (ShannonError::Cancelled, "cancelled", 3),
+            (ShannonError::NotFound(String::new()), "not_found", 4),
+            (ShannonError::AlreadyExists(String::new()), "conflict", 5),
+            (
+                ShannonError::InvalidInput(String::new()),
+                "invalid_input",
+                6,
+            ),
+            (
+                ShannonError::CommandFailed {
+                    command: String::new(),
+                    stderr: String::new(),
+                },
+                "command_failed",
+                7,
+            ),
+            (
+                ShannonError::ChecksFailed {
+                    failed: 1,
+                    total: 2,
+                },
+                "checks_failed",
+                8,
+            ),
+        ];
+        for (err, code, exit) in &errors {
+            assert_eq!(err.code(), *code);
+            assert_eq!(err.exit_code(), *exit);
+            assert_ne!(err.exit_code(), USAGE_EXIT_CODE);
+            assert_ne!(err.exit_code(), INTERNAL_EXIT_CODE);
+        }
+    }
+
+    #[test]
+    fn test_untyped_errors_have_no_code() {
+        let err = Err::<(), _>(std::io::Error::other("boom"))
+            .context("Failed to parse")
+            .unwrap_err();
+        assert!(find(&err).is_none());
+    }
+}
@@ -0,0 +1,407 @@
+//! Persistent inventory of devices seen on the network
+//!
+//! `/var/lib/shannon/devices.json` holds one entry per device, keyed by MAC
+//! address (WireGuard peers, which have none, by `wg:<public key>`). Each
+//! refresh merges what is visible right now — DHCP leases, static
+//! reservations, the kernel's neighbour table and WireGuard handshakes — into
+//! the stored entries, keeping first/last seen and the names, owners and tags
+//! assigned with `shannon device`.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use crate::adapters::dnsmasq::DnsmasqAdapter;
+use crate::adapters::nftables::NftablesAdapter;
+use crate::adapters::system::WAN_INTERFACE;
+use crate::adapters::wireguard;
+use crate::error::ShannonError;
+use crate::location::{execute_shell, execute_shell_with_input, write_file};
+
+pub const INVENTORY_PATH: &str = "/var/lib/shannon/devices.json";
+
+/// OUI prefix → vendor, one `AABBCC<TAB>Vendor` line each, sorted
+/// (regenerate with `scripts/inventory/update-oui.sh`)
+const OUI_DATABASE: &str = include_str!("../data/oui.tsv");
+
+/// A peer counts as present while its handshakes are this recent
+const WIREGUARD_PRESENCE_MINUTES: i64 = 3;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct Device {
+    /// MAC address (lowercase), or `wg:<public key>` for WireGuard peers
+    pub id: String,
+    /// Friendly name set with `shannon device name`
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Hostname from DHCP, or the WireGuard peer name
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Manufacturer from the MAC's OUI
+    #[serde(default)]
+    pub vendor: Option<String>,
+    /// Most recent address
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// Where the device was found in the latest refresh: "dhcp",
+    /// "reservation", "neighbour" or "wireguard"
+    #[serde(default)]
+    pub sources: Vec<String>,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Whether the device was present in the latest refresh
+    #[serde(default)]
+    pub online: bool,
+    /// Cut off from forwarding by `shannon device quarantine`
+    #[serde(default)]
+    pub quarantined: bool,
+}
+
+impl Device {
+    /// Friendly name, falling back to hostname, then id
+    pub fn label(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.hostname.as_deref())
+            .unwrap_or(&self.id)
+    }
+}
+
+/// Something a source reported about a device during a refresh
+struct Sighting {
+    id: String,
+    ip: Option<String>,
+    hostname: Option<String>,
+    source: &'static str,
+    /// When the device was last active; None if the source only proves it
+    /// exists (a reservation), not that it is present
+    seen: Option<DateTime<Utc>>,
+}
+
+/// Vendor of a MAC address from the bundled OUI database
+pub fn vendor(mac: &str) -> Option<String> {
+    let hex: String = mac.chars().filter(|c| c.is_ascii_hexdigit()).collect();
+    let prefix = hex.get(..6)?.to_ascii_uppercase();
+
+    // The locally administered bit marks randomized (private) addresses
+    let first_octet = u8::from_str_radix(&prefix[..2], 16).ok()?;
+    if first_octet & 0x02 != 0 {
+        return Some("Private (randomized MAC)".to_string());
+    }
+
+    let entries = oui_entries();
+    let index = entries
+        .binary_search_by(|(oui, _)| (*oui).cmp(prefix.as_str()))
+        .ok()?;
+    Some(entries[index].1.to_string())
+}
+
+/// (OUI, vendor) pairs of the bundled registry, sorted by OUI
+fn oui_entries() -> &'static [(&'static str, &'static str)] {
+    static ENTRIES: OnceLock<Vec<(&str, &str)>> = OnceLock::new();
+    ENTRIES.get_or_init(|| {
+        OUI_DATABASE
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .collect()
+    })
+}
+
+/// Stored inventory, keyed by id (empty before the first refresh)
+pub fn load() -> Result<BTreeMap<String, Device>> {
+    let output = execute_shell(&format!("cat {} 2>/dev/null", INVENTORY_PATH))?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    if text.trim().is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let devices: Vec<Device> = serde_json::from_str(&text)?;
+    Ok(devices.into_iter().map(|d| (d.id.clone(), d)).collect())
+}
+
+fn render(devices: &BTreeMap<String, Device>) -> Result<String> {
+    let list: Vec<&Device> = devices.values().collect();
+    Ok(format!("{}\n", serde_json::to_string_pretty(&list)?))
+}
+
+/// Save a user's edits (names, owners, tags) as a journaled change
+pub fn save(devices: &BTreeMap<String, Device>) -> Result<()> {
+    execute_shell(&format!("mkdir -p $(dirname {})", INVENTORY_PATH))?;
+    write_file(INVENTORY_PATH, &render(devices)?)
+}
+
+/// Save what was observed on the network
+///
+/// Bookkeeping rather than router state, so not a journaled mutation: it
+/// neither shows up in dry-run previews nor gets attached to whatever
+/// audited action runs next on this thread.
+fn store(devices: &BTreeMap<String, Device>) -> Result<()> {
+    let output = execute_shell_with_input(
+        &format!(
+            "mkdir -p $(dirname {path}) && cat > {path}.shannon-new && mv {path}.shannon-new {path}",
+            path = INVENTORY_PATH
+        ),
+        &render(devices)?,
+    )?;
+    if !output.status.success() {
+        return Err(ShannonError::command_failed("save device inventory", &output).into());
+    }
+    Ok(())
+}
+
+/// Merge what is visible right now into the stored inventory and save it
+pub fn refresh() -> Result<BTreeMap<String, Device>> {
+    let devices = current()?;
+    store(&devices)?;
+    Ok(devices)
+}
+
+/// Stored inventory with what is visible right now merged in, not yet saved
+pub fn current() -> Result<BTreeMap<String, Device>> {
+    let mut devices = load()?;
+    let now = Utc::now();
+    let mut sightings = Vec::new();
+
+    let dnsmasq = DnsmasqAdapter::new();
+    for lease in dnsmasq.list_leases()? {
+        sightings.push(Sighting {
+            id: lease.mac.to_ascii_lowercase(),
+            ip: Some(lease.ip),
+            hostname: lease.hostname,
+            source: "dhcp",
+            seen: lease.active.then_some(now),
+        });
+    }
+    for reservation in dnsmasq.list_reservations()? {
+        sightings.push(Sighting {
+            id: reservation.mac.to_ascii_lowercase(),
+            ip: Some(reservation.ip),
+            hostname: reservation.hostname,
+            source: "reservation",
+            seen: None,
+        });
+    }
+    sightings.extend(neighbours()?.into_iter().map(|(ip, mac)| Sighting {
+        id: mac,
+        ip: Some(ip),
+        hostname: None,
+        source: "neighbour",
+        seen: Some(now),
+    }));
+    for status in wireguard::get_all_status()? {
+        for peer in status.peers {
+            let active = peer
+                .last_handshake
+                .filter(|t| now - *t < Duration::minutes(WIREGUARD_PRESENCE_MINUTES));
+            sightings.push(Sighting {
+                id: format!("wg:{}", peer.public_key),
+                ip: peer
+                    .allowed_ips
+                    .split(", ")
+                    .next()
+                    .map(|net| net.trim_end_matches("/32").to_string())
+                    .filter(|ip| !ip.is_empty()),
+                hostname: Some(peer.name).filter(|n| !n.is_empty()),
+                source: "wireguard",
+                seen: active,
+            });
+        }
+    }
+
+    merge(&mut devices, sightings, now);
+
+    let quarantined = NftablesAdapter::new().list_quarantined()?;
+    for device in devices.values_mut() {
+        device.quarantined = quarantined.contains(&device.id);
+    }
+    Ok(devices)
+}
+
+fn merge(devices: &mut BTreeMap<String, Device>, sightings: Vec<Sighting>, now: DateTime<Utc>) {
+    for device in devices.values_mut() {
+        device.sources.clear();
+        device.online = false;
+    }
+    for sighting in sightings {
+        apply(devices, sighting, now);
+    }
+}
+
+fn apply(devices: &mut BTreeMap<String, Device>, sighting: Sighting, now: DateTime<Utc>) {
+    let device = devices
+        .entry(sighting.id.clone())
+        .or_insert_with(|| Device {
+            vendor: if sighting.id.starts_with("wg:") {
+                None
+            } else {
+                vendor(&sighting.id)
+            },
+            id: sighting.id,
+            name: None,
+            owner: None,
+            tags: Vec::new(),
+            hostname: None,
+            ip: None,
+            sources: Vec::new(),
+            first_seen: sighting.seen.unwrap_or(now),
+            last_seen: sighting.seen.unwrap_or(now),
+            online: false,
+            quarantined: false,
+        });
+    if sighting.ip.is_some() {
+        device.ip = sighting.ip;
+    }
+    if sighting.hostname.is_some() {
+        device.hostname = sighting.hostname;
+    }
+    if !device.sources.iter().any(|s| s == sighting.source) {
+        device.sources.push(sighting.source.to_string());
+    }
+    if let Some(seen) = sighting.seen {
+        device.online = true;
+        device.last_seen = device.last_seen.max(seen);
+    }
+}
+
+/// Record a lease handed out just now (from the dnsmasq `dhcp-script` hook)
+/// without a full refresh; returns the device and whether it is new
+pub fn record_lease(mac: &str, ip: &str, hostname: Option<&str>) -> Result<(Device, bool)> {
+    let mut devices = load()?;
+    let id = mac.to_ascii_lowercase();
+    let is_new = !devices.contains_key(&id);
+    apply(
+        &mut devices,
+        Sighting {
+            id: id.clone(),
+            ip: Some(ip.to_string()),
+            hostname: hostname.map(str::to_string),
+            source: "dhcp",
+            seen: Some(Utc::now()),
+        },
+        Utc::now(),
+    );
+    store(&devices)?;
+    Ok((devices[&id].clone(), is_new))
+}
+
+/// (IP, MAC) pairs from the kernel's IPv4 neighbour table, WAN side excluded
+fn neighbours() -> Result<Vec<(String, String)>> {
+    let output = execute_shell("ip -4 neigh show 2>/dev/null")?;
+    Ok(parse_neighbours(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_neighbours(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .filter_map(|line| {
+            // 192.168.4.84 dev eth0 lladdr aa:bb:cc:dd:ee:ff REACHABLE
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let after = |key: &str| {
+                let i = parts.iter().position(|p| *p == key)?;
+                parts.get(i + 1).copied()
+            };
+            let state = parts.last()?;
+            if after("dev")? == WAN_INTERFACE || matches!(*state, "FAILED" | "INCOMPLETE") {
+                return None;
+            }
+            Some((
+                parts.first()?.to_string(),
+                after("lladdr")?.to_ascii_lowercase(),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vendor_lookup() {
+        assert_eq!(
+            vendor("B8:27:EB:12:34:56").as_deref(),
+            Some("Raspberry Pi Foundation")
+        );
+        assert_eq!(
+            vendor("b8:27:eb:12:34:56").as_deref(),
+            Some("Raspberry Pi Foundation")
+        );
+        // Locally administered: a phone's per-network random address
+        assert_eq!(
+            vendor("da:a1:19:00:00:01").as_deref(),
+            Some("Private (randomized MAC)")
+        );
+        assert_eq!(vendor("00:00:01:00:00:01"), None);
+    }
+
+    #[test]
+    fn test_oui_database_is_sorted() {
+        // vendor() binary-searches it; update-oui.sh sorts with LC_ALL=C
+        assert!(oui_entries().windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_merge_keeps_assigned_names_and_first_seen() {
+        let then = DateTime::from_timestamp(1760000000, 0).unwrap();
+        let now = then + Duration::days(2);
+        let mut devices = BTreeMap::new();
+        merge(
+            &mut devices,
+            vec![Sighting {
+                id: "b8:27:eb:12:34:56".to_string(),
+                ip: Some("192.168.4.20".to_string()),
+                hostname: Some("pi".to_string()),
+                source: "dhcp",
+                seen: Some(then),
+            }],
+            then,
+        );
+        devices.get_mut("b8:27:eb:12:34:56").unwrap().name = Some("Kitchen Pi".to_string());
+
+        merge(
+            &mut devices,
+            vec![
+                Sighting {
+                    id: "b8:27:eb:12:34:56".to_string(),
+                    ip: Some("192.168.4.21".to_string()),
+                    hostname: None,
+                    source: "neighbour",
+                    seen: Some(now),
+                },
+                Sighting {
+                    id: "aa:00:00:00:00:01".to_string(),
+                    ip: Some("192.168.4.50".to_string()),
+                    hostname: None,
+                    source: "reservation",
+                    seen: None,
+                },
+            ],
+            now,
+        );
+
+        let pi = &devices["b8:27:eb:12:34:56"];
+        assert_eq!(pi.label(), "Kitchen Pi");
+        assert_eq!(pi.hostname.as_deref(), Some("pi"));
+        assert_eq!(pi.ip.as_deref(), Some("192.168.4.21"));
+        assert_eq!((pi.first_seen, pi.last_seen), (then, now));
+        assert_eq!(pi.sources, vec!["neighbour"]);
+        assert!(pi.online);
+        assert!(!devices["aa:00:00:00:00:01"].online);
+    }
+
+    #[test]
+    fn test_parse_neighbours_skips_wan_and_failed() {
+        let text = "192.168.4.84 dev eth0 lladdr AA:BB:CC:DD:EE:FF REACHABLE\n\
+            192.168.4.99 dev eth0 FAILED\n\
+            83.252.1.1 dev enxc84d4421f975 lladdr 00:11:22:33:44:55 STALE\n";
+        assert_eq!(
+            parse_neighbours(text),
+            vec![("192.168.4.84".to_string(), "aa:bb:cc:dd:ee:ff".to_string())]
+        );
+    }
+}
@@ -1,10 +1,69 @@
 //! Location detection for local vs remote execution
 
-use anyhow::{Context, Result};
-use std::process::{Command, Output, Stdio};
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::ShannonError;
+use crate::output::unified_diff;
 
 const SHANNON_HOSTNAME: &str = "shannon";
 
+/// Set by `--dry-run`: mutations are recorded instead of applied
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Dry-run for the current thread only, on top of the process-wide flag
+    static THREAD_DRY_RUN: Cell<bool> = const { Cell::new(false) };
+
+    /// Changes made (or, in dry-run mode, planned) by the current thread.
+    /// Per-thread so concurrent dashboard actions don't mix their journals.
+    static JOURNAL: RefCell<Vec<Change>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A change made to the router through one of the mutation helpers
+#[derive(Debug, Serialize, JsonSchema, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Change {
+    /// File rewrite, as a unified diff from the previous content
+    File { path: String, diff: String },
+    /// Shell command that changes router state
+    Command { command: String },
+}
+
+/// Enable or disable dry-run mode for this process
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::SeqCst);
+}
+
+/// Check if mutations should be recorded instead of applied
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst) || THREAD_DRY_RUN.with(Cell::get)
+}
+
+/// Run `f` in dry-run mode without affecting other threads
+#[cfg(test)]
+pub fn with_dry_run<R>(f: impl FnOnce() -> R) -> R {
+    THREAD_DRY_RUN.with(|flag| flag.set(true));
+    let result = f();
+    THREAD_DRY_RUN.with(|flag| flag.set(false));
+    result
+}
+
+/// Drain the changes recorded so far on this thread
+pub fn take_changes() -> Vec<Change> {
+    JOURNAL.with(|journal| std::mem::take(&mut *journal.borrow_mut()))
+}
+
+fn record(change: Change) {
+    JOURNAL.with(|journal| journal.borrow_mut().push(change));
+}
+
 /// Check if we're running on SHANNON itself
 pub fn is_local() -> bool {
     hostname::get()
@@ -20,15 +79,100 @@ pub fn execute_shell(cmd: &str) -> Result<Output> {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
-            .context("Failed to execute command locally")
+            .map_err(|e| spawn_failed("sh", e))
     } else {
         Command::new("ssh")
             .args([SHANNON_HOSTNAME, cmd])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
-            .context("Failed to execute command via SSH")
+            .map_err(|e| spawn_failed("ssh", e))
+    }
+}
+
+/// The shell (or ssh) couldn't be started at all
+fn spawn_failed(program: &str, err: std::io::Error) -> anyhow::Error {
+    ShannonError::CommandFailed {
+        command: program.to_string(),
+        stderr: err.to_string(),
+    }
+    .into()
+}
+
+/// Execute a shell command with `input` on its stdin
+///
+/// Used to hand secrets (keys) to commands without putting them on the
+/// command line, where they would end up in the audit log.
+pub fn execute_shell_with_input(cmd: &str, input: &str) -> Result<Output> {
+    let mut command = if is_local() {
+        let mut c = Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    } else {
+        let mut c = Command::new("ssh");
+        c.args([SHANNON_HOSTNAME, cmd]);
+        c
+    };
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_failed(cmd, e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|e| spawn_failed(cmd, e))?;
+    }
+    child.wait_with_output().map_err(|e| spawn_failed(cmd, e))
+}
+
+/// Execute a shell command that changes router state
+///
+/// In dry-run mode the command is recorded and reported as successful.
+pub fn execute_mutation(cmd: &str) -> Result<Output> {
+    record(Change::Command {
+        command: cmd.to_string(),
+    });
+    if is_dry_run() {
+        return Ok(dry_run_output());
+    }
+    execute_shell(cmd)
+}
+
+/// Like `execute_mutation`, with `input` on stdin; only the command is recorded
+pub fn execute_mutation_with_input(cmd: &str, input: &str) -> Result<Output> {
+    record(Change::Command {
+        command: cmd.to_string(),
+    });
+    if is_dry_run() {
+        return Ok(dry_run_output());
     }
+    execute_shell_with_input(cmd, input)
+}
+
+fn dry_run_output() -> Output {
+    Output {
+        status: ExitStatus::from_raw(0),
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+    }
+}
+
+/// Mask WireGuard private and preshared keys in a diff
+pub fn redact_secrets(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            let lower = line.to_ascii_lowercase();
+            match line.find('=') {
+                Some(eq) if lower.contains("privatekey") || lower.contains("presharedkey") => {
+                    format!("{}= <redacted>\n", &line[..eq])
+                }
+                _ => format!("{}\n", line),
+            }
+        })
+        .collect()
 }
 
 /// Read a file, either locally or via SSH
@@ -37,58 +181,136 @@ pub fn read_file(path: &str) -> Result<String> {
     if output.status.success() {
         Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     } else {
-        anyhow::bail!(
-            "Failed to read {}: {}",
-            path,
-            String::from_utf8_lossy(&output.stderr)
-        )
+        Err(ShannonError::command_failed(format!("read {}", path), &output).into())
     }
 }
 
 /// Write content to a file, either locally or via SSH
+///
+/// The content is staged next to the target and renamed over it, keeping the
+/// original mode and owner, so readers never see a half-written file.
 pub fn write_file(path: &str, content: &str) -> Result<()> {
+    let current = read_file(path).unwrap_or_default();
+    if current == content {
+        return Ok(());
+    }
+    record(Change::File {
+        path: path.to_string(),
+        diff: redact_secrets(&unified_diff(path, path, &current, content)),
+    });
+    if is_dry_run() {
+        return Ok(());
+    }
+
     // Escape content for shell
     let escaped = content.replace('\'', "'\\''");
-    let cmd = format!("printf '%s' '{}' > {}", escaped, path);
+    let cmd = format!(
+        "printf '%s' '{content}' > {path}.shannon-new && \
+         {{ [ ! -e {path} ] || {{ chmod --reference={path} {path}.shannon-new && \
+         chown --reference={path} {path}.shannon-new; }}; }} && \
+         mv {path}.shannon-new {path}",
+        content = escaped,
+        path = path
+    );
     let output = execute_shell(&cmd)?;
     if output.status.success() {
         Ok(())
     } else {
-        anyhow::bail!(
-            "Failed to write {}: {}",
-            path,
-            String::from_utf8_lossy(&output.stderr)
-        )
+        Err(ShannonError::command_failed(format!("write {}", path), &output).into())
     }
 }
 
-/// Append content to a file
-pub fn append_file(path: &str, content: &str) -> Result<()> {
-    let escaped = content.replace('\'', "'\\''");
-    let cmd = format!("printf '%s' '{}' >> {}", escaped, path);
-    let output = execute_shell(&cmd)?;
+/// Replace several files as one unit, removing those given as `None`
+///
+/// Every new version is staged next to its target and the originals are set
+/// aside before anything is renamed. If any step fails, all files are put
+/// back the way they were.
+pub fn write_files(files: &[(&str, Option<&str>)]) -> Result<()> {
+    let mut changed = Vec::new();
+    for &(path, content) in files {
+        let current = read_file(path).ok();
+        if current.as_deref() == content {
+            continue;
+        }
+        record(Change::File {
+            path: path.to_string(),
+            diff: redact_secrets(&unified_diff(
+                path,
+                path,
+                current.as_deref().unwrap_or(""),
+                content.unwrap_or(""),
+            )),
+        });
+        changed.push((path, content));
+    }
+    if changed.is_empty() || is_dry_run() {
+        return Ok(());
+    }
+
+    let output = execute_shell(&replace_files_script(&changed))?;
     if output.status.success() {
         Ok(())
     } else {
-        anyhow::bail!(
-            "Failed to append to {}: {}",
-            path,
-            String::from_utf8_lossy(&output.stderr)
-        )
+        let paths: Vec<&str> = changed.iter().map(|(path, _)| *path).collect();
+        Err(ShannonError::command_failed(format!("write {}", paths.join(", ")), &output).into())
     }
 }
 
+/// Shell script behind `write_files`: stage, set aside, swap, or roll back
+fn replace_files_script(files: &[(&str, Option<&str>)]) -> String {
+    let mut stage = Vec::new();
+    let mut swap = Vec::new();
+    let mut rollback = String::new();
+    let mut cleanup = Vec::new();
+
+    for &(path, content) in files {
+        if let Some(content) = content {
+            stage.push(format!(
+                "printf '%s' '{content}' > {path}.shannon-new && \
+                 {{ [ ! -e {path} ] || {{ chmod --reference={path} {path}.shannon-new && \
+                 chown --reference={path} {path}.shannon-new; }}; }}",
+                content = content.replace('\'', "'\\''"),
+                path = path
+            ));
+            swap.push(format!("mv -f {path}.shannon-new {path}", path = path));
+        } else {
+            swap.push(format!("rm -f {}", path));
+        }
+        stage.push(format!(
+            "{{ [ ! -e {path} ] || cp -p {path} {path}.shannon-old; }}",
+            path = path
+        ));
+        rollback.push_str(&format!(
+            "if [ -e {path}.shannon-old ]; then mv -f {path}.shannon-old {path}; else rm -f {path}; fi\n",
+            path = path
+        ));
+        cleanup.push(format!(
+            "{path}.shannon-new {path}.shannon-old",
+            path = path
+        ));
+    }
+
+    let cleanup = format!("rm -f {}", cleanup.join(" "));
+    format!(
+        "{{ {stage}; }} || {{ {cleanup}; exit 1; }}\n\
+         {{ {swap}; }} || {{\n{rollback}{cleanup}; exit 1; }}\n\
+         {cleanup}\n",
+        stage = stage.join(" && "),
+        swap = swap.join(" && "),
+        rollback = rollback,
+        cleanup = cleanup
+    )
+}
+
 /// Run systemctl command
 pub fn systemctl(action: &str, service: &str) -> Result<()> {
-    let output = execute_shell(&format!("systemctl {} {}", action, service))?;
+    let output = execute_mutation(&format!("systemctl {} {}", action, service))?;
     if output.status.success() {
         Ok(())
     } else {
-        anyhow::bail!(
-            "systemctl {} {} failed: {}",
-            action,
-            service,
-            String::from_utf8_lossy(&output.stderr)
+        Err(
+            ShannonError::command_failed(format!("systemctl {} {}", action, service), &output)
+                .into(),
         )
     }
 }
@@ -102,4 +324,45 @@ mod tests {
         // Just verify it doesn't panic
         let _ = is_local();
     }
+
+    #[test]
+    fn test_dry_run_records_commands() {
+        let output =
+            with_dry_run(|| execute_mutation("iptables -I INPUT -s 192.0.2.1 -j DROP").unwrap());
+
+        assert!(output.status.success());
+        let changes = take_changes();
+        assert!(changes.iter().any(|c| matches!(
+            c,
+            Change::Command { command } if command.contains("192.0.2.1")
+        )));
+    }
+
+    #[test]
+    fn test_replace_files_script_stages_before_swapping() {
+        let script = replace_files_script(&[
+            ("/etc/dnsmasq.conf", Some("port=53")),
+            ("/etc/wireguard/wg0.conf", None),
+        ]);
+        let (stage, rest) = script.split_once('\n').unwrap();
+        assert!(stage.contains("printf '%s' 'port=53' > /etc/dnsmasq.conf.shannon-new"));
+        assert!(stage.contains("cp -p /etc/wireguard/wg0.conf /etc/wireguard/wg0.conf.shannon-old"));
+        assert!(!stage.contains("mv -f"));
+        // Both swaps happen together, and a failure restores every original
+        assert!(rest.starts_with(
+            "{ mv -f /etc/dnsmasq.conf.shannon-new /etc/dnsmasq.conf && rm -f /etc/wireguard/wg0.conf; }"
+        ));
+        assert!(rest.contains(
+            "if [ -e /etc/wireguard/wg0.conf.shannon-old ]; then mv -f /etc/wireguard/wg0.conf.shannon-old /etc/wireguard/wg0.conf"
+        ));
+    }
+
+    #[test]
+    fn test_redact_secrets_masks_keys_only() {
+        let diff = "+PublicKey = cHVibGlj\n+PresharedKey = c2VjcmV0\n PrivateKey = a2V5\n";
+        assert_eq!(
+            redact_secrets(diff),
+            "+PublicKey = cHVibGlj\n+PresharedKey = <redacted>\n PrivateKey = <redacted>\n"
+        );
+    }
 }
@@ -4,18 +4,33 @@
 //! DNS, DHCP, firewall, security, and VPN on the SHANNON router.
 
 mod adapters;
+mod audit;
 mod cli;
 mod commands;
+mod error;
+mod inventory;
 mod location;
 mod notify;
 mod output;
+mod schema;
+mod snapshot;
+mod traffic;
 mod web;
 
 use anyhow::Result;
 use clap::Parser;
-use tracing::error;
+use tracing::warn;
 
-use cli::{Cli, Commands, DdnsAction, DhcpAction, DnsAction, FwAction, SecAction, VpnAction};
+use adapters::dnsmasq::ScopeChange;
+use adapters::egress::EGRESS_INTERFACE;
+use adapters::wireguard::DEFAULT_INTERFACE;
+use error::ShannonError;
+
+use cli::{
+    AllowlistAction, Cli, Commands, DdnsAction, DeviceAction, DhcpAction, DnsAction, EgressAction,
+    ForwardAction, FwAction, HubAction, ScopeAction, SecAction, SnapshotAction, UpstreamAction,
+    VpnAction,
+};
 
 fn main() {
     // Initialize logging (stderr only, preserve stdout for output)
@@ -28,12 +43,55 @@ fn main() {
         .with_writer(std::io::stderr)
         .init();
 
-    let cli = Cli::parse();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => exit_usage(e),
+    };
+    location::set_dry_run(cli.dry_run);
+    let json = cli.json;
 
     if let Err(e) = run(cli) {
-        error!("{:#}", e);
-        std::process::exit(1);
+        // Commands that fail with data (doctor) have already printed it
+        let reported = matches!(error::find(&e), Some(ShannonError::ChecksFailed { .. }));
+        if !reported {
+            output::print_error(&e, json);
+        }
+        std::process::exit(
+            error::find(&e)
+                .map(ShannonError::exit_code)
+                .unwrap_or(error::INTERNAL_EXIT_CODE),
+        );
+    }
+}
+
+/// Report a command line error; as an error envelope when --json was given
+fn exit_usage(err: clap::Error) -> ! {
+    use clap::error::ErrorKind;
+
+    let wants_json = std::env::args().any(|a| a == "--json");
+    let is_help = matches!(
+        err.kind(),
+        ErrorKind::DisplayHelp
+            | ErrorKind::DisplayVersion
+            | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+    );
+    if !wants_json || is_help {
+        err.exit();
     }
+
+    let message = err.to_string();
+    let body = output::ErrorBody {
+        code: "usage",
+        message: message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim_start_matches("error: ")
+            .to_string(),
+        hint: Some("Run 'shannon --help' for usage".to_string()),
+    };
+    output::print_error_body(body, true);
+    std::process::exit(error::USAGE_EXIT_CODE);
 }
 
 #[tokio::main]
@@ -41,25 +99,148 @@ async fn run_web(bind: String, port: u16) -> Result<()> {
     web::serve(&bind, port).await
 }
 
+/// Run a command; if it changes router state, snapshot the managed config
+/// first and record it in the audit log afterwards
 fn run(cli: Cli) -> Result<()> {
+    let mutation = if cli.dry_run {
+        None
+    } else {
+        cli.command.mutation()
+    };
+    let origin = audit::Origin::detect(cli.json);
+
+    let snapshot = mutation.as_ref().and_then(|m| {
+        match snapshot::create(Some(&format!("before {}", m.command))) {
+            Ok(info) => Some(info.id),
+            Err(e) => {
+                warn!("Failed to take snapshot: {:#}", e);
+                None
+            }
+        }
+    });
+
+    let result = dispatch(cli);
+
+    if let Some(mutation) = mutation {
+        let error = result.as_ref().err().map(|e| format!("{:#}", e));
+        if let Err(e) = audit::record(origin, &audit::current_user(), mutation, snapshot, error) {
+            warn!("Failed to write audit log: {:#}", e);
+        }
+    }
+
+    result
+}
+
+fn dispatch(cli: Cli) -> Result<()> {
     match cli.command {
-        Commands::Status => commands::status::status(cli.json),
+        Commands::Status { upstreams } => commands::status::status(upstreams, cli.json),
         Commands::Doctor => commands::status::doctor(cli.json),
 
         Commands::Dns { action } => match action {
             DnsAction::List => commands::dns::list(cli.json),
-            DnsAction::Add { hostname, ip } => commands::dns::add(&hostname, ip, cli.json),
+            DnsAction::Add {
+                hostname,
+                value,
+                record_type,
+            } => commands::dns::add(&hostname, &value, record_type.as_deref(), cli.json),
             DnsAction::Rm { hostname } => commands::dns::remove(&hostname, cli.json),
+            DnsAction::Test {
+                name,
+                record_type,
+                client,
+            } => commands::dns::test(&name, &record_type, client, cli.json),
+            DnsAction::Forward { action } => match action {
+                ForwardAction::List => commands::dns::forward_list(cli.json),
+                ForwardAction::Add {
+                    domain,
+                    upstream,
+                    no_check,
+                } => commands::dns::forward_add(&domain, &upstream, no_check, cli.json),
+                ForwardAction::Rm { domain } => commands::dns::forward_rm(&domain, cli.json),
+            },
+            DnsAction::Upstream { action } => match action {
+                UpstreamAction::List => commands::dns::upstream_list(cli.json),
+                UpstreamAction::Set {
+                    upstreams,
+                    bootstrap,
+                    no_check,
+                } => commands::dns::upstream_set(
+                    &upstreams,
+                    bootstrap.as_deref(),
+                    no_check,
+                    cli.json,
+                ),
+                UpstreamAction::Test { count } => commands::dns::upstream_test(count, cli.json),
+            },
         },
 
         Commands::Dhcp { action } => match action {
             DhcpAction::Leases => commands::dhcp::leases(cli.json),
+            DhcpAction::Reservations => commands::dhcp::reservations(cli.json),
             DhcpAction::Reserve { mac, ip, hostname } => {
                 commands::dhcp::reserve(&mac, ip, hostname.as_deref(), cli.yes, cli.json)
             }
+            DhcpAction::Pin {
+                device,
+                hostname,
+                dns,
+            } => commands::dhcp::pin(&device, hostname.as_deref(), dns, cli.yes, cli.json),
             DhcpAction::Unreserve { target } => {
                 commands::dhcp::unreserve(&target, cli.yes, cli.json)
             }
+            DhcpAction::Scope { action } => match action {
+                ScopeAction::Show => commands::dhcp::scope_show(cli.json),
+                ScopeAction::Set {
+                    range,
+                    lease_time,
+                    gateway,
+                    dns,
+                    domain,
+                    ipv6,
+                } => commands::dhcp::scope_set(
+                    ScopeChange {
+                        range,
+                        lease_time,
+                        gateway,
+                        dns_servers: dns,
+                        domain,
+                        ipv6_mode: ipv6,
+                    },
+                    cli.yes,
+                    cli.json,
+                ),
+            },
+        },
+
+        Commands::Device { action } => match action {
+            DeviceAction::List { tag, online } => {
+                commands::device::list(tag.as_deref(), online, cli.json)
+            }
+            DeviceAction::Show { device } => commands::device::show(&device, cli.json),
+            DeviceAction::Name {
+                device,
+                name,
+                owner,
+            } => commands::device::name(&device, name.as_deref(), owner.as_deref(), cli.json),
+            DeviceAction::Tag { device, tags, rm } => {
+                commands::device::tag(&device, &tags, rm, cli.json)
+            }
+            DeviceAction::Quarantine { device } => {
+                commands::device::quarantine(&device, true, cli.json)
+            }
+            DeviceAction::Release { device } => {
+                commands::device::quarantine(&device, false, cli.json)
+            }
+            DeviceAction::Watch { quarantine, off } => {
+                commands::device::watch(!off, quarantine, cli.json)
+            }
+            DeviceAction::Hook {
+                event,
+                mac,
+                ip,
+                hostname,
+                quarantine,
+            } => commands::device::hook(&event, &mac, &ip, hostname.as_deref(), quarantine),
         },
 
         Commands::Fw { action } => match action {
@@ -79,20 +260,114 @@ fn run(cli: Cli) -> Result<()> {
         Commands::Sec { action } => match action {
             SecAction::Status => commands::sec::status(cli.json),
             SecAction::Blocks => commands::sec::blocks(cli.json),
+            SecAction::Alerts {
+                since,
+                scenario,
+                ip,
+            } => commands::sec::alerts(since, scenario, ip, cli.json),
+            SecAction::Stats { since } => commands::sec::stats(since, cli.json),
+            SecAction::Whois { ip } => commands::sec::whois(ip, cli.json),
+            SecAction::Hub { action } => match action {
+                HubAction::List { all } => commands::sec::hub_list(all, cli.json),
+                HubAction::Install { kind, name } => {
+                    commands::sec::hub_install(&kind, &name, cli.json)
+                }
+                HubAction::Remove { kind, name } => {
+                    commands::sec::hub_remove(&kind, &name, cli.yes, cli.json)
+                }
+                HubAction::Upgrade { kind, name } => {
+                    commands::sec::hub_upgrade(kind.as_deref(), name.as_deref(), cli.json)
+                }
+            },
+            SecAction::Allowlist { action } => match action {
+                AllowlistAction::List => commands::sec::allowlist_list(cli.json),
+                AllowlistAction::Add { entry, note } => {
+                    commands::sec::allowlist_add(&entry, note.as_deref(), cli.json)
+                }
+                AllowlistAction::Rm { entry } => commands::sec::allowlist_remove(&entry, cli.json),
+            },
             SecAction::Scan => commands::sec::scan(cli.json),
             SecAction::Report { hours } => commands::sec::report(hours, cli.json),
         },
 
-        Commands::Vpn { action } => match action {
-            VpnAction::Peers => commands::vpn::peers(cli.json),
-            VpnAction::Status => commands::vpn::status(cli.json),
-        },
+        Commands::Vpn { interface, action } => {
+            let iface = interface.as_deref().unwrap_or(DEFAULT_INTERFACE);
+            match action {
+                VpnAction::Peers => commands::vpn::peers(iface, cli.json),
+                VpnAction::Status => commands::vpn::status(interface.as_deref(), cli.json),
+                VpnAction::AddPeer { name, split, .. } => {
+                    commands::vpn::add_peer(iface, &name, split, cli.json)
+                }
+                VpnAction::AddSite {
+                    name,
+                    remote_subnets,
+                    endpoint,
+                    keepalive,
+                } => commands::vpn::add_site(
+                    iface,
+                    &name,
+                    &remote_subnets,
+                    endpoint.as_deref(),
+                    keepalive,
+                    cli.json,
+                ),
+                VpnAction::RmPeer { name } => {
+                    commands::vpn::remove_peer(iface, &name, cli.yes, cli.json)
+                }
+                VpnAction::RenamePeer { name, new_name } => {
+                    commands::vpn::rename_peer(iface, &name, &new_name, cli.json)
+                }
+                VpnAction::DisablePeer { name } => {
+                    commands::vpn::set_peer_enabled(iface, &name, false, cli.yes, cli.json)
+                }
+                VpnAction::EnablePeer { name } => {
+                    commands::vpn::set_peer_enabled(iface, &name, true, cli.yes, cli.json)
+                }
+                VpnAction::RotateKey { name, split } => {
+                    commands::vpn::rotate_key(iface, &name, split, cli.yes, cli.json)
+                }
+                VpnAction::Sample => commands::vpn::sample(cli.json),
+                VpnAction::Usage { peer, since } => {
+                    commands::vpn::usage(interface.as_deref(), peer.as_deref(), since, cli.json)
+                }
+                VpnAction::Egress { action } => match action {
+                    EgressAction::Setup { config } => commands::vpn::egress_setup(
+                        interface.as_deref().unwrap_or(EGRESS_INTERFACE),
+                        &config,
+                        cli.yes,
+                        cli.json,
+                    ),
+                    EgressAction::Add { device } => commands::vpn::egress_add(&device, cli.json),
+                    EgressAction::Rm { device } => {
+                        commands::vpn::egress_remove(&device, cli.yes, cli.json)
+                    }
+                    EgressAction::List => commands::vpn::egress_list(cli.json),
+                },
+            }
+        }
 
         Commands::Ddns { action } => match action {
             DdnsAction::Status => commands::ddns::status(cli.json),
             DdnsAction::Update { force } => commands::ddns::update(force, cli.json),
         },
 
+        Commands::Snapshot { action } => match action {
+            SnapshotAction::Create { label } => {
+                commands::snapshot::create(label.as_deref(), cli.json)
+            }
+            SnapshotAction::List => commands::snapshot::list(cli.json),
+            SnapshotAction::Diff { from, to } => {
+                commands::snapshot::diff(&from, to.as_deref(), cli.json)
+            }
+            SnapshotAction::Restore { id } => commands::snapshot::restore(&id, cli.yes, cli.json),
+        },
+
+        Commands::Audit { since, kind, limit } => {
+            commands::audit::list(since, kind.as_deref(), limit, cli.json)
+        }
+
+        Commands::Schema { command } => commands::schema::show(&command.join(" "), cli.json),
+
         Commands::Web { port, bind } => run_web(bind, port),
     }
 }
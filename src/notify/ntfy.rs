@@ -1,9 +1,27 @@
-//! ntfy.sh push notification (placeholder)
+//! ntfy push notifications
+//!
+//! Messages go to the ntfy server on the Dell, which the Mac's ntfy-bridge
+//! relays to the TTS daemon (see "Alert Pipeline" in the README).
 
-// TODO: Implement ntfy notification
-// POST to configured ntfy.sh topic
+use anyhow::Result;
 
-pub fn notify_ntfy(_topic: &str, _message: &str) -> anyhow::Result<()> {
-    // TODO: POST to ntfy.sh
+use crate::error::ShannonError;
+use crate::location::execute_shell_with_input;
+
+pub const NTFY_SERVER: &str = "http://192.168.4.84:8099";
+/// Topic of the daily digest and other non-urgent security news
+pub const SECURITY_TOPIC: &str = "shannon-security";
+
+/// POST a message to a topic (from SHANNON, over the LAN)
+pub fn notify_ntfy(topic: &str, message: &str) -> Result<()> {
+    let cmd = format!("curl -fsS --max-time 5 -d @- {}/{}", NTFY_SERVER, topic);
+    let output = execute_shell_with_input(&cmd, message)?;
+    if !output.status.success() {
+        return Err(ShannonError::CommandFailed {
+            command: cmd,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
     Ok(())
 }
@@ -3,6 +3,7 @@
 // TODO: Implement TTS notification by writing to daemon socket
 // See ~/.claude/hooks/tts_daemon.py for protocol
 
+#[allow(dead_code)]
 pub fn notify_tts(_message: &str) -> anyhow::Result<()> {
     // TODO: Write to TTS daemon socket/file
     Ok(())
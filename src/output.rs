@@ -1,23 +1,202 @@
 //! Output formatting for plain text and JSON
+//!
+//! Every `--json` response is wrapped in the same envelope,
+//! `{"ok": bool, "data": ..., "error": {"code", "message", "hint"}}`, with
+//! `data` or `error` null as appropriate. Error codes come from `ShannonError`.
 
+use schemars::JsonSchema;
 use serde::Serialize;
+use similar::TextDiff;
 use std::fmt::Display;
+use tracing::error;
+
+use crate::error::{self, ShannonError};
+use crate::location::{is_dry_run, take_changes, Change};
+
+/// Envelope wrapping every `--json` response
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Envelope<'a, T: Serialize> {
+    pub ok: bool,
+    pub data: Option<&'a T>,
+    pub error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl ErrorBody {
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        let typed = error::find(err);
+        ErrorBody {
+            code: typed.map(ShannonError::code).unwrap_or("internal"),
+            message: format!("{:#}", err),
+            hint: typed.and_then(ShannonError::hint),
+        }
+    }
+}
+
+fn to_json<T: Serialize>(envelope: &Envelope<'_, T>) -> String {
+    serde_json::to_string_pretty(envelope).unwrap_or_else(|e| {
+        serde_json::json!({
+            "ok": false,
+            "data": null,
+            "error": { "code": "internal", "message": e.to_string(), "hint": null },
+        })
+        .to_string()
+    })
+}
 
 /// Format output based on --json flag
 pub fn format_output<T: Serialize + Display>(data: &T, json: bool) -> String {
     if json {
-        serde_json::to_string_pretty(data).unwrap_or_else(|e| format!("{{\"error\": \"{}\"}}", e))
+        to_json(&Envelope {
+            ok: true,
+            data: Some(data),
+            error: None,
+        })
     } else {
         data.to_string()
     }
 }
 
 /// Print output with newline
+///
+/// In dry-run mode the result is wrapped together with the changes that
+/// would have been made.
 pub fn print_output<T: Serialize + Display>(data: &T, json: bool) {
+    if is_dry_run() {
+        let changes = take_changes();
+        if !changes.is_empty() {
+            let preview = DryRunPreview {
+                dry_run: true,
+                result: data,
+                changes,
+            };
+            println!("{}", format_output(&preview, json));
+            return;
+        }
+    }
     println!("{}", format_output(data, json));
 }
 
+/// Print a result that still counts as a failure (e.g. doctor with failed
+/// checks): the data is shown, and `ok` is false in the JSON envelope
+pub fn print_failure<T: Serialize + Display>(data: &T, err: &anyhow::Error, json: bool) {
+    if json {
+        println!(
+            "{}",
+            to_json(&Envelope {
+                ok: false,
+                data: Some(data),
+                error: Some(ErrorBody::from_error(err)),
+            })
+        );
+    } else {
+        println!("{}", data);
+    }
+}
+
+/// Report a failed command: an error envelope on stdout with --json,
+/// otherwise the error chain and hint on stderr
+pub fn print_error(err: &anyhow::Error, json: bool) {
+    print_error_body(ErrorBody::from_error(err), json);
+}
+
+/// Report an error that has already been classified
+pub fn print_error_body(body: ErrorBody, json: bool) {
+    if json {
+        println!(
+            "{}",
+            to_json::<()>(&Envelope {
+                ok: false,
+                data: None,
+                error: Some(body),
+            })
+        );
+    } else {
+        error!("{}", body.message);
+        if let Some(hint) = body.hint {
+            eprintln!("  hint: {}", hint);
+        }
+    }
+}
+
+/// Result of a mutating command run with --dry-run
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DryRunPreview<'a, T: Serialize> {
+    pub dry_run: bool,
+    /// What the command reports, in the shape of its own output
+    pub result: &'a T,
+    pub changes: Vec<Change>,
+}
+
+impl<T: Serialize + Display> Display for DryRunPreview<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "DRY RUN — no changes were made")?;
+        writeln!(f, "{}", self.result)?;
+        writeln!(f)?;
+        for change in &self.changes {
+            match change {
+                Change::File { diff, .. } => write!(f, "{}", diff)?,
+                Change::Command { command } => writeln!(f, "$ {}", command)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Unified diff between two versions of a file
+pub fn unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// Human-readable byte count, e.g. "1.5 MiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Compact length of time: "45s", "3m", "2h 5m", "4d"
+pub fn format_span(span: chrono::Duration) -> String {
+    let secs = span.num_seconds().max(0);
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// One block character per value, scaled to the largest ("▁▃█▂")
+pub fn sparkline(values: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let max = values.iter().copied().max().unwrap_or(0).max(1);
+    values
+        .iter()
+        .map(|&v| BARS[((v as f64 / max as f64) * 7.0).round() as usize])
+        .collect()
+}
+
 /// Simple key-value output for status displays
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct StatusLine {
     pub key: String,
@@ -26,6 +205,7 @@ pub struct StatusLine {
     pub status: Option<LineStatus>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum LineStatus {
@@ -47,6 +227,7 @@ impl Display for StatusLine {
 }
 
 /// Collection of status lines for multi-line output
+#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct StatusReport {
     pub lines: Vec<StatusLine>,
@@ -62,7 +243,7 @@ impl Display for StatusReport {
 }
 
 /// Table output for lists (DNS records, DHCP leases, etc.)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct TableOutput<T: Serialize> {
     pub headers: Vec<String>,
     pub rows: Vec<T>,
@@ -76,7 +257,7 @@ impl<T: Serialize + TableRow> Display for TableOutput<T> {
             let cells = row.cells();
             for (i, cell) in cells.iter().enumerate() {
                 if i < widths.len() {
-                    widths[i] = widths[i].max(cell.len());
+                    widths[i] = widths[i].max(cell.chars().count());
                 }
             }
         }
@@ -124,7 +305,8 @@ pub trait TableRow {
 
 /// Confirmation prompt
 pub fn confirm(message: &str, yes_flag: bool) -> bool {
-    if yes_flag {
+    // Nothing is applied in dry-run mode, so there is nothing to confirm
+    if yes_flag || is_dry_run() {
         return true;
     }
 
@@ -137,3 +319,72 @@ pub fn confirm(message: &str, yes_flag: bool) -> bool {
 
     matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_success_envelope_shape() {
+        let data = json!({ "success": true });
+        let envelope: serde_json::Value =
+            serde_json::from_str(&format_output(&data, true)).unwrap();
+        assert_eq!(envelope, json!({ "ok": true, "data": data, "error": null }));
+    }
+
+    #[test]
+    fn test_error_envelope_carries_code_and_hint() {
+        let err = anyhow::Error::from(ShannonError::command_failed(
+            "systemctl restart dnsmasq",
+            &std::process::Output {
+                status: std::os::unix::process::ExitStatusExt::from_raw(256),
+                stdout: Vec::new(),
+                stderr: b"Job failed\n".to_vec(),
+            },
+        ))
+        .context("Failed to restart dnsmasq");
+        let envelope = serde_json::to_value(Envelope::<()> {
+            ok: false,
+            data: None,
+            error: Some(ErrorBody::from_error(&err)),
+        })
+        .unwrap();
+
+        assert_eq!(envelope["ok"], false);
+        assert_eq!(envelope["data"], serde_json::Value::Null);
+        assert_eq!(envelope["error"]["code"], "command_failed");
+        assert_eq!(
+            envelope["error"]["message"],
+            "Failed to restart dnsmasq: systemctl restart dnsmasq failed: Job failed"
+        );
+        assert!(envelope["error"]["hint"].is_string());
+    }
+
+    #[test]
+    fn test_untyped_error_is_internal() {
+        let body = ErrorBody::from_error(&anyhow::anyhow!("unexpected"));
+        assert_eq!(body.code, "internal");
+        assert_eq!(body.hint, None);
+    }
+
+    #[test]
+    fn test_dry_run_preview_shape() {
+        let result = json!({ "success": true });
+        let preview = DryRunPreview {
+            dry_run: true,
+            result: &result,
+            changes: vec![Change::Command {
+                command: "systemctl restart dnsmasq".to_string(),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&preview).unwrap(),
+            json!({
+                "dry_run": true,
+                "result": { "success": true },
+                "changes": [{ "kind": "command", "command": "systemctl restart dnsmasq" }],
+            })
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! JSON Schemas for every `--json` output
+//!
+//! Agents depend on these shapes, so they are versioned: any change to a
+//! serialized output type must bump `SCHEMA_VERSION`. The fingerprint test
+//! at the bottom of this file fails until it does.
+
+use schemars::gen::SchemaSettings;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::adapters::crowdsec::{AllowlistEntry, CrowdsecAlert};
+use crate::adapters::dnsmasq::{DhcpLease, DhcpReservation, DnsRecord};
+use crate::adapters::egress::EgressDevice;
+use crate::adapters::nftables::PortForward;
+use crate::audit::AuditEntry;
+use crate::commands::ddns::DdnsStatus;
+use crate::commands::device::{DeviceUpdateResult, DeviceWatchResult};
+use crate::commands::dhcp::{
+    DhcpPinResult, DhcpReserveResult, DhcpScopeResult, DhcpUnreserveResult,
+};
+use crate::commands::dns::{
+    DnsAddResult, DnsForward, DnsForwardRemoveResult, DnsForwardResult, DnsRemoveResult,
+    DnsTestResult, DnsUpstream, DnsUpstreamSetResult, UpstreamHealth,
+};
+use crate::commands::fw::{FwBlockResult, FwForwardResult, FwUnblockResult, FwUnforwardResult};
+use crate::commands::sec::{
+    BlocksResult, HubEntry, ScanResult, SecAllowlistResult, SecHubResult, SecStats, SecurityStatus,
+    WhoisReport,
+};
+use crate::commands::snapshot::{SnapshotCreateResult, SnapshotDiff, SnapshotRestoreResult};
+use crate::commands::status::{DiagnosticResult, SystemStatus};
+use crate::commands::vpn::{
+    VpnAddPeerResult, VpnAddSiteResult, VpnEgressDeviceResult, VpnEgressSetupResult,
+    VpnPeersResult, VpnRemovePeerResult, VpnRenamePeerResult, VpnRotateKeyResult, VpnSampleResult,
+    VpnStatusResult, VpnTogglePeerResult,
+};
+use crate::inventory::Device;
+use crate::output::{DryRunPreview, Envelope, TableOutput};
+use crate::snapshot::SnapshotInfo;
+use crate::traffic::PeerUsage;
+
+/// Version of the `--json` output shapes
+pub const SCHEMA_VERSION: u32 = 23;
+
+/// Schema of the full `--json` envelope with `T` as `data`
+fn envelope<T: JsonSchema + Serialize + 'static>() -> RootSchema {
+    SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Envelope<'static, T>>()
+}
+
+/// Output schema of every command, keyed by its command line (`dns list`)
+pub fn command_schemas() -> BTreeMap<&'static str, RootSchema> {
+    BTreeMap::from([
+        // Any mutating command run with --dry-run; `result` is that
+        // command's own `data`
+        (
+            "dry-run",
+            envelope::<DryRunPreview<'static, serde_json::Value>>(),
+        ),
+        ("status", envelope::<SystemStatus>()),
+        ("doctor", envelope::<DiagnosticResult>()),
+        ("dns list", envelope::<TableOutput<DnsRecord>>()),
+        ("dns add", envelope::<DnsAddResult>()),
+        ("dns rm", envelope::<DnsRemoveResult>()),
+        ("dns forward list", envelope::<TableOutput<DnsForward>>()),
+        ("dns forward add", envelope::<DnsForwardResult>()),
+        ("dns forward rm", envelope::<DnsForwardRemoveResult>()),
+        ("dns upstream list", envelope::<TableOutput<DnsUpstream>>()),
+        ("dns upstream set", envelope::<DnsUpstreamSetResult>()),
+        (
+            "dns upstream test",
+            envelope::<TableOutput<UpstreamHealth>>(),
+        ),
+        ("dns test", envelope::<DnsTestResult>()),
+        ("dhcp leases", envelope::<TableOutput<DhcpLease>>()),
+        (
+            "dhcp reservations",
+            envelope::<TableOutput<DhcpReservation>>(),
+        ),
+        ("dhcp reserve", envelope::<DhcpReserveResult>()),
+        ("dhcp pin", envelope::<DhcpPinResult>()),
+        ("dhcp scope show", envelope::<DhcpScopeResult>()),
+        ("dhcp scope set", envelope::<DhcpScopeResult>()),
+        ("dhcp unreserve", envelope::<DhcpUnreserveResult>()),
+        ("device list", envelope::<TableOutput<Device>>()),
+        ("device show", envelope::<Device>()),
+        ("device name", envelope::<DeviceUpdateResult>()),
+        ("device tag", envelope::<DeviceUpdateResult>()),
+        ("device quarantine", envelope::<DeviceUpdateResult>()),
+        ("device release", envelope::<DeviceUpdateResult>()),
+        ("device watch", envelope::<DeviceWatchResult>()),
+        ("fw list", envelope::<TableOutput<PortForward>>()),
+        ("fw forward", envelope::<FwForwardResult>()),
+        ("fw unforward", envelope::<FwUnforwardResult>()),
+        ("fw block", envelope::<FwBlockResult>()),
+        ("fw unblock", envelope::<FwUnblockResult>()),
+        ("sec status", envelope::<SecurityStatus>()),
+        ("sec blocks", envelope::<BlocksResult>()),
+        ("sec alerts", envelope::<TableOutput<CrowdsecAlert>>()),
+        ("sec stats", envelope::<SecStats>()),
+        ("sec whois", envelope::<WhoisReport>()),
+        ("sec hub list", envelope::<TableOutput<HubEntry>>()),
+        ("sec hub install", envelope::<SecHubResult>()),
+        ("sec hub remove", envelope::<SecHubResult>()),
+        ("sec hub upgrade", envelope::<SecHubResult>()),
+        (
+            "sec allowlist list",
+            envelope::<TableOutput<AllowlistEntry>>(),
+        ),
+        ("sec allowlist add", envelope::<SecAllowlistResult>()),
+        ("sec allowlist rm", envelope::<SecAllowlistResult>()),
+        ("sec scan", envelope::<ScanResult>()),
+        ("sec report", envelope::<ScanResult>()),
+        ("vpn peers", envelope::<VpnPeersResult>()),
+        ("vpn status", envelope::<VpnStatusResult>()),
+        ("vpn add-peer", envelope::<VpnAddPeerResult>()),
+        ("vpn add-site", envelope::<VpnAddSiteResult>()),
+        ("vpn rm-peer", envelope::<VpnRemovePeerResult>()),
+        ("vpn rename-peer", envelope::<VpnRenamePeerResult>()),
+        ("vpn disable-peer", envelope::<VpnTogglePeerResult>()),
+        ("vpn enable-peer", envelope::<VpnTogglePeerResult>()),
+        ("vpn rotate-key", envelope::<VpnRotateKeyResult>()),
+        ("vpn sample", envelope::<VpnSampleResult>()),
+        ("vpn usage", envelope::<TableOutput<PeerUsage>>()),
+        ("vpn egress setup", envelope::<VpnEgressSetupResult>()),
+        ("vpn egress add", envelope::<VpnEgressDeviceResult>()),
+        ("vpn egress rm", envelope::<VpnEgressDeviceResult>()),
+        ("vpn egress list", envelope::<TableOutput<EgressDevice>>()),
+        ("ddns status", envelope::<DdnsStatus>()),
+        ("ddns update", envelope::<DdnsStatus>()),
+        ("snapshot create", envelope::<SnapshotCreateResult>()),
+        ("snapshot list", envelope::<TableOutput<SnapshotInfo>>()),
+        ("snapshot diff", envelope::<SnapshotDiff>()),
+        ("snapshot restore", envelope::<SnapshotRestoreResult>()),
+        ("audit", envelope::<TableOutput<AuditEntry>>()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fingerprint of the schemas at each version, oldest first
+    ///
+    /// When `test_schema_version_matches_shapes` fails, bump `SCHEMA_VERSION` and
+    /// append the new version with the fingerprint from the failure message.
+    const SCHEMA_HISTORY: &[(u32, u64)] = &[
+        (1, 0xd37834a072d5decb),
+        (2, 0x776d5f5990078ff6),  // vpn add-peer
+        (3, 0x69c75be04d8acb7f),  // vpn rm/rename/disable/enable-peer, rotate-key
+        (4, 0x49241f94917e2dd7),  // WireguardPeer: typed handshake/transfer, endpoint
+        (5, 0xcba45869c1661a2b),  // vpn sample, vpn usage
+        (6, 0x4d56a85c289bc356),  // WireGuard interface names, vpn status per interface, add-site
+        (7, 0x9692cd564be3bbb2),  // vpn egress setup/add/rm/list
+        (8, 0xb9df748402b853ea),  // CrowdsecDecision from LAPI alerts, CrowdSec metrics
+        (9, 0xe048ef5cbf07f358),  // sec alerts, sec stats
+        (10, 0x8d4bad8e8ec4e98c), // sec hub, sec allowlist
+        (11, 0xd5b7bd6f94819a82), // sec whois
+        (12, 0xeef50daf79e75652), // device list/show/name/tag
+        (13, 0x839dde151f69d373), // device quarantine/release/watch
+        (14, 0x23ae96cc5a91e4d7), // DhcpLease expiry/active/client id, dhcp reservations, whois reservations
+        (15, 0x263dbaaa51ea86f4), // dhcp pin
+        (16, 0xefff3d8a8520f9dc), // dhcp scope show/set, DHCP pool in status
+        (17, 0xc9bec2b4e1b5dfd8), // IPv6 status, DHCPv6 scope, fw pinholes, DDNS AAAA
+        (18, 0xe79fbf44e44fb7ad), // dns record types (CNAME/TXT/SRV/MX/PTR), dns add returns records
+        (19, 0xf68f9a1663d5964c), // dns forward list/add/rm
+        (20, 0x8920daee2ca91a03), // dns upstream list/set/test, DNS upstream health in status
+        (21, 0x69ea48e4255b92d3), // dns test
+        (22, 0xd14635fbc27b1793), // SnapshotInfo managed files, snapshot restore removed
+        (23, 0x53ff44abf033823d), // --dry-run preview
+    ];
+
+    /// Drop doc-comment descriptions, which don't change the shape
+    fn strip_descriptions(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("description");
+                map.values_mut().for_each(strip_descriptions);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(strip_descriptions),
+            _ => {}
+        }
+    }
+
+    /// FNV-1a over the canonical (key-sorted) JSON of all schemas
+    fn fingerprint() -> u64 {
+        let mut value = serde_json::to_value(command_schemas()).unwrap();
+        strip_descriptions(&mut value);
+        serde_json::to_string(&value)
+            .unwrap()
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            })
+    }
+
+    #[test]
+    fn test_schema_version_matches_shapes() {
+        let actual = fingerprint();
+        let (version, expected) = *SCHEMA_HISTORY.last().unwrap();
+        assert_eq!(
+            version, SCHEMA_VERSION,
+            "SCHEMA_HISTORY must end with the current version: add ({}, {:#x})",
+            SCHEMA_VERSION, actual
+        );
+        assert_eq!(
+            actual,
+            expected,
+            "--json output shapes changed: bump SCHEMA_VERSION to {} and add ({}, {:#x}) to SCHEMA_HISTORY",
+            SCHEMA_VERSION + 1,
+            SCHEMA_VERSION + 1,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_schema_history_versions_increase() {
+        assert!(SCHEMA_HISTORY.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
@@ -0,0 +1,394 @@
+//! Configuration snapshots of the state shannon manages
+//!
+//! A snapshot is a tar.gz archive in `/var/lib/shannon/snapshots/` on SHANNON
+//! holding a copy of each managed file plus a `manifest.json`. One is taken
+//! automatically before every mutating command.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::adapters::crowdsec::ALLOWLIST_PATH;
+use crate::adapters::nftables::NftablesAdapter;
+use crate::error::ShannonError;
+use crate::location::{execute_shell, read_file};
+
+const SNAPSHOT_DIR: &str = "/var/lib/shannon/snapshots";
+
+/// Snapshots kept before the oldest are pruned
+const MAX_SNAPSHOTS: usize = 100;
+
+/// Name of the live firewall ruleset inside an archive
+pub const FIREWALL_RULES: &str = "iptables.rules";
+
+/// Name of the live IPv6 firewall ruleset (pinholes, forward guard)
+pub const FIREWALL6_RULES: &str = "ip6tables.rules";
+
+/// Managed files at fixed paths: (name inside the archive, live path)
+pub const MANAGED_FILES: &[(&str, &str)] = &[
+    ("dnsmasq.conf", "/etc/dnsmasq.conf"),
+    ("dnsmasq-custom.conf", "/etc/dnsmasq.d/custom.conf"),
+    ("AdGuardHome.yaml", "/opt/AdGuardHome/AdGuardHome.yaml"),
+    ("crowdsec-allowlist.yaml", ALLOWLIST_PATH),
+];
+
+const WIREGUARD_DIR: &str = "/etc/wireguard";
+
+/// Entry in `SnapshotInfo::managed` standing for every WireGuard config,
+/// each stored in the archive as `<interface>.conf`
+pub const WIREGUARD_CONFIGS: &str = "wireguard/*.conf";
+
+#[derive(Debug, Serialize, JsonSchema, Deserialize, Clone)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Archive names of the files managed when the snapshot was taken;
+    /// empty for snapshots from before this was recorded
+    #[serde(default)]
+    pub managed: Vec<String>,
+}
+
+/// Snapshot id for a creation time, e.g. `20261019-142501-042`
+///
+/// Includes milliseconds so back-to-back snapshots (the automatic one before
+/// a mutation, then `snapshot create`) get distinct ids.
+fn snapshot_id(created: DateTime<Utc>) -> String {
+    created.format("%Y%m%d-%H%M%S-%3f").to_string()
+}
+
+/// Exit status of the create script when the archive already exists
+const EXISTS_STATUS: i32 = 17;
+
+fn archive_path(id: &str) -> String {
+    format!("{}/{}.tar.gz", SNAPSHOT_DIR, id)
+}
+
+/// Capture the managed files and the live firewall ruleset
+pub fn create(label: Option<&str>) -> Result<SnapshotInfo> {
+    let created = Utc::now();
+    let info = SnapshotInfo {
+        id: snapshot_id(created),
+        created,
+        label: label.map(String::from),
+        managed: MANAGED_FILES
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .chain([WIREGUARD_CONFIGS.to_string()])
+            .collect(),
+    };
+
+    let manifest = serde_json::to_string(&info)?.replace('\'', "'\\''");
+    let mut copies: String = MANAGED_FILES
+        .iter()
+        .map(|(name, path)| format!("[ ! -f {path} ] || cp -p {path} \"$tmp/files/{name}\"\n"))
+        .collect();
+    copies.push_str(&format!(
+        "for f in {}/*.conf; do [ ! -f \"$f\" ] || cp -p \"$f\" \"$tmp/files/\"; done\n",
+        WIREGUARD_DIR
+    ));
+
+    let script = format!(
+        "set -e
+umask 077
+mkdir -p {dir}
+[ ! -e {archive} ] || exit {exists}
+tmp=$(mktemp -d {dir}/.new.XXXXXX)
+trap 'rm -rf \"$tmp\"' EXIT
+mkdir \"$tmp/files\"
+{copies}iptables-save | grep -v '^#' > \"$tmp/files/{rules}\"
+ip6tables-save | grep -v '^#' > \"$tmp/files/{rules6}\"
+printf '%s' '{manifest}' > \"$tmp/files/manifest.json\"
+(cd \"$tmp/files\" && tar -czf \"$tmp/archive.tar.gz\" *)
+# ln refuses to replace an archive created in the meantime
+ln \"$tmp/archive.tar.gz\" {archive} || exit {exists}
+ls -1t {dir}/*.tar.gz | tail -n +{keep} | xargs -r rm -f",
+        dir = SNAPSHOT_DIR,
+        rules = FIREWALL_RULES,
+        rules6 = FIREWALL6_RULES,
+        archive = archive_path(&info.id),
+        keep = MAX_SNAPSHOTS + 1,
+        exists = EXISTS_STATUS,
+    );
+
+    let output = execute_shell(&script)?;
+    if output.status.code() == Some(EXISTS_STATUS) {
+        return Err(ShannonError::AlreadyExists(format!("Snapshot {}", info.id)).into());
+    }
+    if !output.status.success() {
+        return Err(ShannonError::command_failed("create snapshot", &output).into());
+    }
+
+    Ok(info)
+}
+
+/// List snapshots, newest first
+pub fn list() -> Result<Vec<SnapshotInfo>> {
+    let output = execute_shell(&format!(
+        "for f in $(ls -1t {}/*.tar.gz 2>/dev/null); do tar -xzOf \"$f\" manifest.json; echo; done",
+        SNAPSHOT_DIR
+    ))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Resolve a snapshot id, unique id prefix or `latest`
+pub fn resolve(id: &str) -> Result<SnapshotInfo> {
+    let snapshots = list()?;
+
+    if id == "latest" {
+        return snapshots
+            .into_iter()
+            .next()
+            .ok_or_else(|| ShannonError::NotFound("Snapshot".to_string()).into());
+    }
+
+    let mut matches: Vec<SnapshotInfo> = snapshots
+        .into_iter()
+        .filter(|s| s.id.starts_with(id))
+        .collect();
+
+    match matches.len() {
+        0 => Err(ShannonError::NotFound(format!("Snapshot {}", id)).into()),
+        1 => Ok(matches.remove(0)),
+        n => Err(ShannonError::InvalidInput(format!(
+            "Snapshot id {} is ambiguous ({} matches)",
+            id, n
+        ))
+        .into()),
+    }
+}
+
+/// Contents of a snapshot, keyed by name inside the archive
+///
+/// Files that didn't exist when the snapshot was taken are absent.
+pub fn read_files(id: &str) -> Result<BTreeMap<String, String>> {
+    let archive = archive_path(id);
+    let mut files = BTreeMap::new();
+
+    let listing = execute_shell(&format!("tar -tzf {} 2>/dev/null", archive))?;
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    let names = listing
+        .lines()
+        .map(|name| name.trim_start_matches("./"))
+        .filter(|name| *name != "manifest.json" && !name.is_empty());
+
+    for name in names {
+        let output = execute_shell(&format!("tar -xzOf {} {} 2>/dev/null", archive, name))?;
+        if output.status.success() {
+            files.insert(
+                name.to_string(),
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+            );
+        }
+    }
+
+    if files.is_empty() {
+        return Err(
+            ShannonError::InvalidInput(format!("Snapshot {} is empty or unreadable", id)).into(),
+        );
+    }
+    Ok(files)
+}
+
+/// Current live state, in the same shape as `read_files`
+pub fn read_live() -> Result<BTreeMap<String, String>> {
+    let mut files = BTreeMap::new();
+
+    for (name, path) in MANAGED_FILES {
+        if let Ok(content) = read_file(path) {
+            files.insert(name.to_string(), content);
+        }
+    }
+    let configs = execute_shell(&format!("ls -1 {} 2>/dev/null", WIREGUARD_DIR))?;
+    for name in String::from_utf8_lossy(&configs.stdout).lines() {
+        if wireguard_interface(name).is_some() {
+            if let Ok(content) = read_file(&format!("{}/{}", WIREGUARD_DIR, name)) {
+                files.insert(name.to_string(), content);
+            }
+        }
+    }
+    let firewall = NftablesAdapter::new();
+    files.insert(FIREWALL_RULES.to_string(), firewall.current_ruleset()?);
+    files.insert(FIREWALL6_RULES.to_string(), firewall.current_ruleset_v6()?);
+
+    Ok(files)
+}
+
+/// Live path of a file inside an archive
+pub fn live_path(name: &str) -> String {
+    match name {
+        FIREWALL_RULES => "(live firewall ruleset)".to_string(),
+        FIREWALL6_RULES => "(live IPv6 firewall ruleset)".to_string(),
+        _ => match MANAGED_FILES.iter().find(|(n, _)| *n == name) {
+            Some((_, path)) => path.to_string(),
+            None => format!("{}/{}", WIREGUARD_DIR, name),
+        },
+    }
+}
+
+/// The WireGuard interface whose config a name inside an archive is
+pub fn wireguard_interface(name: &str) -> Option<&str> {
+    if is_ruleset(name) || MANAGED_FILES.iter().any(|(n, _)| *n == name) {
+        return None;
+    }
+    name.strip_suffix(".conf").filter(|iface| {
+        !iface.is_empty()
+            && iface
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '=' | '+'))
+    })
+}
+
+/// Whether a name inside an archive is a live ruleset rather than a file
+pub fn is_ruleset(name: &str) -> bool {
+    name == FIREWALL_RULES || name == FIREWALL6_RULES
+}
+
+/// Files to write (or remove, as `None`) to bring live state back to a
+/// snapshot, keyed by name inside the archive
+///
+/// A managed file missing from the archive didn't exist when the snapshot
+/// was taken, so it is removed; files the snapshot didn't manage are left
+/// alone.
+pub fn restore_plan<'a>(
+    info: &SnapshotInfo,
+    target: &'a BTreeMap<String, String>,
+    live: &'a BTreeMap<String, String>,
+) -> Vec<(&'a str, Option<&'a str>)> {
+    let managed = |name: &str| {
+        info.managed.iter().any(|m| m == name)
+            || (wireguard_interface(name).is_some()
+                && info.managed.iter().any(|m| m == WIREGUARD_CONFIGS))
+    };
+    let writes = target
+        .iter()
+        .filter(|(name, content)| live.get(*name) != Some(*content))
+        .map(|(name, content)| (name.as_str(), Some(content.as_str())));
+    let removals = live
+        .keys()
+        .filter(|name| !is_ruleset(name) && !target.contains_key(*name) && managed(name))
+        .map(|name| (name.as_str(), None));
+    writes.chain(removals).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn files(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, content)| (name.to_string(), content.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_snapshot_ids_distinguish_same_second() {
+        let first = Utc.with_ymd_and_hms(2026, 10, 19, 14, 25, 1).unwrap();
+        let second = first + chrono::Duration::milliseconds(42);
+        assert_eq!(snapshot_id(first), "20261019-142501-000");
+        assert_eq!(snapshot_id(second), "20261019-142501-042");
+    }
+
+    #[test]
+    fn test_restore_plan_writes_changed_and_removes_missing() {
+        let info = SnapshotInfo {
+            id: "20261019-142501-000".to_string(),
+            created: Utc::now(),
+            label: None,
+            managed: vec!["dnsmasq.conf".to_string(), "wg0.conf".to_string()],
+        };
+        let target = files(&[("dnsmasq.conf", "port=53\n"), (FIREWALL_RULES, "*filter\n")]);
+        let live = files(&[
+            ("dnsmasq.conf", "port=5353\n"),
+            ("wg0.conf", "[Interface]\n"),
+            ("crowdsec-allowlist.yaml", "name: shannon\n"),
+            (FIREWALL_RULES, "*filter\n"),
+        ]);
+
+        assert_eq!(
+            restore_plan(&info, &target, &live),
+            vec![("dnsmasq.conf", Some("port=53\n")), ("wg0.conf", None)]
+        );
+    }
+
+    #[test]
+    fn test_restore_plan_covers_ipv6_ruleset() {
+        let info = SnapshotInfo {
+            id: "20261019-142501-000".to_string(),
+            created: Utc::now(),
+            label: None,
+            managed: vec!["dnsmasq.conf".to_string()],
+        };
+        let target = files(&[
+            (FIREWALL_RULES, "*filter\n"),
+            (FIREWALL6_RULES, "*filter\n:FORWARD ACCEPT [0:0]\n"),
+        ]);
+        let live = files(&[
+            (FIREWALL_RULES, "*filter\n"),
+            (FIREWALL6_RULES, "*filter\n:FORWARD DROP [0:0]\n"),
+        ]);
+
+        let plan = restore_plan(&info, &target, &live);
+        assert_eq!(
+            plan,
+            vec![(FIREWALL6_RULES, Some("*filter\n:FORWARD ACCEPT [0:0]\n"))]
+        );
+        assert!(is_ruleset(plan[0].0));
+        assert_eq!(live_path(FIREWALL6_RULES), "(live IPv6 firewall ruleset)");
+    }
+
+    #[test]
+    fn test_restore_plan_covers_every_wireguard_config() {
+        let info = SnapshotInfo {
+            id: "20261019-142501-000".to_string(),
+            created: Utc::now(),
+            label: None,
+            managed: vec!["dnsmasq.conf".to_string(), WIREGUARD_CONFIGS.to_string()],
+        };
+        let target = files(&[("wg0.conf", "[Interface]\n"), ("wg1.conf", "[Interface]\n")]);
+        let live = files(&[
+            ("wg0.conf", "[Interface]\n"),
+            ("wg1.conf", "[Interface]\nListenPort = 51821\n"),
+            ("wg2.conf", "[Interface]\n"),
+            ("crowdsec-allowlist.yaml", "name: shannon\n"),
+        ]);
+
+        assert_eq!(
+            restore_plan(&info, &target, &live),
+            vec![("wg1.conf", Some("[Interface]\n")), ("wg2.conf", None)]
+        );
+    }
+
+    #[test]
+    fn test_wireguard_interface_names() {
+        assert_eq!(wireguard_interface("wg1.conf"), Some("wg1"));
+        assert_eq!(wireguard_interface("wgc0.conf"), Some("wgc0"));
+        assert_eq!(wireguard_interface("dnsmasq.conf"), None);
+        assert_eq!(wireguard_interface(FIREWALL_RULES), None);
+        assert_eq!(wireguard_interface("AdGuardHome.yaml"), None);
+        assert_eq!(live_path("wg1.conf"), "/etc/wireguard/wg1.conf");
+    }
+
+    #[test]
+    fn test_restore_plan_keeps_files_unknown_to_old_snapshots() {
+        let info = SnapshotInfo {
+            id: "20261001-090000".to_string(),
+            created: Utc::now(),
+            label: None,
+            managed: Vec::new(),
+        };
+        let target = files(&[("dnsmasq.conf", "port=53\n")]);
+        let live = files(&[("dnsmasq.conf", "port=53\n"), ("wg0.conf", "[Interface]\n")]);
+
+        assert!(restore_plan(&info, &target, &live).is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Per-peer WireGuard traffic history
+//!
+//! `shannon vpn sample`, run every minute by `shannon-wg-traffic.timer`,
+//! appends each peer's rx/tx counters to one JSONL file per day under
+//! `/var/lib/shannon/wg-traffic`. Usage over a window is the sum of the
+//! counter increases between consecutive samples; a counter that went down
+//! was reset by an interface restart.
+
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::adapters::wireguard::WireguardStatus;
+use crate::error::ShannonError;
+use crate::location::execute_shell;
+
+const TRAFFIC_DIR: &str = "/var/lib/shannon/wg-traffic";
+const RETENTION_DAYS: u32 = 35;
+
+/// Counters of every peer of one interface at one point in time
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sample {
+    pub timestamp: DateTime<Utc>,
+    pub interface: String,
+    pub peers: Vec<PeerCounters>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerCounters {
+    pub public_key: String,
+    pub name: String,
+    pub rx: u64,
+    pub tx: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+}
+
+/// Traffic of one peer over a window
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PeerUsage {
+    pub name: String,
+    pub interface: String,
+    pub public_key: String,
+    /// Bytes received from the peer
+    pub rx_bytes: u64,
+    /// Bytes sent to the peer
+    pub tx_bytes: u64,
+    /// Total bytes per equal slice of the window, oldest first
+    pub buckets: Vec<u64>,
+    /// Addresses the peer connected from during the window
+    pub endpoints: Vec<EndpointSeen>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct EndpointSeen {
+    pub ip: String,
+    /// ISO country code, when the GeoIP database knows the address
+    pub country: Option<String>,
+}
+
+fn day_file(day: chrono::NaiveDate) -> String {
+    format!("{}/{}.jsonl", TRAFFIC_DIR, day.format("%Y-%m-%d"))
+}
+
+/// Append a sample of the interface's current counters
+pub fn record(interface: &str, status: &WireguardStatus) -> Result<Sample> {
+    let sample = Sample {
+        timestamp: Utc::now(),
+        interface: interface.to_string(),
+        peers: status
+            .peers
+            .iter()
+            .map(|p| PeerCounters {
+                public_key: p.public_key.clone(),
+                name: p.name.clone(),
+                rx: p.transfer_rx,
+                tx: p.transfer_tx,
+                endpoint: p.endpoint.clone(),
+            })
+            .collect(),
+    };
+
+    // Bookkeeping rather than router state, so not a journaled mutation
+    let line = serde_json::to_string(&sample)?.replace('\'', "'\\''");
+    let output = execute_shell(&format!(
+        "mkdir -p {dir} && printf '%s\\n' '{line}' >> {file} && \
+         find {dir} -name '*.jsonl' -mtime +{days} -delete",
+        dir = TRAFFIC_DIR,
+        line = line,
+        file = day_file(sample.timestamp.date_naive()),
+        days = RETENTION_DAYS,
+    ))?;
+    if !output.status.success() {
+        return Err(ShannonError::command_failed("record traffic sample", &output).into());
+    }
+    Ok(sample)
+}
+
+/// Samples from the day of `since` onwards, oldest first
+pub fn read_since(since: DateTime<Utc>) -> Result<Vec<Sample>> {
+    let today = Utc::now().date_naive();
+    let files: Vec<String> = since
+        .date_naive()
+        .iter_days()
+        .take_while(|day| *day <= today)
+        .map(day_file)
+        .collect();
+
+    // Missing days are normal (sampler not running yet), so ignore errors
+    let output = execute_shell(&format!("cat {} 2>/dev/null", files.join(" ")))?;
+    let mut samples: Vec<Sample> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    samples.sort_by_key(|s| s.timestamp);
+    Ok(samples)
+}
+
+/// Per-peer usage between `since` and `until`, busiest first
+pub fn summarize(
+    samples: &[Sample],
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    buckets: usize,
+) -> Vec<PeerUsage> {
+    let window = (until - since).max(Duration::seconds(1)).num_seconds();
+    let mut previous: HashMap<(&str, &str), (u64, u64)> = HashMap::new();
+    let mut usage: HashMap<(&str, &str), PeerUsage> = HashMap::new();
+
+    for sample in samples {
+        for peer in &sample.peers {
+            let key = (sample.interface.as_str(), peer.public_key.as_str());
+            let last = previous.insert(key, (peer.rx, peer.tx));
+            if sample.timestamp < since || sample.timestamp > until {
+                continue;
+            }
+            let Some((last_rx, last_tx)) = last else {
+                continue;
+            };
+            let rx = peer.rx.checked_sub(last_rx).unwrap_or(peer.rx);
+            let tx = peer.tx.checked_sub(last_tx).unwrap_or(peer.tx);
+
+            let entry = usage.entry(key).or_insert_with(|| PeerUsage {
+                name: String::new(),
+                interface: sample.interface.clone(),
+                public_key: peer.public_key.clone(),
+                rx_bytes: 0,
+                tx_bytes: 0,
+                buckets: vec![0; buckets],
+                endpoints: Vec::new(),
+            });
+            entry.name = peer.name.clone();
+            entry.rx_bytes += rx;
+            entry.tx_bytes += tx;
+            let offset = (sample.timestamp - since).num_seconds();
+            let bucket = (offset * buckets as i64 / window) as usize;
+            if let Some(slot) = entry.buckets.get_mut(bucket.min(buckets - 1)) {
+                *slot += rx + tx;
+            }
+            if let Some(ip) = peer.endpoint.as_deref().map(endpoint_ip) {
+                if !entry.endpoints.iter().any(|e| e.ip == ip) {
+                    entry.endpoints.push(EndpointSeen {
+                        ip: ip.to_string(),
+                        country: None,
+                    });
+                }
+            }
+        }
+    }
+
+    let mut usage: Vec<PeerUsage> = usage.into_values().collect();
+    usage.sort_by_key(|u| std::cmp::Reverse(u.rx_bytes + u.tx_bytes));
+    usage
+}
+
+/// IP part of a WireGuard endpoint (`1.2.3.4:51820` or `[2001:db8::1]:51820`)
+pub fn endpoint_ip(endpoint: &str) -> &str {
+    endpoint
+        .rsplit_once(':')
+        .map_or(endpoint, |(ip, _)| ip)
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(minute: i64, rx: u64, tx: u64, endpoint: &str) -> Sample {
+        Sample {
+            timestamp: DateTime::from_timestamp(1760000000 + minute * 60, 0).unwrap(),
+            interface: "wg0".to_string(),
+            peers: vec![PeerCounters {
+                public_key: "aVBob25l".to_string(),
+                name: "iPhone".to_string(),
+                rx,
+                tx,
+                endpoint: Some(endpoint.to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_summarize_sums_deltas_across_counter_reset() {
+        let samples = vec![
+            sample(0, 1000, 100, "203.0.113.7:41230"),
+            sample(1, 1500, 150, "203.0.113.7:41230"),
+            // Interface restarted: counters start over
+            sample(2, 200, 20, "[2001:db8::1]:51820"),
+            sample(3, 700, 70, "[2001:db8::1]:51820"),
+        ];
+        let since = samples[0].timestamp;
+        let until = since + Duration::minutes(4);
+
+        let usage = summarize(&samples, since, until, 4);
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].rx_bytes, 500 + 200 + 500);
+        assert_eq!(usage[0].tx_bytes, 50 + 20 + 50);
+        assert_eq!(usage[0].buckets, vec![0, 550, 220, 550]);
+        let ips: Vec<&str> = usage[0].endpoints.iter().map(|e| e.ip.as_str()).collect();
+        assert_eq!(ips, vec!["203.0.113.7", "2001:db8::1"]);
+    }
+}
@@ -4,24 +4,38 @@
 //! Designed for LAN-only access (http://internet.local:8080).
 
 use axum::{
-    extract::{Path, Query},
+    extract::{ConnectInfo, Path, Query},
     http::{header, StatusCode},
     response::{Html, IntoResponse},
     routing::get,
     Router,
 };
 use serde::Deserialize;
+use serde_json::json;
 use std::net::SocketAddr;
-use tracing::info;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
+use crate::adapters::crowdsec;
+use crate::adapters::dnsmasq::DnsmasqAdapter;
 use crate::adapters::system::{get_service_status, get_system_metrics, get_wan_ip};
-use crate::location::execute_shell;
+use crate::adapters::wireguard;
+use crate::audit::{self, AuditEntry, Origin};
+use crate::cli::Mutation;
+use crate::commands::dns::{self, UpstreamHealth};
+use crate::commands::sec::{self, SecStats};
+use crate::inventory;
+use crate::location::{self, execute_mutation, execute_shell};
+use crate::output::{format_bytes, sparkline, Envelope, ErrorBody};
+use crate::traffic::{self, PeerUsage};
 
 pub async fn serve(bind: &str, port: u16) -> anyhow::Result<()> {
     let app = Router::new()
         .route("/", get(dashboard))
         .route("/api/status", get(api_status))
         .route("/api/action", get(api_action))
+        .route("/api/sec/stats", get(api_sec_stats))
         .route("/fonts/{filename}", get(serve_font));
 
     let addr: SocketAddr = format!("{}:{}", bind, port).parse()?;
@@ -29,7 +43,7 @@ pub async fn serve(bind: &str, port: u16) -> anyhow::Result<()> {
     info!("Add 'internet.local' to dnsmasq for friendly access");
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
     Ok(())
 }
 
@@ -39,6 +53,7 @@ struct SecurityFinding {
     timestamp: String,
     category: String,
     summary: String,
+    #[allow(dead_code)]
     findings: Vec<String>,
 }
 
@@ -47,13 +62,37 @@ struct DashboardData {
     uptime: String,
     memory_pct: f32,
     cpu_load: f32,
-    services: Vec<(&'static str, &'static str, &'static str, bool)>, // (id, name, description, active)
+    services: Vec<(String, String, &'static str, bool)>, // (id, name, description, active)
     connected_devices: u32,
     blocked_ips: u32,
     dns_queries_today: String,
     wan_speed: String,
     disk_pct: String,
     recent_security: Vec<SecurityFinding>,
+    recent_changes: Vec<AuditEntry>,
+    vpn_usage: Vec<PeerUsage>,
+    attack_stats: Option<SecStats>,
+    dns_upstreams: Option<Vec<UpstreamHealth>>,
+}
+
+/// How long an upstream health check is reused across dashboard renders
+const UPSTREAM_CHECK_TTL: Duration = Duration::from_secs(300);
+
+/// Last upstream health check and when it ran
+static UPSTREAM_CHECK: Mutex<Option<(Instant, Option<Vec<UpstreamHealth>>)>> = Mutex::new(None);
+
+/// One test query to each AdGuard upstream, at most once per
+/// `UPSTREAM_CHECK_TTL`; None when AdGuard is unreachable
+fn cached_upstream_health() -> Option<Vec<UpstreamHealth>> {
+    let mut cache = UPSTREAM_CHECK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((checked, health)) = cache.as_ref() {
+        if checked.elapsed() < UPSTREAM_CHECK_TTL {
+            return health.clone();
+        }
+    }
+    let health = dns::check_upstreams().ok();
+    *cache = Some((Instant::now(), health.clone()));
+    health
 }
 
 fn collect_dashboard_data() -> DashboardData {
@@ -65,15 +104,15 @@ fn collect_dashboard_data() -> DashboardData {
     });
 
     // Count connected devices from DHCP leases
-    let connected_devices = execute_shell("cat /var/lib/misc/dnsmasq.leases 2>/dev/null | wc -l")
-        .ok()
-        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse::<u32>().ok())
+    // (counting lines would also count the DHCPv6 section)
+    let connected_devices = DnsmasqAdapter::new()
+        .list_leases()
+        .map(|leases| leases.iter().filter(|l| l.active).count() as u32)
         .unwrap_or(0);
 
     // Count CrowdSec blocked IPs
-    let blocked_ips = execute_shell("cscli decisions list -o json 2>/dev/null | grep -c '\"value\"' || echo 0")
-        .ok()
-        .and_then(|o| String::from_utf8_lossy(&o.stdout).trim().parse::<u32>().ok())
+    let blocked_ips = crowdsec::list_decisions()
+        .map(|d| d.len() as u32)
         .unwrap_or(0);
 
     // AdGuard DNS stats
@@ -96,15 +135,31 @@ fn collect_dashboard_data() -> DashboardData {
         })
         .unwrap_or_else(|| "Unknown".into());
 
-    let services = vec![
+    let mut services: Vec<(String, String, &'static str, bool)> = [
         ("dnsmasq", "Network Core", "Assigns IP addresses to all devices and resolves domain names on the local network (DHCP + DNS)", get_service_status("dnsmasq").unwrap_or(false)),
         ("AdGuardHome", "Ad & Tracker Blocker", "Filters out ads, trackers, and malicious domains before they reach any device — like an ad blocker for the entire house", get_service_status("AdGuardHome").unwrap_or(false)),
         ("crowdsec", "Intrusion Detection", "Monitors network traffic and SSH logs for suspicious activity, automatically blocks attackers using community-shared threat intelligence", get_service_status("crowdsec").unwrap_or(false)),
         ("nftables", "Firewall", "Controls which traffic flows in and out — only allows connections you've approved, blocks everything else", get_service_status("nftables").unwrap_or(false)),
-        ("wg-quick@wg0", "VPN Tunnel", "Encrypted private tunnel (WireGuard) for secure remote access to the home network from anywhere in the world", get_service_status("wg-quick@wg0").unwrap_or(false)),
         ("wan-watchdog", "Connection Guardian", "Monitors internet connectivity and automatically recovers from outages by resetting the WAN adapter (~90 second recovery)", get_service_status("wan-watchdog").unwrap_or(false)),
         ("ssh", "Remote Access", "Secure shell access for administration — key-only authentication, no passwords accepted", get_service_status("ssh").unwrap_or(false)),
-    ];
+    ]
+    .into_iter()
+    .map(|(id, name, desc, active)| (id.to_string(), name.to_string(), desc, active))
+    .collect();
+
+    // One VPN card per WireGuard interface, after the firewall
+    let tunnels = wireguard::interfaces().unwrap_or_default().into_iter().map(|iface| {
+        let id = format!("wg-quick@{}", iface);
+        let active = get_service_status(&id).unwrap_or(false);
+        if wireguard::is_client(&iface) {
+            (id, format!("VPN Egress ({})", iface), "Outbound tunnel to a VPN provider, carrying the internet traffic of the devices routed through it", active)
+        } else if iface == wireguard::DEFAULT_INTERFACE {
+            (id, "VPN Tunnel".to_string(), "Encrypted private tunnel (WireGuard) for secure remote access to the home network from anywhere in the world", active)
+        } else {
+            (id, format!("VPN Tunnel ({})", iface), "Additional WireGuard tunnel, e.g. a site-to-site link joining another LAN to this one", active)
+        }
+    });
+    services.splice(4..4, tunnels);
 
     // Disk usage
     let disk_pct = execute_shell("df / --output=pcent 2>/dev/null | tail -1 | tr -d ' %'")
@@ -222,6 +277,23 @@ fn collect_dashboard_data() -> DashboardData {
     all_security.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
     all_security.truncate(10); // Keep last 10
 
+    // Most recent audit log entries
+    let mut recent_changes = audit::read_entries().unwrap_or_default();
+    recent_changes.reverse();
+    recent_changes.truncate(8);
+
+    // Per-peer VPN traffic over the last 24h, one sparkline bar per hour
+    let until = chrono::Utc::now();
+    let since = until - chrono::Duration::hours(24);
+    let vpn_usage = traffic::read_since(since)
+        .map(|samples| traffic::summarize(&samples, since, until, 24))
+        .unwrap_or_default();
+
+    // CrowdSec alerts over the last 24h
+    let attack_stats = sec::collect_stats(None).ok();
+
+    let dns_upstreams = cached_upstream_health();
+
     DashboardData {
         wan_ip,
         uptime: metrics.uptime,
@@ -234,16 +306,36 @@ fn collect_dashboard_data() -> DashboardData {
         wan_speed,
         disk_pct,
         recent_security: all_security,
+        recent_changes,
+        vpn_usage,
+        attack_stats,
+        dns_upstreams,
     }
 }
 
 // --- HTML rendering ---
 
+/// Escape text for interpolation into HTML content or attribute values
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn render_dashboard(data: &DashboardData) -> String {
     let services_html: String = data.services.iter().map(|(id, name, desc, active)| {
         let status_class = if *active { "status-ok" } else { "status-down" };
         let status_text = if *active { "Running" } else { "Stopped" };
-        let status_dot = if *active { "&#x25CF;" } else { "&#x25CF;" };
+        let status_dot = "&#x25CF;";
         format!(
             r#"<div class="card service-card">
                 <div class="service-header">
@@ -281,13 +373,96 @@ fn render_dashboard(data: &DashboardData) -> String {
                 r#"<div style="margin-bottom: 10px"><span style="color:{cat_color};font-size:1.3rem">&#x25CF;</span> <span style="color:var(--text2);font-size:1.3rem">{ts_display}</span><br><span style="font-size:1.5rem">{summary}</span></div>"#,
                 cat_color = cat_color,
                 ts_display = ts_display,
-                summary = finding.summary,
+                summary = escape_html(&finding.summary),
             ));
         }
         html.push_str("</div>");
         html
     };
 
+    // Build VPN usage HTML
+    let vpn_usage_html = if data.vpn_usage.is_empty() {
+        r#"<p class="service-desc" style="color: var(--text2); font-style: italic">No VPN traffic recorded in the last 24 hours.</p>"#.to_string()
+    } else {
+        data.vpn_usage.iter().map(|peer| {
+            format!(
+                r#"<div style="margin-bottom: 10px"><span style="font-size:1.5rem">{name}</span> <span style="color:var(--text2);font-size:1.3rem">{rx} rx &middot; {tx} tx</span><br><span style="font-family:monospace;font-size:1.8rem;color:var(--accent)">{trend}</span></div>"#,
+                name = escape_html(&peer.name),
+                rx = format_bytes(peer.rx_bytes),
+                tx = format_bytes(peer.tx_bytes),
+                trend = sparkline(&peer.buckets),
+            )
+        }).collect()
+    };
+
+    // Build attack statistics HTML
+    let attacks_html = match &data.attack_stats {
+        None => r#"<p class="service-desc" style="color: var(--text2); font-style: italic">CrowdSec local API unavailable.</p>"#.to_string(),
+        Some(stats) if stats.alerts == 0 => r#"<p class="service-desc" style="color: var(--text2); font-style: italic">No attacks detected in the last 24 hours.</p>"#.to_string(),
+        Some(stats) => {
+            let hourly: Vec<u64> = stats.by_hour.iter().map(|h| h.alerts as u64).collect();
+            let top = |counts: &[sec::AttackCount]| counts.iter().take(5)
+                .map(|c| format!("{} ({})", escape_html(&c.key), c.alerts))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                r#"<div style="margin-bottom: 10px"><span style="font-size:1.5rem">{alerts} alerts from {sources} sources</span><br><span style="font-family:monospace;font-size:1.8rem;color:var(--bad)">{trend}</span></div><p class="service-desc"><strong>Scenarios:</strong> {scenarios}</p><p class="service-desc"><strong>Countries:</strong> {countries}</p>"#,
+                alerts = stats.alerts,
+                sources = stats.sources,
+                trend = sparkline(&hourly),
+                scenarios = top(&stats.by_scenario),
+                countries = top(&stats.by_country),
+            )
+        }
+    };
+
+    // Build DNS upstreams HTML
+    let dns_upstreams_html = match &data.dns_upstreams {
+        None => r#"<p class="service-desc" style="color: var(--text2); font-style: italic">AdGuard Home API unavailable.</p>"#.to_string(),
+        Some(upstreams) if upstreams.is_empty() => r#"<p class="service-desc" style="color: var(--text2); font-style: italic">No upstreams configured.</p>"#.to_string(),
+        Some(upstreams) => upstreams.iter().map(|upstream| {
+            let (color, state) = match &upstream.error {
+                None => ("var(--ok)", "answering".to_string()),
+                Some(error) => ("var(--bad)", error.clone()),
+            };
+            format!(
+                r#"<div style="margin-bottom: 10px"><span style="color:{color};font-size:1.3rem">&#x25CF;</span> <span style="font-size:1.5rem;word-break:break-all">{upstream}</span><br><span style="color:var(--text2);font-size:1.3rem">{protocol} &middot; {state}</span></div>"#,
+                upstream = escape_html(&upstream.upstream),
+                protocol = escape_html(&upstream.protocol),
+                state = escape_html(&state),
+            )
+        }).collect()
+    };
+
+    // Build recent changes (audit log) HTML
+    let changes_html = if data.recent_changes.is_empty() {
+        r#"<p class="service-desc" style="color: var(--text2); font-style: italic">No changes recorded yet.</p>"#.to_string()
+    } else {
+        data.recent_changes.iter().map(|entry| {
+            let color = match entry.result {
+                audit::Outcome::Ok => "var(--ok)",
+                audit::Outcome::Error => "var(--bad)",
+            };
+            let ts_display = entry.timestamp
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M");
+            let args: Vec<String> = entry.args.as_object()
+                .map(|m| m.values().filter_map(|v| match v {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Null => None,
+                    other => Some(other.to_string()),
+                }).collect())
+                .unwrap_or_default();
+            format!(
+                r#"<div style="margin-bottom: 10px"><span style="color:{color};font-size:1.3rem">&#x25CF;</span> <span style="color:var(--text2);font-size:1.3rem">{ts_display} &middot; {origin} &middot; {user}</span><br><span style="font-size:1.5rem">{command} {args}</span></div>"#,
+                origin = escape_html(&entry.origin.to_string()),
+                user = escape_html(&entry.user),
+                command = escape_html(&entry.command),
+                args = escape_html(&args.join(" ")),
+            )
+        }).collect()
+    };
+
     format!(r##"<!DOCTYPE html>
 <html lang="en">
 <head>
@@ -597,6 +772,26 @@ footer a {{ color: var(--accent); text-decoration: none; }}
     {security_findings}
 </div>
 
+<div class="section-title">Attacks (24h)</div>
+<div class="card">
+    {attacks}
+</div>
+
+<div class="section-title">VPN Usage (24h)</div>
+<div class="card">
+    {vpn_usage}
+</div>
+
+<div class="section-title">Recent Changes</div>
+<div class="card">
+    {recent_changes}
+</div>
+
+<div class="section-title">DNS Upstreams</div>
+<div class="card">
+    {dns_upstreams}
+</div>
+
 <div class="section-title">Network Info</div>
 <div class="card">
     <table style="width:100%; font-size:1.7rem; color: var(--text2)">
@@ -662,6 +857,10 @@ async function doAction(action, target) {{
         services = services_html,
         disk = data.disk_pct,
         security_findings = security_findings_html,
+        recent_changes = changes_html,
+        vpn_usage = vpn_usage_html,
+        attacks = attacks_html,
+        dns_upstreams = dns_upstreams_html,
     )
 }
 
@@ -694,6 +893,37 @@ async fn api_status() -> impl IntoResponse {
     (StatusCode::OK, [(header::CONTENT_TYPE, "application/json")], json.to_string())
 }
 
+async fn api_sec_stats() -> impl IntoResponse {
+    let stats = tokio::task::spawn_blocking(|| sec::collect_stats(None))
+        .await
+        .unwrap();
+
+    // Same envelope as `shannon sec stats --json`
+    let (status, body) = match stats {
+        Ok(stats) => (
+            StatusCode::OK,
+            serde_json::to_string(&Envelope {
+                ok: true,
+                data: Some(&stats),
+                error: None,
+            }),
+        ),
+        Err(e) => (
+            StatusCode::BAD_GATEWAY,
+            serde_json::to_string(&Envelope::<()> {
+                ok: false,
+                data: None,
+                error: Some(ErrorBody::from_error(&e)),
+            }),
+        ),
+    };
+    (
+        status,
+        [(header::CONTENT_TYPE, "application/json")],
+        body.unwrap_or_default(),
+    )
+}
+
 #[derive(Deserialize)]
 struct ActionParams {
     action: String,
@@ -735,9 +965,30 @@ async fn serve_font(Path(filename): Path<String>) -> impl IntoResponse {
     (StatusCode::NOT_FOUND, "Font not found").into_response()
 }
 
-async fn api_action(Query(params): Query<ActionParams>) -> impl IntoResponse {
+/// Audit log description of dashboard actions that change router state
+fn action_mutation(params: &ActionParams) -> Option<Mutation> {
+    let target = params.target.as_deref().unwrap_or("");
+    let (kind, command, args) = match params.action.as_str() {
+        "restart" if target == "wan" => ("system", "restart wan", json!({})),
+        "restart" => ("service", "service restart", json!({ "service": target })),
+        "reboot" => ("system", "reboot", json!({})),
+        "flush_dns" => ("dns", "dns flush-cache", json!({})),
+        "update_blocklists" => ("dns", "dns update-blocklists", json!({})),
+        _ => return None,
+    };
+    Some(Mutation { kind, command, args })
+}
+
+async fn api_action(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(params): Query<ActionParams>,
+) -> impl IntoResponse {
     let result = tokio::task::spawn_blocking(move || {
-        match params.action.as_str() {
+        // Blocking threads are reused: drop changes journaled by earlier,
+        // unrelated work so they aren't audited as part of this action
+        location::take_changes();
+        let mutation = action_mutation(&params);
+        let result = match params.action.as_str() {
             "doctor" => {
                 let output = execute_shell("shannon doctor 2>&1");
                 match output {
@@ -770,11 +1021,11 @@ async fn api_action(Query(params): Query<ActionParams>) -> impl IntoResponse {
                 let target = params.target.as_deref().unwrap_or("");
                 match target {
                     "wan" => {
-                        let _ = execute_shell("ip link set enxc84d4421f975 down && sleep 2 && ip link set enxc84d4421f975 up");
+                        let _ = execute_mutation("ip link set enxc84d4421f975 down && sleep 2 && ip link set enxc84d4421f975 up");
                         serde_json::json!({"ok": true, "message": "WAN adapter restarted. Reconnecting..."})
                     }
                     service => {
-                        let output = execute_shell(&format!("systemctl restart {} 2>&1", service));
+                        let output = execute_mutation(&format!("systemctl restart {} 2>&1", service));
                         match output {
                             Ok(o) if o.status.success() => {
                                 serde_json::json!({"ok": true, "message": format!("{} restarted", service)})
@@ -789,40 +1040,39 @@ async fn api_action(Query(params): Query<ActionParams>) -> impl IntoResponse {
                 }
             }
             "reboot" => {
-                let _ = execute_shell("shutdown -r +1 'Reboot requested from web dashboard'");
+                let _ = execute_mutation("shutdown -r +1 'Reboot requested from web dashboard'");
                 serde_json::json!({"ok": true, "message": "Rebooting in 1 minute..."})
             }
             "flush_dns" => {
-                let _ = execute_shell("curl -s -X POST http://127.0.0.1:3000/control/cache_clear 2>/dev/null");
+                let _ = execute_mutation("curl -s -X POST http://127.0.0.1:3000/control/cache_clear 2>/dev/null");
                 serde_json::json!({"ok": true, "message": "DNS cache flushed"})
             }
             "update_blocklists" => {
-                let output = execute_shell("curl -s -X POST http://127.0.0.1:3000/control/filtering/refresh 2>/dev/null");
+                let output = execute_mutation("curl -s -X POST http://127.0.0.1:3000/control/filtering/refresh 2>/dev/null");
                 match output {
                     Ok(_) => serde_json::json!({"ok": true, "message": "Blocklist update triggered"}),
                     Err(e) => serde_json::json!({"ok": false, "message": format!("Error: {}", e)}),
                 }
             }
-            "show_leases" => {
-                let output = execute_shell("cat /var/lib/misc/dnsmasq.leases 2>/dev/null");
-                match output {
-                    Ok(o) => {
-                        let text = String::from_utf8_lossy(&o.stdout);
-                        let devices: Vec<String> = text.lines().map(|line| {
-                            let parts: Vec<&str> = line.split_whitespace().collect();
-                            if parts.len() >= 4 {
-                                format!("{} — {} ({})", parts[2], parts[3], parts[1])
-                            } else {
-                                line.to_string()
+            "show_leases" => match inventory::refresh() {
+                Ok(devices) => {
+                    let devices: Vec<String> = devices
+                        .values()
+                        .filter(|d| d.online)
+                        .map(|d| {
+                            let ip = d.ip.as_deref().unwrap_or("-");
+                            match &d.vendor {
+                                Some(vendor) => format!("{} — {} ({})", d.label(), ip, vendor),
+                                None => format!("{} — {}", d.label(), ip),
                             }
-                        }).collect();
-                        let count = devices.len();
-                        let list = devices.join("\n");
-                        serde_json::json!({"ok": true, "message": format!("{} devices:\n{}", count, list)})
-                    }
-                    Err(e) => serde_json::json!({"ok": false, "message": format!("Error: {}", e)}),
+                        })
+                        .collect();
+                    let count = devices.len();
+                    let list = devices.join("\n");
+                    serde_json::json!({"ok": true, "message": format!("{} devices online:\n{}", count, list)})
                 }
-            }
+                Err(e) => serde_json::json!({"ok": false, "message": format!("Error: {}", e)}),
+            },
             "wg_status" => {
                 let output = execute_shell("wg show 2>/dev/null");
                 match output {
@@ -840,8 +1090,32 @@ async fn api_action(Query(params): Query<ActionParams>) -> impl IntoResponse {
                 }
             }
             _ => serde_json::json!({"ok": false, "message": "Unknown action"}),
+        };
+
+        if let Some(mutation) = mutation {
+            let error = (result["ok"] == false).then(|| result["message"].as_str().unwrap_or("").to_string());
+            let user = format!("dashboard@{}", client.ip());
+            if let Err(e) = audit::record(Origin::Web, &user, mutation, None, error) {
+                warn!("Failed to write audit log: {:#}", e);
+            }
         }
+
+        result
     }).await.unwrap();
 
     (StatusCode::OK, [(header::CONTENT_TYPE, "application/json")], result.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html(r#"<img src=x onerror="alert('x')"> & co"#),
+            "&lt;img src=x onerror=&quot;alert(&#39;x&#39;)&quot;&gt; &amp; co"
+        );
+        assert_eq!(escape_html("dns add nas 192.168.4.10"), "dns add nas 192.168.4.10");
+    }
+}